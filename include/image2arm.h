@@ -0,0 +1,101 @@
+#ifndef IMAGE2ARM_H
+#define IMAGE2ARM_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define IMAGE2ARM_FORMAT_INDEXED 0
+
+#define IMAGE2ARM_FORMAT_RGB565 1
+
+#define IMAGE2ARM_FORMAT_ARGB4444 2
+
+#define IMAGE2ARM_FORMAT_ARGB8888 3
+
+#define IMAGE2ARM_ENDIAN_LITTLE 0
+
+#define IMAGE2ARM_ENDIAN_BIG 1
+
+/*
+ Options for `image2arm_convert`, a NULL pointer means use the defaults,
+ as does a struct zeroed but for `size`.
+ */
+typedef struct Image2armOptions {
+  /*
+   Must be set to `sizeof(Image2armOptions)`, which lets fields be added
+   later without breaking callers compiled against an older header. The
+   first header's struct held only `size`, so a `size` of
+   `sizeof(size_t)` means that struct, and gets the defaults.
+   */
+  size_t size;
+  /*
+   How each pixel is stored, one of the `IMAGE2ARM_FORMAT_` constants.
+   */
+  uint32_t format;
+  /*
+   The byte order of pixels and tables wider than a byte, one of the
+   `IMAGE2ARM_ENDIAN_` constants.
+   */
+  uint32_t endian;
+  /*
+   The names of the outputs to write, like "asm", "c-header" and "json",
+   each named after `out_path` and written alongside it. NULL writes only
+   the assembly.
+   */
+  const char *const *emit;
+  /*
+   How many names `emit` points to.
+   */
+  size_t emit_count;
+  /*
+   Merge palette colours whose channels all differ by no more than this.
+   */
+  uint8_t merge_tolerance;
+  /*
+   How many palette indices at the start belong to the system, which the
+   palette is numbered after.
+   */
+  size_t reserved_indices;
+} Image2armOptions;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Compile `n` PNG files into an assembly file written to `out_path`, along
+ with any other outputs the options name.
+
+ Returns 0 on success, otherwise the exit code the command line tool would
+ have used for the same failure, with the message available from
+ `image2arm_last_error`.
+
+ # Safety
+
+ `paths` must point to `n` valid NUL terminated strings, `out_path` must be
+ a valid NUL terminated string and `options` must be NULL or point to an
+ `Image2armOptions` with `size` filled in, whose `emit` points to
+ `emit_count` valid NUL terminated strings unless it is NULL.
+ */
+int image2arm_convert(const char *const *paths,
+                      size_t n,
+                      const struct Image2armOptions *options,
+                      const char *out_path);
+
+/*
+ The message for the last failed call on this thread, or NULL if the last
+ call succeeded. The string is owned by the library and is only valid until
+ the next call into it.
+ */
+const char *image2arm_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* IMAGE2ARM_H */
@@ -0,0 +1,47 @@
+//! Simple colour corrections made to a sprite before the palette is built, so
+//! small fixes to the art don't need a trip back through an image editor.
+
+use crate::image::{Image, Pixel};
+use serde::Deserialize;
+
+/// A brightness, contrast and gamma correction for one asset.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Adjustment {
+    /// Added to every channel, from -1.0 (black) to 1.0 (white).
+    pub brightness: f64,
+    /// How far every channel is stretched from the middle grey, 1.0 leaves it alone.
+    pub contrast: f64,
+    /// Above 1.0 lightens the mid tones and below darkens them.
+    pub gamma: f64,
+}
+
+impl Default for Adjustment {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Adjustment {
+    /// Correct every pixel's colour, leaving alpha alone.
+    pub fn apply(&self, image: &Image) -> Image {
+        let channel = |c: u8| {
+            let c = (c as f64 / 255.0 - 0.5) * self.contrast + 0.5 + self.brightness;
+            let c = c.clamp(0.0, 1.0).powf(1.0 / self.gamma);
+            (c * 255.0).round() as u8
+        };
+
+        image.map(|pixel| {
+            Pixel::new(
+                channel(pixel.r),
+                channel(pixel.g),
+                channel(pixel.b),
+                pixel.a,
+            )
+        })
+    }
+}
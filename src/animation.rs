@@ -0,0 +1,217 @@
+use crate::encoding::Encoding;
+use crate::output::Packed;
+use crate::Label;
+use anyhow::Result;
+use std::fmt::{self, Write as FmtWrite};
+use std::str::FromStr;
+
+/// The unit frame durations are counted in, such as milliseconds or frames of
+/// a display refreshing at 60Hz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tick {
+    micros: u32,
+}
+
+impl Tick {
+    pub const MILLISECOND: Tick = Tick { micros: 1000 };
+
+    /// How many ticks last for the given number of milliseconds, to the
+    /// nearest tick but never less than one.
+    pub fn ticks(self, millis: u32) -> u32 {
+        let micros = millis as u64 * 1000;
+        let ticks = (micros + self.micros as u64 / 2) / self.micros as u64;
+        ticks.clamp(1, u32::MAX as u64) as u32
+    }
+}
+
+impl Default for Tick {
+    fn default() -> Self {
+        Tick::MILLISECOND
+    }
+}
+
+impl fmt::Display for Tick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.micros % 1000 {
+            0 => write!(f, "{}ms", self.micros / 1000),
+            _ => write!(f, "{}us", self.micros),
+        }
+    }
+}
+
+impl FromStr for Tick {
+    type Err = String;
+
+    /// Parse a length of time like `20ms` or `500us`, or a rate like `60hz`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let error = || format!("expected a tick like 1ms, 500us or 60hz, found '{}'", s);
+
+        let number: u32 = number.parse().map_err(|_| error())?;
+        let micros = match unit {
+            "ms" => number.checked_mul(1000),
+            "us" => Some(number),
+            "hz" if number > 0 => Some(1_000_000 / number),
+            _ => None,
+        };
+
+        match micros {
+            Some(micros) if micros > 0 => Ok(Tick { micros }),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// A named run of frames, each of which is emitted as its own asset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Animation {
+    name: String,
+    /// The names of the frames' images, in the order they're played.
+    frames: Vec<String>,
+    /// How long each frame is shown for in milliseconds, when that's known.
+    durations: Option<Vec<u32>>,
+    /// The bytes from the start of one frame to the next, for
+    /// [`crate::Options::frame_stride`].
+    pub(crate) frame_stride: Option<usize>,
+}
+
+impl Animation {
+    pub fn new(name: String, frames: Vec<String>) -> Self {
+        Self {
+            name,
+            frames,
+            durations: None,
+            frame_stride: None,
+        }
+    }
+
+    /// Set how many milliseconds each frame is shown for.
+    pub fn with_durations(self, durations: Vec<u32>) -> Self {
+        Self {
+            durations: Some(durations),
+            ..self
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn frames(&self) -> &[String] {
+        &self.frames
+    }
+
+    #[inline]
+    pub fn durations(&self) -> Option<&[u32]> {
+        self.durations.as_deref()
+    }
+
+    #[inline]
+    pub fn frame_stride(&self) -> Option<usize> {
+        self.frame_stride
+    }
+
+    /// Group assets numbered like `walk_0`, `walk_1`, ... into animations
+    /// named after what comes before the number, when they are next to each
+    /// other in the asset table and count up by one. Names already taken by
+    /// other animations are skipped.
+    pub(crate) fn numbered_runs<'a>(
+        names: impl IntoIterator<Item = &'a str>,
+        existing: &[Animation],
+    ) -> Vec<Animation> {
+        // each frame of the current run as its name, base and number
+        type Run<'a> = Vec<(&'a str, &'a str, u32)>;
+        fn finish(runs: &mut Vec<Animation>, run: &mut Run, existing: &[Animation]) {
+            if let [(_, base, _), _, ..] = run[..] {
+                if !existing.iter().any(|animation| animation.name == base) {
+                    let frames = run.iter().map(|(name, _, _)| name.to_string()).collect();
+                    runs.push(Animation::new(base.to_owned(), frames));
+                }
+            }
+            run.clear();
+        }
+
+        let mut runs = Vec::new();
+        let mut run: Run = Vec::new();
+        for name in names {
+            let numbered = name
+                .rsplit_once('_')
+                .and_then(|(base, number)| Some((base, number.parse::<u32>().ok()?)));
+            let continues = match (run.last(), numbered) {
+                (Some((_, base, last)), Some((next, number))) => {
+                    *base == next && Some(number) == last.checked_add(1)
+                }
+                _ => false,
+            };
+            if !continues {
+                finish(&mut runs, &mut run, existing);
+            }
+            if let Some((base, number)) = numbered {
+                run.push((name, base, number));
+            }
+        }
+        finish(&mut runs, &mut run, existing);
+
+        runs
+    }
+
+    /// The prefix of the animation's constants.
+    fn symbol(&self) -> Label {
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect();
+        format!("ANIM_{}", name)
+    }
+
+    /// Write constants for where the animation starts in the asset table and
+    /// how many frames it has, which only works as its frames are contiguous.
+    pub(crate) fn to_asm(&self, packed: &Packed) -> Result<String> {
+        let mut buf = String::new();
+        let symbol = self.symbol();
+
+        if let Some(first) = self.frames.first() {
+            let first = packed.label(first);
+            writeln!(buf, "{}_START\tEQU\tASSET{}", symbol, first)?;
+            writeln!(buf, "{}_FIRST\tEQU\tASSET{}", symbol, first)?;
+        }
+        writeln!(buf, "{}_LENGTH\tEQU\t{}", symbol, self.frames.len())?;
+        writeln!(buf, "{}_COUNT\tEQU\t{}", symbol, self.frames.len())?;
+        if let Some(stride) = self.frame_stride {
+            writeln!(buf, "{}_FRAME_STRIDE\tEQU\t{}", symbol, stride)?;
+        }
+
+        Ok(buf)
+    }
+
+    /// Write a word per frame holding how many ticks it is shown for, which
+    /// must be aligned. Animations without durations have no table.
+    pub(crate) fn durations_asm(&self, tick: Tick, encoding: &Encoding) -> Result<Option<String>> {
+        let durations = match &self.durations {
+            Some(durations) => durations,
+            None => return Ok(None),
+        };
+
+        let mut buf = String::new();
+        writeln!(buf, "{}_Durations", self.label())?;
+        for (frame, millis) in self.frames.iter().zip(durations.iter()) {
+            let ticks = encoding.words(&[tick.ticks(*millis)]);
+            writeln!(buf, "\t{}\t; {} ({}ms)", ticks, frame, millis)?;
+        }
+
+        Ok(Some(buf))
+    }
+
+    /// The start of the animation's labels.
+    fn label(&self) -> Label {
+        format!("_{}", self.name)
+    }
+}
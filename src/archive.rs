@@ -0,0 +1,101 @@
+//! Reading the images out of a zip archive, as artists often hand them over,
+//! without extracting it first.
+
+use crate::animation::Animation;
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::image::Image;
+use crate::{aseprite, input, Options};
+use anyhow::{ensure, Result};
+use log::{info, warn};
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+
+pub(crate) fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+}
+
+/// Whether an entry is metadata macOS and editors leave behind, rather than
+/// something to convert.
+pub(crate) fn is_metadata(entry: &str) -> bool {
+    entry
+        .split('/')
+        .any(|part| part.starts_with('.') || part == "__MACOSX")
+}
+
+/// Read every image in the archive, sorted by their paths inside it. Each is
+/// named after its path without the extension, with the directories joined
+/// by underscores, so `enemies/bat.png` becomes `enemies_bat`, unless
+/// [`Options::strict`] forbids renaming.
+pub(crate) fn load(path: &Path, options: &Options) -> Result<(Vec<Image>, Vec<Animation>)> {
+    let error = |reason: String| {
+        Diagnostic::new(FailureClass::Input, reason)
+            .with_file(path)
+            .with_suggestion("check that the archive is a valid zip file")
+    };
+    let file = std::fs::File::open(path).map_err(|e| {
+        Diagnostic::new(FailureClass::Input, format!("failed to open file: {}", e))
+            .with_file(path)
+            .with_suggestion("check that the path exists and is readable")
+    })?;
+    let mut archive =
+        ZipArchive::new(file).map_err(|e| error(format!("failed to read the archive: {}", e)))?;
+
+    let mut entries: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_owned)
+        .collect();
+    entries.sort();
+
+    let mut images = Vec::new();
+    let mut animations = Vec::new();
+    for entry in entries {
+        if is_metadata(&entry) {
+            continue;
+        }
+        let entry_path = Path::new(&entry);
+        if !input::is_image(entry_path) {
+            warn!(
+                "skipping {} in {}, which isn't an image",
+                entry,
+                path.display()
+            );
+            continue;
+        }
+
+        ensure!(
+            !options.strict || !entry.contains('/'),
+            Diagnostic::new(
+                FailureClass::Input,
+                format!("{} would be renamed to fit in a label", entry)
+            )
+            .with_file(path)
+            .with_suggestion("move it to the top of the archive, or drop --strict")
+        );
+        let name = entry
+            .rsplit_once('.')
+            .map_or(entry.as_str(), |(stem, _)| stem)
+            .replace('/', "_");
+        let mut bytes = Vec::new();
+        archive
+            .by_name(&entry)
+            .and_then(|mut file| Ok(file.read_to_end(&mut bytes)?))
+            .map_err(|e| error(format!("failed to read {}: {}", entry, e)))?;
+
+        let located = |d: Diagnostic| d.with_file(path.join(&entry));
+        match aseprite::is_aseprite(entry_path) {
+            true => {
+                let sprite = aseprite::decode(&name, &bytes).map_err(located)?;
+                images.extend(sprite.frames);
+                animations.extend(sprite.animations);
+            }
+            false => images.push(Image::decode(name, bytes.as_slice()).map_err(located)?),
+        }
+    }
+
+    info!("decoded {} images from {}", images.len(), path.display());
+    Ok((images, animations))
+}
@@ -0,0 +1,448 @@
+//! Aseprite's native `.aseprite`/`.ase` files, so sprites don't need
+//! exporting to PNG first.
+//!
+//! Every frame is flattened into an image by drawing the visible layers over
+//! each other, and tags become named ranges of those frames.
+
+use crate::animation::Animation;
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::image::{Image, Pixel};
+use log::{info, warn};
+use std::io::Read;
+use std::path::Path;
+
+const FILE_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+
+const LAYER_VISIBLE: u16 = 0x01;
+const LAYER_BACKGROUND: u16 = 0x08;
+const LAYER_NORMAL: u16 = 0;
+const BLEND_NORMAL: u16 = 0;
+
+const CEL_RAW: u16 = 0;
+const CEL_LINKED: u16 = 1;
+const CEL_COMPRESSED: u16 = 2;
+
+const TRANSPARENT: Pixel = Pixel::new(0, 0, 0, 0);
+
+/// The frames of a sprite and the animations its tags describe.
+pub(crate) struct Sprite {
+    pub frames: Vec<Image>,
+    pub animations: Vec<Animation>,
+}
+
+#[inline]
+pub(crate) fn is_aseprite(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .is_some_and(|e| e == "aseprite" || e == "ase")
+}
+
+/// Read a sprite, naming the frames after `name` or the file, followed by the
+/// frame number unless there is only one.
+pub(crate) fn load(path: &Path, name: Option<&str>) -> anyhow::Result<Sprite> {
+    let bytes = std::fs::read(path).map_err(|e| {
+        Diagnostic::new(FailureClass::Input, format!("failed to open file: {}", e))
+            .with_file(path)
+            .with_suggestion("check that the path exists and is readable")
+    })?;
+    let name = name
+        .or_else(|| path.file_stem().and_then(|stem| stem.to_str()))
+        .ok_or_else(|| {
+            Diagnostic::new(
+                FailureClass::Input,
+                "the file name contains invalid unicode",
+            )
+            .with_file(path)
+            .with_suggestion("rename the file using only unicode characters")
+        })?;
+
+    let sprite = decode(name, &bytes).map_err(|d| d.with_file(path))?;
+    info!(
+        "decoded {} frames and {} tags from {}",
+        sprite.frames.len(),
+        sprite.animations.len(),
+        path.display()
+    );
+
+    Ok(sprite)
+}
+
+struct Layer {
+    flags: u16,
+    visible: bool,
+    opacity: u8,
+}
+
+struct Cel {
+    layer: usize,
+    x: i32,
+    y: i32,
+    opacity: u8,
+    z_index: i32,
+    width: u32,
+    height: u32,
+    /// Colours in the file's pixel format, only turned into pixels once the
+    /// whole palette has been read.
+    data: Vec<u8>,
+}
+
+/// A little endian cursor over the file, where running off the end is always
+/// a truncated file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Diagnostic> {
+        if self.bytes.len() < n {
+            return Err(
+                Diagnostic::new(FailureClass::Decode, "the Aseprite file is cut short")
+                    .with_suggestion("the file may be truncated or corrupt"),
+            );
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Diagnostic> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Diagnostic> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16, Diagnostic> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32, Diagnostic> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, Diagnostic> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+pub(crate) fn decode(name: &str, bytes: &[u8]) -> Result<Sprite, Diagnostic> {
+    let mut file = Reader { bytes };
+
+    let mut header = Reader {
+        bytes: file.take(128)?,
+    };
+    header.u32()?;
+    if header.u16()? != FILE_MAGIC {
+        return Err(Diagnostic::new(
+            FailureClass::Decode,
+            "not an Aseprite file, the magic is wrong",
+        )
+        .with_suggestion("save the sprite from Aseprite as .aseprite or .ase"));
+    }
+    let frame_count = header.u16()? as usize;
+    let width = header.u16()? as u32;
+    let height = header.u16()? as u32;
+    let depth = header.u16()?;
+    header.take(14)?;
+    let transparent_index = header.u8()?;
+
+    if !matches!(depth, 8 | 16 | 32) {
+        return Err(Diagnostic::new(
+            FailureClass::Decode,
+            format!("unsupported colour depth of {} bits", depth),
+        ));
+    }
+
+    let mut layers: Vec<Layer> = Vec::new();
+    let mut parents: Vec<bool> = Vec::new();
+    let mut palette: Vec<Pixel> = Vec::new();
+    let mut tags = Vec::new();
+    let mut frames: Vec<Vec<Cel>> = Vec::with_capacity(frame_count);
+    let mut durations = Vec::with_capacity(frame_count);
+    let mut skipped = false;
+
+    for frame in 0..frame_count {
+        let frame_size = file.u32()? as usize;
+        let mut body = Reader {
+            bytes: file.take(frame_size.saturating_sub(4))?,
+        };
+        if body.u16()? != FRAME_MAGIC {
+            return Err(Diagnostic::new(
+                FailureClass::Decode,
+                format!("frame {} has the wrong magic", frame),
+            )
+            .with_suggestion("the file may be truncated or corrupt"));
+        }
+        let old_chunks = body.u16()? as usize;
+        durations.push(body.u16()? as u32);
+        body.take(2)?;
+        let chunk_count = match body.u32()? as usize {
+            0 => old_chunks,
+            new => new,
+        };
+
+        let mut cels: Vec<Cel> = Vec::new();
+        for _ in 0..chunk_count {
+            let chunk_size = body.u32()? as usize;
+            let mut chunk = Reader {
+                bytes: body.take(chunk_size.saturating_sub(4))?,
+            };
+
+            match chunk.u16()? {
+                CHUNK_LAYER => {
+                    let flags = chunk.u16()?;
+                    let kind = chunk.u16()?;
+                    let level = chunk.u16()? as usize;
+                    chunk.take(4)?;
+                    let blend = chunk.u16()?;
+                    let opacity = chunk.u8()?;
+
+                    // a layer is only seen when every group above it is visible too
+                    parents.truncate(level);
+                    let visible = flags & LAYER_VISIBLE != 0 && parents.iter().all(|v| *v);
+                    parents.push(visible);
+
+                    let drawn = visible && kind == LAYER_NORMAL;
+                    if drawn && blend != BLEND_NORMAL {
+                        skipped = true;
+                    }
+                    layers.push(Layer {
+                        flags,
+                        visible: drawn,
+                        opacity,
+                    });
+                }
+                CHUNK_CEL => {
+                    let layer = chunk.u16()? as usize;
+                    let x = chunk.i16()? as i32;
+                    let y = chunk.i16()? as i32;
+                    let opacity = chunk.u8()?;
+                    let kind = chunk.u16()?;
+                    let z_index = chunk.i16()? as i32;
+                    chunk.take(5)?;
+
+                    match kind {
+                        CEL_RAW | CEL_COMPRESSED => {
+                            let width = chunk.u16()? as u32;
+                            let height = chunk.u16()? as u32;
+                            let data = match kind {
+                                CEL_RAW => chunk.bytes.to_vec(),
+                                _ => {
+                                    let mut data = Vec::new();
+                                    flate2::read::ZlibDecoder::new(chunk.bytes)
+                                        .read_to_end(&mut data)
+                                        .map_err(|e| {
+                                            Diagnostic::new(
+                                                FailureClass::Decode,
+                                                format!("a cel won't decompress: {}", e),
+                                            )
+                                        })?;
+                                    data
+                                }
+                            };
+                            cels.push(Cel {
+                                layer,
+                                x,
+                                y,
+                                opacity,
+                                z_index,
+                                width,
+                                height,
+                                data,
+                            });
+                        }
+                        CEL_LINKED => {
+                            let source = chunk.u16()? as usize;
+                            let linked = frames
+                                .get(source)
+                                .and_then(|cels| cels.iter().find(|cel| cel.layer == layer))
+                                .ok_or_else(|| {
+                                    Diagnostic::new(
+                                        FailureClass::Decode,
+                                        format!("frame {} links to a missing cel", frame),
+                                    )
+                                })?;
+                            cels.push(Cel {
+                                opacity,
+                                z_index,
+                                data: linked.data.clone(),
+                                ..*linked
+                            });
+                        }
+                        // tilemap cels have no pixels of their own
+                        _ => skipped = true,
+                    }
+                }
+                CHUNK_PALETTE => {
+                    let size = chunk.u32()? as usize;
+                    let first = chunk.u32()? as usize;
+                    let last = chunk.u32()? as usize;
+                    chunk.take(8)?;
+
+                    palette.resize(size.max(palette.len()), TRANSPARENT);
+                    for index in first..=last {
+                        let flags = chunk.u16()?;
+                        let rgba = chunk.take(4)?;
+                        if flags & 1 != 0 {
+                            chunk.string()?;
+                        }
+                        if let Some(entry) = palette.get_mut(index) {
+                            *entry = Pixel::new(rgba[0], rgba[1], rgba[2], rgba[3]);
+                        }
+                    }
+                }
+                // only files from before Aseprite 1.2 have just the old palette
+                CHUNK_OLD_PALETTE if palette.is_empty() => {
+                    let mut index = 0;
+                    for _ in 0..chunk.u16()? {
+                        index += chunk.u8()? as usize;
+                        let count = match chunk.u8()? {
+                            0 => 256,
+                            n => n as usize,
+                        };
+                        for _ in 0..count {
+                            let rgb = chunk.take(3)?;
+                            if palette.len() <= index {
+                                palette.resize(index + 1, TRANSPARENT);
+                            }
+                            palette[index] = Pixel::new(rgb[0], rgb[1], rgb[2], 0xFF);
+                            index += 1;
+                        }
+                    }
+                }
+                CHUNK_TAGS => {
+                    let count = chunk.u16()?;
+                    chunk.take(8)?;
+                    for _ in 0..count {
+                        let from = chunk.u16()? as usize;
+                        let to = chunk.u16()? as usize;
+                        chunk.take(13)?;
+                        tags.push((chunk.string()?, from, to));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        frames.push(cels);
+    }
+
+    if skipped {
+        warn!(
+            "{} uses blend modes or tilemaps which aren't supported, so frames may look different",
+            name
+        );
+    }
+
+    let colour = |layer: &Layer, data: &[u8], i: usize| -> Pixel {
+        match depth {
+            32 => Pixel::new(
+                data[i * 4],
+                data[i * 4 + 1],
+                data[i * 4 + 2],
+                data[i * 4 + 3],
+            ),
+            16 => Pixel::new(data[i * 2], data[i * 2], data[i * 2], data[i * 2 + 1]),
+            _ => match data[i] {
+                // the background layer is opaque so even the transparent index is drawn
+                index if index == transparent_index && layer.flags & LAYER_BACKGROUND == 0 => {
+                    TRANSPARENT
+                }
+                index => palette.get(index as usize).copied().unwrap_or(TRANSPARENT),
+            },
+        }
+    };
+    let bytes_per_pixel = depth as usize / 8;
+
+    let single = frames.len() == 1;
+    let images = frames
+        .into_iter()
+        .enumerate()
+        .map(|(frame, mut cels)| {
+            // a cel's z-index moves it past that many layers, winning ties with them
+            cels.sort_by_key(|cel| (cel.layer as i32 + cel.z_index, cel.z_index));
+
+            let mut canvas = vec![TRANSPARENT; (width * height) as usize];
+            for cel in cels.iter() {
+                let layer = match layers.get(cel.layer) {
+                    Some(layer) if layer.visible => layer,
+                    _ => continue,
+                };
+                let pixels = (cel.width * cel.height) as usize;
+                if cel.data.len() < pixels * bytes_per_pixel {
+                    return Err(Diagnostic::new(
+                        FailureClass::Decode,
+                        format!("a cel in frame {} is missing pixels", frame),
+                    )
+                    .with_suggestion("the file may be truncated or corrupt"));
+                }
+
+                let opacity = (cel.opacity as u32 * layer.opacity as u32 / 255) as u8;
+                for i in 0..pixels {
+                    let x = cel.x + (i as u32 % cel.width) as i32;
+                    let y = cel.y + (i as u32 / cel.width) as i32;
+                    if (0..width as i32).contains(&x) && (0..height as i32).contains(&y) {
+                        let under = &mut canvas[(y as u32 * width + x as u32) as usize];
+                        *under = blend(*under, colour(layer, &cel.data, i), opacity);
+                    }
+                }
+            }
+
+            let name = match single {
+                true => name.to_owned(),
+                false => format!("{}_{}", name, frame),
+            };
+            Ok(Image::new(name, width, height, canvas))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let animations = tags
+        .into_iter()
+        .filter_map(|(tag, from, to)| {
+            let frames: Vec<String> = images
+                .get(from..=to)?
+                .iter()
+                .map(|frame| frame.name().to_owned())
+                .collect();
+            Some(
+                Animation::new(format!("{}_{}", name, tag), frames)
+                    .with_durations(durations[from..=to].to_vec()),
+            )
+        })
+        .collect();
+
+    Ok(Sprite {
+        frames: images,
+        animations,
+    })
+}
+
+/// Draw `over` on top of `under` with Aseprite's normal blend mode.
+fn blend(under: Pixel, over: Pixel, opacity: u8) -> Pixel {
+    let over_alpha = over.a as u32 * opacity as u32 / 255;
+    let under_alpha = under.a as u32 * (255 - over_alpha) / 255;
+    let alpha = over_alpha + under_alpha;
+    if alpha == 0 {
+        return TRANSPARENT;
+    }
+
+    let mix = |o: u8, u: u8| ((o as u32 * over_alpha + u as u32 * under_alpha) / alpha) as u8;
+    Pixel::new(
+        mix(over.r, under.r),
+        mix(over.g, under.g),
+        mix(over.b, under.b),
+        alpha as u8,
+    )
+}
@@ -0,0 +1,324 @@
+//! `cargo image2arm` - compile the sprites listed in Cargo.toml.
+//!
+//! The inputs and output are read from the package metadata:
+//!
+//! ```toml
+//! [package.metadata.image2arm]
+//! inputs = ["sprites/player.png", "sprites/enemy.png"]
+//! output = "src/assets.s"
+//!
+//! [package.metadata.image2arm.adjust.player]
+//! brightness = 0.1
+//! gamma = 1.2
+//!
+//! [package.metadata.image2arm.assets.enemy]
+//! private = true
+//!
+//! [package.metadata.image2arm.assets.player]
+//! priority = 2
+//!
+//! [package.metadata.image2arm.configs.release]
+//! output = "src/assets_release.s"
+//! fill_runs = 16
+//! ```
+//!
+//! All paths are relative to the directory containing Cargo.toml, and
+//! `--config release` builds with the settings of the `release` config.
+
+use anyhow::Result;
+use clap::Parser;
+use image2arm::diagnostic::{self, Diagnostic, ErrorFormat, FailureClass};
+use image2arm::{Adjustment, Endian, NinePatch, Options, PixelFormat, Region, Target};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Parser)]
+#[command(bin_name = "cargo")]
+enum Cargo {
+    /// Compile the sprites listed in [package.metadata.image2arm].
+    #[command(version)]
+    Image2arm(Args),
+}
+
+#[derive(Debug, clap::Args)]
+struct Args {
+    /// Path to Cargo.toml, by default the nearest one to the current directory.
+    #[arg(long, value_name = "PATH")]
+    manifest_path: Option<PathBuf>,
+
+    /// Build with the settings of this config from [package.metadata.image2arm.configs].
+    #[arg(long, value_name = "NAME")]
+    config: Option<String>,
+
+    /// How errors are reported on stderr.
+    #[arg(long, value_enum, default_value = "human")]
+    error_format: ErrorFormat,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    package: Option<Package>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    metadata: Option<PackageMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PackageMetadata {
+    image2arm: Option<Metadata>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Metadata {
+    inputs: Vec<PathBuf>,
+    #[serde(default = "default_output")]
+    output: PathBuf,
+    #[serde(default)]
+    adjust: BTreeMap<String, Adjustment>,
+    #[serde(default)]
+    assets: BTreeMap<String, AssetMetadata>,
+    #[serde(default)]
+    configs: BTreeMap<String, Config>,
+    /// Write a table of the assets sorted by priority.
+    #[serde(default)]
+    priority_table: bool,
+    #[serde(flatten)]
+    settings: Settings,
+}
+
+/// Settings for one build of the same sprites, picked with `--config`, which
+/// override the rest of the metadata.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    output: Option<PathBuf>,
+    #[serde(flatten)]
+    settings: Settings,
+}
+
+/// How the sprites are packed, which can be set for every build as well as
+/// by each config. Flattened into both, so it can't deny unknown fields
+/// itself.
+#[derive(Debug, Deserialize)]
+struct Settings {
+    /// A display preset, applied before the settings below.
+    target: Option<Target>,
+    format: Option<PixelFormat>,
+    endian: Option<Endian>,
+    /// Write every palette index as a halfword.
+    wide_indices: Option<bool>,
+    /// Write runs of at least this many copies of a byte as a fill.
+    fill_runs: Option<usize>,
+}
+
+impl Settings {
+    fn apply(&self, options: &mut Options) {
+        if let Some(target) = self.target {
+            target.apply(options);
+        }
+        if let Some(format) = self.format {
+            options.format = format;
+        }
+        if let Some(endian) = self.endian {
+            options.endian = endian;
+        }
+        if let Some(wide_indices) = self.wide_indices {
+            options.wide_indices = wide_indices;
+        }
+        if let Some(fill_runs) = self.fill_runs {
+            options.fill_runs = Some(fill_runs);
+        }
+    }
+}
+
+/// Settings for a single asset.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AssetMetadata {
+    /// Leave the asset out of the asset table.
+    #[serde(default)]
+    private: bool,
+    /// Named rectangles to cut out of the asset, like `heart=0,0,8,8; coin=8,0,8,8`.
+    regions: Option<String>,
+    /// Cut the asset into its corners, edges and centre.
+    nine_patch: Option<NinePatch>,
+    /// Where the asset is drawn from back to front, lowest first.
+    priority: Option<i32>,
+}
+
+/// The regions of every asset which has some.
+fn regions(metadata: &Metadata, manifest_path: &Path) -> Result<BTreeMap<String, Vec<Region>>> {
+    let mut regions = BTreeMap::new();
+    for (name, asset) in metadata.assets.iter() {
+        if let Some(list) = &asset.regions {
+            let list = Region::parse_list(list).map_err(|reason| {
+                Diagnostic::new(
+                    FailureClass::Usage,
+                    format!("the regions of {} are invalid: {}", name, reason),
+                )
+                .with_file(manifest_path)
+            })?;
+            regions.insert(name.clone(), list);
+        }
+    }
+
+    Ok(regions)
+}
+
+fn default_output() -> PathBuf {
+    Options::default().file_name.into()
+}
+
+fn main() {
+    let Cargo::Image2arm(args) = Cargo::parse();
+
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Warn)
+        .parse_default_env()
+        .init();
+
+    if let Err(err) = run(&args) {
+        std::process::exit(diagnostic::report(&err, args.error_format));
+    }
+}
+
+fn run(args: &Args) -> Result<()> {
+    let manifest_path = match &args.manifest_path {
+        Some(path) => path.clone(),
+        None => find_manifest()?,
+    };
+    let root = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+
+    let metadata = read_metadata(&manifest_path)?;
+    let config = match &args.config {
+        Some(name) => Some(metadata.configs.get(name).ok_or_else(|| {
+            let configs: Vec<&str> = metadata.configs.keys().map(String::as_str).collect();
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("there is no config named {} in the manifest", name),
+            )
+            .with_file(&manifest_path)
+            .with_suggestion(match configs.is_empty() {
+                true => "add it under [package.metadata.image2arm.configs]".to_owned(),
+                false => format!("pick one of {}", configs.join(", ")),
+            })
+        })?),
+        None => None,
+    };
+
+    let inputs: Vec<PathBuf> = metadata
+        .inputs
+        .iter()
+        .map(|input| root.join(input))
+        .collect();
+
+    let output = root.join(
+        config
+            .and_then(|config| config.output.as_ref())
+            .unwrap_or(&metadata.output),
+    );
+    let out_dir = output.parent().unwrap_or(root);
+    let mut options = Options {
+        file_name: output
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| {
+                Diagnostic::new(FailureClass::Usage, "the output path has no file name")
+                    .with_file(&manifest_path)
+                    .with_suggestion("set output to a file such as \"src/assets.s\"")
+            })?
+            .to_owned(),
+        adjustments: metadata.adjust.clone(),
+        private: metadata
+            .assets
+            .iter()
+            .filter(|(_, asset)| asset.private)
+            .map(|(name, _)| name.clone())
+            .collect(),
+        regions: regions(&metadata, &manifest_path)?,
+        nine_patches: metadata
+            .assets
+            .iter()
+            .filter_map(|(name, asset)| Some((name.clone(), asset.nine_patch?)))
+            .collect(),
+        priorities: metadata
+            .assets
+            .iter()
+            .filter_map(|(name, asset)| Some((name.clone(), asset.priority?)))
+            .collect(),
+        priority_table: metadata.priority_table,
+        jobs: std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        ..Options::default()
+    };
+    // a config's settings override the ones for every build
+    metadata.settings.apply(&mut options);
+    if let Some(config) = config {
+        config.settings.apply(&mut options);
+    }
+
+    image2arm::build_assets(out_dir, &inputs, options)?;
+
+    Ok(())
+}
+
+/// Walk up from the current directory looking for Cargo.toml, like cargo does.
+fn find_manifest() -> Result<PathBuf> {
+    let cwd = std::env::current_dir().map_err(|e| {
+        Diagnostic::new(
+            FailureClass::Input,
+            format!("failed to get the current directory: {}", e),
+        )
+    })?;
+
+    cwd.ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            Diagnostic::new(
+                FailureClass::Input,
+                "couldn't find Cargo.toml in this directory or any parent",
+            )
+            .with_suggestion("run from inside a cargo project or pass --manifest-path")
+            .into()
+        })
+}
+
+fn read_metadata(manifest_path: &Path) -> Result<Metadata> {
+    let contents = std::fs::read_to_string(manifest_path).map_err(|e| {
+        Diagnostic::new(
+            FailureClass::Input,
+            format!("failed to read manifest: {}", e),
+        )
+        .with_file(manifest_path)
+    })?;
+
+    let manifest: Manifest = toml::from_str(&contents).map_err(|e| {
+        Diagnostic::new(
+            FailureClass::Usage,
+            format!("invalid manifest: {}", e.message()),
+        )
+        .with_file(manifest_path)
+        .with_suggestion(
+            "[package.metadata.image2arm] takes `inputs`, `output`, `adjust`, `assets`, `configs`, `priority_table`, `target`, `format`, `endian`, `wide_indices` and `fill_runs`",
+        )
+    })?;
+
+    manifest
+        .package
+        .and_then(|package| package.metadata)
+        .and_then(|metadata| metadata.image2arm)
+        .ok_or_else(|| {
+            Diagnostic::new(
+                FailureClass::Usage,
+                "no [package.metadata.image2arm] table in the manifest",
+            )
+            .with_file(manifest_path)
+            .with_suggestion("add the table with an `inputs` list of sprites to compile")
+            .into()
+        })
+}
@@ -0,0 +1,91 @@
+//! Scrambling the packed data so it can't be read straight out of the binary,
+//! which some course and competition targets ask for. It's obfuscation, not
+//! security: the key is written into the output alongside the data.
+
+use std::str::FromStr;
+
+/// How each asset's data and alpha plane are scrambled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cipher {
+    /// Every byte XORed with the next byte of the key, going back to the
+    /// first byte of the key at the start of each asset.
+    Xor(Vec<u8>),
+}
+
+impl Cipher {
+    /// Scramble an asset's bytes.
+    pub(crate) fn encrypt(&self, bytes: &mut [u8]) {
+        match self {
+            Cipher::Xor(key) => xor(bytes, key),
+        }
+    }
+
+    /// Undo [`Cipher::encrypt`].
+    pub(crate) fn decrypt(&self, bytes: &mut [u8]) {
+        match self {
+            Cipher::Xor(key) => xor(bytes, key),
+        }
+    }
+
+    /// The constants a loader unscrambles the data with, as names and values.
+    pub(crate) fn constants(&self) -> Vec<(&'static str, String)> {
+        match self {
+            // the first byte of the key is the low byte of the word
+            Cipher::Xor(key) => {
+                let word = key
+                    .iter()
+                    .rev()
+                    .fold(0_u32, |word, &byte| word << 8 | byte as u32);
+                vec![
+                    ("XOR_KEY", format!("0x{:02X}", word)),
+                    ("XOR_KEY_LENGTH", key.len().to_string()),
+                ]
+            }
+        }
+    }
+
+    /// Read the cipher back from the constants written by
+    /// [`Cipher::constants`], looked up by name.
+    pub(crate) fn from_constants<'a>(constant: impl Fn(&str) -> Option<&'a str>) -> Option<Self> {
+        let word = constant("XOR_KEY")?;
+        let word = u32::from_str_radix(word.trim().trim_start_matches("0x"), 16).ok()?;
+        let length = constant("XOR_KEY_LENGTH")?.trim().parse::<usize>().ok()?;
+        let key = word.to_le_bytes().get(..length)?.to_vec();
+        Some(Cipher::Xor(key))
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = String;
+
+    /// Parse an XOR key of one to four bytes, like `0x5A` or `0x5A,0x3C`,
+    /// whose bytes are used in turn.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key = s
+            .split(',')
+            .map(|byte| {
+                let byte = byte.trim();
+                let parsed = match byte.strip_prefix("0x").or_else(|| byte.strip_prefix("0X")) {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => byte.parse(),
+                };
+                parsed.map_err(|_| format!("'{}' isn't a byte of a key", byte))
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+
+        match key.len() {
+            1..=4 if key.iter().any(|&byte| byte != 0) => Ok(Cipher::Xor(key)),
+            1..=4 => Err("a key of zeroes leaves the data as it is".to_owned()),
+            _ => Err(format!(
+                "expected a key of one to four bytes, found '{}'",
+                s
+            )),
+        }
+    }
+}
+
+fn xor(bytes: &mut [u8], key: &[u8]) {
+    for (byte, key) in bytes.iter_mut().zip(key.iter().cycle()) {
+        *byte ^= key;
+    }
+}
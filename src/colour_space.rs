@@ -0,0 +1,476 @@
+//! Colour management, from the chunks a PNG says what colour space its
+//! pixels are in with, so exports of the same art from tools which manage
+//! colour differently give the same palette.
+
+use crate::diagnostic::Diagnostic;
+use crate::exif;
+use crate::image::{Image, Pixel};
+use flate2::read::ZlibDecoder;
+use std::io::{self, Read};
+
+/// The biggest colour profile read, well past the size of any real one.
+const MAX_PROFILE: u64 = 4 * 1024 * 1024;
+
+/// The primaries of sRGB adapted to D50, as the colorants of its profile.
+const SRGB: [[f64; 3]; 3] = [
+    [0.4360747, 0.3850649, 0.1430804],
+    [0.2225045, 0.7168786, 0.0606169],
+    [0.0139322, 0.0971045, 0.7141733],
+];
+
+/// The sRGB transfer curve, as the parameters of an ICC `para` curve.
+const SRGB_CURVE: [f64; 7] = [
+    2.4,
+    1.0 / 1.055,
+    0.055 / 1.055,
+    1.0 / 12.92,
+    0.04045,
+    0.0,
+    0.0,
+];
+
+/// What to do about PNGs which say what colour space their pixels are in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColourManagement {
+    /// Convert the pixels to sRGB by the `sRGB`, `iCCP` or `gAMA` chunk.
+    #[default]
+    Convert,
+    /// Use the pixels as they're stored.
+    Ignore,
+}
+
+impl ColourManagement {
+    pub(crate) fn apply(self, image: Image) -> Result<Image, Diagnostic> {
+        match self {
+            ColourManagement::Convert => image.into_srgb(),
+            ColourManagement::Ignore => Ok(image),
+        }
+    }
+}
+
+/// The chunks of a PNG describing the colour space of its pixels.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct ColourChunks {
+    srgb: bool,
+    /// The gamma the pixels were encoded with, times 100000.
+    gamma: Option<u32>,
+    /// The `iCCP` chunk, with the profile's name and compressed data.
+    profile: Option<Vec<u8>>,
+}
+
+impl ColourChunks {
+    /// How to convert the pixels to sRGB, or `None` when they already are
+    /// or nothing says otherwise.
+    pub(crate) fn conversion(&self) -> Result<Option<Conversion>, String> {
+        // the chunks take precedence in the order the PNG spec gives
+        if self.srgb {
+            return Ok(None);
+        }
+        if let Some(chunk) = &self.profile {
+            let profile = Profile::parse(&decompress(chunk)?)?;
+            return Ok(match profile.is_srgb() {
+                true => None,
+                false => Some(Conversion::new(&profile.curves, &profile.colorants)),
+            });
+        }
+        match self.gamma {
+            Some(0) => Err("the gAMA chunk gives a gamma of 0".to_owned()),
+            // 1/2.2 is how tools saving only a gamma mean sRGB
+            Some(gamma) if gamma.abs_diff(45455) > 500 => {
+                let curve =
+                    Curve::Parametric([100000.0 / gamma as f64, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+                Ok(Some(Conversion::new(
+                    &[curve.clone(), curve.clone(), curve],
+                    &SRGB,
+                )))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// A map from a colour space's pixels to sRGB.
+#[derive(Debug, Clone)]
+pub(crate) struct Conversion {
+    /// The linear light of each of the 256 levels of each channel.
+    linear: Vec<[f64; 256]>,
+    /// From the linear channels to linear sRGB.
+    matrix: [[f64; 3]; 3],
+}
+
+impl Conversion {
+    fn new(curves: &[Curve; 3], colorants: &[[f64; 3]; 3]) -> Self {
+        let linear = curves
+            .iter()
+            .map(|curve| {
+                let mut levels = [0.0; 256];
+                for (level, linear) in levels.iter_mut().enumerate() {
+                    *linear = curve.linear(level as f64 / 255.0);
+                }
+                levels
+            })
+            .collect();
+
+        Self {
+            linear,
+            matrix: multiply(&invert(&SRGB), colorants),
+        }
+    }
+
+    pub(crate) fn apply(&self, pixel: Pixel) -> Pixel {
+        let linear = [
+            self.linear[0][pixel.r as usize],
+            self.linear[1][pixel.g as usize],
+            self.linear[2][pixel.b as usize],
+        ];
+        let channel = |row: &[f64; 3]| {
+            let light: f64 = row.iter().zip(linear).map(|(m, l)| m * l).sum();
+            (encode(light.clamp(0.0, 1.0)) * 255.0).round() as u8
+        };
+
+        Pixel::new(
+            channel(&self.matrix[0]),
+            channel(&self.matrix[1]),
+            channel(&self.matrix[2]),
+            pixel.a,
+        )
+    }
+}
+
+/// How a channel's stored value maps to linear light.
+#[derive(Debug, Clone, PartialEq)]
+enum Curve {
+    /// The parameters `g, a, b, c, d, e, f` of the ICC function
+    /// `(ax + b)^g + e` from `d` up and `cx + f` below it.
+    Parametric([f64; 7]),
+    /// Evenly spaced samples, interpolated between.
+    Table(Vec<f64>),
+}
+
+impl Curve {
+    fn linear(&self, x: f64) -> f64 {
+        match self {
+            Curve::Parametric([g, a, b, c, d, e, f]) => match x >= *d {
+                true => (a * x + b).max(0.0).powf(*g) + e,
+                false => c * x + f,
+            },
+            Curve::Table(samples) => {
+                let at = x * (samples.len() - 1) as f64;
+                let below = at.floor() as usize;
+                let above = (below + 1).min(samples.len() - 1);
+                samples[below] + (samples[above] - samples[below]) * (at - below as f64)
+            }
+        }
+    }
+}
+
+/// The parts of a matrix profile which say how to get to linear XYZ.
+struct Profile {
+    curves: [Curve; 3],
+    colorants: [[f64; 3]; 3],
+}
+
+impl Profile {
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let u32_at = |at: usize| {
+            bytes
+                .get(at..at + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or_else(|| "the colour profile is truncated".to_owned())
+        };
+        let fixed_at = |at: usize| u32_at(at).map(|value| value as i32 as f64 / 65536.0);
+
+        if bytes.get(36..40) != Some(b"acsp") {
+            return Err("the iCCP chunk doesn't hold an ICC profile".to_owned());
+        }
+        match (bytes.get(16..20), bytes.get(20..24)) {
+            (Some(b"RGB "), Some(b"XYZ ")) => {}
+            (Some(b"RGB "), _) => {
+                return Err("only profiles connecting through XYZ can be used".to_owned())
+            }
+            (space, _) => {
+                return Err(format!(
+                    "the colour profile is for {} pixels rather than RGB",
+                    String::from_utf8_lossy(space.unwrap_or_default()).trim()
+                ))
+            }
+        }
+
+        let count = u32_at(128)? as usize;
+        // a tag's offset in the profile, and its data
+        let tag = |signature: &[u8; 4]| -> Result<(usize, &[u8]), String> {
+            for entry in (0..count.min(1024)).map(|index| 132 + index * 12) {
+                if bytes.get(entry..entry + 4) == Some(&signature[..]) {
+                    let (offset, size) = (u32_at(entry + 4)? as usize, u32_at(entry + 8)? as usize);
+                    return bytes
+                        .get(offset..offset.saturating_add(size))
+                        .map(|data| (offset, data))
+                        .ok_or_else(|| "the colour profile is truncated".to_owned());
+                }
+            }
+            Err(format!(
+                "the colour profile has no {} tag, and only matrix profiles can be used",
+                String::from_utf8_lossy(signature)
+            ))
+        };
+
+        let mut colorants = [[0.0; 3]; 3];
+        for (column, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().enumerate() {
+            let (offset, _) = tag(signature)?;
+            for (row, colorant) in colorants.iter_mut().enumerate() {
+                colorant[column] = fixed_at(offset + 8 + row * 4)?;
+            }
+        }
+
+        let curve = |signature: &[u8; 4]| -> Result<Curve, String> {
+            let (offset, data) = tag(signature)?;
+            match data.get(..4) {
+                Some(b"curv") => {
+                    let points = u32_at(offset + 8)? as usize;
+                    let sample = |index: usize| {
+                        data.get(12 + index * 2..14 + index * 2)
+                            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                            .ok_or_else(|| "the colour profile is truncated".to_owned())
+                    };
+                    match points {
+                        0 => Ok(Curve::Parametric([1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0])),
+                        1 => {
+                            let gamma = sample(0)? as f64 / 256.0;
+                            Ok(Curve::Parametric([gamma, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]))
+                        }
+                        _ => (0..points)
+                            .map(|index| sample(index).map(|value| value as f64 / 65535.0))
+                            .collect::<Result<_, _>>()
+                            .map(Curve::Table),
+                    }
+                }
+                Some(b"para") => {
+                    let kind = data
+                        .get(8..10)
+                        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                        .ok_or_else(|| "the colour profile is truncated".to_owned())?;
+                    let parameter = |index: usize| fixed_at(offset + 12 + index * 4);
+                    // every kind of curve is the last kind with some parameters fixed
+                    let g = parameter(0)?;
+                    let [a, b] = match kind {
+                        0 => [1.0, 0.0],
+                        _ => [parameter(1)?, parameter(2)?],
+                    };
+                    let below = if a == 0.0 { 0.0 } else { -b / a };
+                    match kind {
+                        0 => Ok(Curve::Parametric([g, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0])),
+                        1 => Ok(Curve::Parametric([g, a, b, 0.0, below, 0.0, 0.0])),
+                        2 => {
+                            let c = parameter(3)?;
+                            Ok(Curve::Parametric([g, a, b, 0.0, below, c, c]))
+                        }
+                        3 => Ok(Curve::Parametric([
+                            g,
+                            a,
+                            b,
+                            parameter(3)?,
+                            parameter(4)?,
+                            0.0,
+                            0.0,
+                        ])),
+                        4 => Ok(Curve::Parametric([
+                            g,
+                            a,
+                            b,
+                            parameter(3)?,
+                            parameter(4)?,
+                            parameter(5)?,
+                            parameter(6)?,
+                        ])),
+                        _ => Err(format!(
+                            "the colour profile has a curve of unknown kind {}",
+                            kind
+                        )),
+                    }
+                }
+                _ => Err("the colour profile has a curve of an unknown type".to_owned()),
+            }
+        };
+
+        Ok(Self {
+            curves: [curve(b"rTRC")?, curve(b"gTRC")?, curve(b"bTRC")?],
+            colorants,
+        })
+    }
+
+    /// Whether the profile is sRGB, give or take the rounding of its numbers,
+    /// which would otherwise move colours by a level for nothing.
+    fn is_srgb(&self) -> bool {
+        let srgb = Curve::Parametric(SRGB_CURVE);
+        let colorants = self
+            .colorants
+            .iter()
+            .flatten()
+            .zip(SRGB.iter().flatten())
+            .all(|(a, b)| (a - b).abs() < 0.002);
+        let curves = self.curves.iter().all(|curve| {
+            (0..=255).all(|level| {
+                let x = level as f64 / 255.0;
+                (encode(curve.linear(x)) - encode(srgb.linear(x))).abs() < 0.5 / 255.0
+            })
+        });
+
+        colorants && curves
+    }
+}
+
+/// Read the profile out of an `iCCP` chunk.
+fn decompress(chunk: &[u8]) -> Result<Vec<u8>, String> {
+    let name = chunk
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| "the iCCP chunk has no profile name".to_owned())?;
+    let compressed = chunk.get(name + 2..).unwrap_or_default();
+
+    let mut profile = Vec::new();
+    ZlibDecoder::new(compressed)
+        .take(MAX_PROFILE)
+        .read_to_end(&mut profile)
+        .map_err(|e| format!("the colour profile is corrupt: {}", e))?;
+
+    Ok(profile)
+}
+
+/// The sRGB encoding of linear light.
+fn encode(light: f64) -> f64 {
+    match light <= 0.0031308 {
+        true => 12.92 * light,
+        false => 1.055 * light.powf(1.0 / 2.4) - 0.055,
+    }
+}
+
+fn multiply(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut product = [[0.0; 3]; 3];
+    for (row, product) in product.iter_mut().enumerate() {
+        for (column, value) in product.iter_mut().enumerate() {
+            *value = (0..3).map(|i| a[row][i] * b[i][column]).sum();
+        }
+    }
+    product
+}
+
+fn invert(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let cofactor = |row: usize, column: usize| {
+        let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+        let (c0, c1) = ((column + 1) % 3, (column + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant: f64 = (0..3)
+        .map(|column| m[0][column] * cofactor(0, column))
+        .sum();
+
+    let mut inverse = [[0.0; 3]; 3];
+    for (row, inverse) in inverse.iter_mut().enumerate() {
+        for (column, value) in inverse.iter_mut().enumerate() {
+            *value = cofactor(column, row) / determinant;
+        }
+    }
+    inverse
+}
+
+/// Passes a PNG through to the decoder, keeping the chunks about its colour
+/// space and its EXIF orientation on the way without holding the rest of
+/// the file.
+pub(crate) struct ChunkReader<R> {
+    inner: R,
+    /// Bytes to pass over, of the signature or a chunk's CRC.
+    skip: usize,
+    /// The length and type of the next chunk, as far as they've been read.
+    header: Vec<u8>,
+    kind: [u8; 4],
+    /// The bytes of the current chunk still to come.
+    left: usize,
+    /// Whether the current chunk is one to keep, and what's been read of it.
+    keep: bool,
+    data: Vec<u8>,
+    /// Whether the image data has started, after which none of the chunks
+    /// kept can come.
+    done: bool,
+    chunks: ColourChunks,
+    orientation: Option<u16>,
+}
+
+impl<R> ChunkReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            skip: 8,
+            header: Vec::with_capacity(8),
+            kind: [0; 4],
+            left: 0,
+            keep: false,
+            data: Vec::new(),
+            done: false,
+            chunks: ColourChunks::default(),
+            orientation: None,
+        }
+    }
+
+    /// The colour chunks, and the orientation from an `eXIf` chunk.
+    pub(crate) fn into_parts(self) -> (ColourChunks, Option<u16>) {
+        (self.chunks, self.orientation)
+    }
+
+    fn scan(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() && !self.done {
+            if self.skip > 0 {
+                let n = self.skip.min(bytes.len());
+                self.skip -= n;
+                bytes = &bytes[n..];
+            } else if self.left > 0 {
+                let n = self.left.min(bytes.len());
+                if self.keep {
+                    self.data.extend_from_slice(&bytes[..n]);
+                }
+                self.left -= n;
+                bytes = &bytes[n..];
+                if self.left == 0 {
+                    self.finish();
+                }
+            } else {
+                let n = (8 - self.header.len()).min(bytes.len());
+                self.header.extend_from_slice(&bytes[..n]);
+                bytes = &bytes[n..];
+                if self.header.len() == 8 {
+                    let h = &self.header;
+                    self.left = u32::from_be_bytes([h[0], h[1], h[2], h[3]]) as usize;
+                    self.kind = [h[4], h[5], h[6], h[7]];
+                    self.header.clear();
+                    self.keep = matches!(&self.kind, b"gAMA" | b"sRGB" | b"iCCP" | b"eXIf")
+                        && self.left as u64 <= MAX_PROFILE;
+                    self.done = &self.kind == b"IDAT";
+                    if self.left == 0 {
+                        self.finish();
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        let data = std::mem::take(&mut self.data);
+        match &self.kind {
+            b"sRGB" => self.chunks.srgb = true,
+            b"gAMA" if data.len() == 4 => {
+                self.chunks.gamma = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+            }
+            b"iCCP" => self.chunks.profile = Some(data),
+            b"eXIf" => self.orientation = exif::orientation(&data),
+            _ => {}
+        }
+        self.skip = 4;
+    }
+}
+
+impl<R: Read> Read for ChunkReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.scan(&buf[..n]);
+        Ok(n)
+    }
+}
@@ -0,0 +1,132 @@
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The broad class of a failure, each class has its own exit code so that
+/// build systems can tell them apart without parsing the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureClass {
+    /// Something went wrong that doesn't fit any of the other classes.
+    Internal,
+    /// The command line was invalid.
+    Usage,
+    /// An input file couldn't be found or read.
+    Input,
+    /// An input file was read but couldn't be decoded as an image.
+    Decode,
+    /// The images can't be represented with a single palette.
+    Palette,
+    /// The output file couldn't be written.
+    Output,
+}
+
+impl FailureClass {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureClass::Internal => 1,
+            FailureClass::Usage => 2,
+            FailureClass::Input => 3,
+            FailureClass::Decode => 4,
+            FailureClass::Palette => 5,
+            FailureClass::Output => 6,
+        }
+    }
+}
+
+/// How diagnostics get written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ErrorFormat {
+    /// Plain text meant for people.
+    Human,
+    /// One JSON object per diagnostic, meant for tools.
+    Json,
+}
+
+/// A failure with enough structure to point the user at what went wrong.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub class: FailureClass,
+    pub file: Option<PathBuf>,
+    pub reason: String,
+    pub suggestion: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(class: FailureClass, reason: impl Into<String>) -> Self {
+        Self {
+            class,
+            file: None,
+            reason: reason.into(),
+            suggestion: None,
+        }
+    }
+
+    pub fn with_file(mut self, file: impl AsRef<Path>) -> Self {
+        self.file = Some(file.as_ref().to_owned());
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}: {}", file.display(), self.reason),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[derive(Serialize)]
+struct JsonDiagnostic<'a> {
+    class: FailureClass,
+    exit_code: i32,
+    file: Option<&'a Path>,
+    reason: String,
+    suggestion: Option<&'a str>,
+}
+
+/// Write an error to stderr in the requested format and return the exit code
+/// the process should finish with.
+pub fn report(err: &anyhow::Error, format: ErrorFormat) -> i32 {
+    // errors that were never classified still get reported, just without the extra detail
+    let diagnostic = err.chain().find_map(|e| e.downcast_ref::<Diagnostic>());
+    let class = diagnostic.map_or(FailureClass::Internal, |d| d.class);
+
+    match format {
+        ErrorFormat::Human => {
+            eprintln!("error: {}", err);
+            for cause in err.chain().skip(1) {
+                eprintln!("  caused by: {}", cause);
+            }
+            if let Some(suggestion) = diagnostic.and_then(|d| d.suggestion.as_deref()) {
+                eprintln!("  help: {}", suggestion);
+            }
+        }
+        ErrorFormat::Json => {
+            let json = JsonDiagnostic {
+                class,
+                exit_code: class.exit_code(),
+                file: diagnostic.and_then(|d| d.file.as_deref()),
+                reason: match diagnostic {
+                    Some(d) => d.reason.clone(),
+                    None => format!("{:#}", err),
+                },
+                suggestion: diagnostic.and_then(|d| d.suggestion.as_deref()),
+            };
+            match serde_json::to_string(&json) {
+                Ok(line) => eprintln!("{}", line),
+                Err(_) => eprintln!("error: {:#}", err),
+            }
+        }
+    }
+
+    class.exit_code()
+}
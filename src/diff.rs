@@ -0,0 +1,197 @@
+//! Summarising what changed between two outputs, or two versions of a
+//! sprite, for reviewing changes to the art.
+
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::image::{Image, Pixel};
+use crate::merge::{byte_directive, Existing};
+use crate::output::PackedAsset;
+use crate::palette::hex;
+use anyhow::{ensure, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as FmtWrite;
+use std::path::Path;
+
+/// Compare two assembly files written by this tool, or two PNGs, returning
+/// a summary of the differences a line each.
+pub fn diff(old: &Path, new: &Path) -> Result<String> {
+    ensure!(
+        is_image(old) == is_image(new),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "only two assembly files or two images can be compared"
+        )
+        .with_file(new)
+        .with_suggestion("convert the image first, or compare it with the old image")
+    );
+
+    match is_image(old) {
+        true => diff_images(&Image::load(old)?, &Image::load(new)?),
+        false => diff_outputs(&load(old)?, &load(new)?),
+    }
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+/// Read an assembly file, with whichever directive it writes bytes with.
+fn load(path: &Path) -> Result<Existing> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        Diagnostic::new(
+            FailureClass::Input,
+            format!("failed to read the file to compare: {}", e),
+        )
+        .with_file(path)
+        .with_suggestion("check that the path exists and is readable")
+    })?;
+
+    let existing = Existing::parse(&text, byte_directive(&text)).map_err(|reason| {
+        Diagnostic::new(FailureClass::Input, reason)
+            .with_file(path)
+            .with_suggestion("only indexed output written by image2arm can be compared")
+    })?;
+
+    Ok(existing)
+}
+
+fn diff_outputs(old: &Existing, new: &Existing) -> Result<String> {
+    let mut buf = String::new();
+
+    if old.palette != new.palette {
+        writeln!(
+            buf,
+            "palette: {} -> {} colours",
+            old.palette.len(),
+            new.palette.len()
+        )?;
+        for index in 0..old.palette.len().max(new.palette.len()) {
+            match (old.palette.get(index), new.palette.get(index)) {
+                (Some(old), Some(new)) if old != new => {
+                    writeln!(buf, "  ~ {}: {} -> {}", index, hex(old), hex(new))?
+                }
+                (Some(old), None) => writeln!(buf, "  - {}: {}", index, hex(old))?,
+                (None, Some(new)) => writeln!(buf, "  + {}: {}", index, hex(new))?,
+                _ => {}
+            }
+        }
+    }
+    if old.bits_per_colour != new.bits_per_colour {
+        writeln!(
+            buf,
+            "bits per colour: {} -> {}",
+            old.bits_per_colour, new.bits_per_colour
+        )?;
+    }
+
+    // assets are matched by name, wherever they are in the table
+    let size = |asset: &PackedAsset| asset.data.len() + asset.alpha.as_ref().map_or(0, Vec::len);
+    let old_assets: HashMap<&str, _> = old
+        .assets
+        .iter()
+        .map(|asset| (asset.name.as_str(), asset))
+        .collect();
+    let new_assets: HashMap<&str, _> = new
+        .assets
+        .iter()
+        .map(|asset| (asset.name.as_str(), asset))
+        .collect();
+    let names: BTreeSet<&str> = old_assets
+        .keys()
+        .chain(new_assets.keys())
+        .copied()
+        .collect();
+
+    let mut unchanged = 0;
+    let mut changes = String::new();
+    for name in names {
+        match (old_assets.get(name), new_assets.get(name)) {
+            (Some(old), Some(new)) if old.data == new.data && old.alpha == new.alpha => {
+                unchanged += 1
+            }
+            (Some(old), Some(new)) => writeln!(
+                changes,
+                "  ~ {}: {} -> {} bytes ({})",
+                name,
+                size(old),
+                size(new),
+                delta(size(old), size(new))
+            )?,
+            (Some(old), None) => writeln!(changes, "  - {}: {} bytes", name, size(old))?,
+            (None, Some(new)) => writeln!(changes, "  + {}: {} bytes", name, size(new))?,
+            (None, None) => {}
+        }
+    }
+    writeln!(
+        buf,
+        "assets: {} -> {}, {} unchanged",
+        old.assets.len(),
+        new.assets.len(),
+        unchanged
+    )?;
+    buf.push_str(&changes);
+
+    let old_total: usize = old.assets.iter().map(size).sum();
+    let new_total: usize = new.assets.iter().map(size).sum();
+    writeln!(
+        buf,
+        "total: {} -> {} bytes ({})",
+        old_total,
+        new_total,
+        delta(old_total, new_total)
+    )?;
+
+    Ok(buf)
+}
+
+fn diff_images(old: &Image, new: &Image) -> Result<String> {
+    let mut buf = String::new();
+
+    let (old_size, new_size) = ((old.width(), old.height()), (new.width(), new.height()));
+    match old_size == new_size {
+        true => {
+            let changed = old.iter().zip(new.iter()).filter(|(a, b)| a != b).count();
+            writeln!(
+                buf,
+                "size: {}x{}, {} of {} pixels changed",
+                new.width(),
+                new.height(),
+                changed,
+                new.iter().len()
+            )?;
+        }
+        false => writeln!(
+            buf,
+            "size: {}x{} -> {}x{}",
+            old.width(),
+            old.height(),
+            new.width(),
+            new.height()
+        )?,
+    }
+
+    let colours = |image: &Image| image.iter().copied().collect::<BTreeSet<Pixel>>();
+    let (old_colours, new_colours) = (colours(old), colours(new));
+    writeln!(
+        buf,
+        "colours: {} -> {}",
+        old_colours.len(),
+        new_colours.len()
+    )?;
+    for colour in old_colours.difference(&new_colours) {
+        writeln!(buf, "  - {}", hex(colour))?;
+    }
+    for colour in new_colours.difference(&old_colours) {
+        writeln!(buf, "  + {}", hex(colour))?;
+    }
+
+    Ok(buf)
+}
+
+/// A change in size with its sign.
+fn delta(old: usize, new: usize) -> String {
+    match new >= old {
+        true => format!("+{}", new - old),
+        false => format!("-{}", old - new),
+    }
+}
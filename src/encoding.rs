@@ -0,0 +1,558 @@
+//! How pixels are turned into the bytes written to the output.
+
+use crate::image::{Image, Pixel};
+use crate::output::Span;
+use crate::palette::Palette;
+use crate::Options;
+use anyhow::Result;
+use std::borrow::Cow;
+use std::fmt::Write as FmtWrite;
+
+/// How each pixel is stored.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelFormat {
+    /// Indices into the shared palette, packed as tightly as the palette allows.
+    #[default]
+    Indexed,
+    /// 16-bit colour with 5 bits of red, 6 of green and 5 of blue, ignoring alpha.
+    Rgb565,
+    /// 16-bit colour with 4 bits each of alpha, red, green and blue.
+    Argb4444,
+    /// 32-bit colour with a byte each of alpha, red, green and blue.
+    Argb8888,
+}
+
+impl PixelFormat {
+    /// The number of bytes each pixel takes, or `None` when pixels are
+    /// packed into bytes.
+    pub fn bytes_per_pixel(self) -> Option<usize> {
+        match self {
+            PixelFormat::Indexed => None,
+            PixelFormat::Rgb565 | PixelFormat::Argb4444 => Some(2),
+            PixelFormat::Argb8888 => Some(4),
+        }
+    }
+
+    /// Whether the format rounds any of the pixel's channels, which are held
+    /// exactly when widening them back to 8 bits by repeating their bits
+    /// gives the original value. RGB565 drops alpha, which isn't counted.
+    pub(crate) fn rounds(self, pixel: &Pixel) -> bool {
+        let exact = |value: u8, bits: u32| {
+            let kept = value as u32 >> (8 - bits);
+            (kept << (8 - bits) | kept >> (2 * bits - 8)) == value as u32
+        };
+        match self {
+            PixelFormat::Indexed | PixelFormat::Argb8888 => false,
+            PixelFormat::Rgb565 => !(exact(pixel.r, 5) && exact(pixel.g, 6) && exact(pixel.b, 5)),
+            PixelFormat::Argb4444 => !pixel.iter().all(|channel| exact(channel, 4)),
+        }
+    }
+}
+
+/// The order of the bytes within values wider than a byte, and of the pixels
+/// packed into each byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// How the alpha channel is written when it is split out of the colours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AlphaPlane {
+    /// A byte of alpha per pixel.
+    #[value(name = "8")]
+    Bits8,
+    /// A bit per pixel, set where the pixel is at least half opaque.
+    #[value(name = "1")]
+    Bits1,
+}
+
+impl AlphaPlane {
+    /// The bits of alpha written per pixel.
+    pub fn bits(self) -> usize {
+        match self {
+            AlphaPlane::Bits8 => 8,
+            AlphaPlane::Bits1 => 1,
+        }
+    }
+}
+
+/// The order the rows of each image are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum RowOrder {
+    #[default]
+    TopDown,
+    BottomUp,
+}
+
+/// How an image is split into fields, for interlaced displays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Fields {
+    /// The even rows, counting from the top row as 0, then the odd rows.
+    EvenOdd,
+}
+
+/// The boundary each row of an image starts on, padding the row before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RowAlign {
+    /// Start every row on a fresh byte, so no byte holds pixels of two rows.
+    Byte,
+    /// Start every row on a word boundary.
+    Word,
+}
+
+impl RowAlign {
+    fn bytes(self) -> usize {
+        match self {
+            RowAlign::Byte => 1,
+            RowAlign::Word => 4,
+        }
+    }
+}
+
+/// Displays whose framebuffer layout is set up in one go with `--target`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    /// The ILI9341 320x240 SPI LCD controller.
+    Ili9341,
+    /// The ST7735 160x128 SPI LCD controller.
+    St7735,
+}
+
+impl Target {
+    /// Set the options the display needs: both controllers take RGB565 over
+    /// SPI most significant byte first, top row first, and are fed by DMA so
+    /// every image starts on a word boundary.
+    pub fn apply(self, options: &mut Options) {
+        match self {
+            Target::Ili9341 | Target::St7735 => {
+                options.format = PixelFormat::Rgb565;
+                options.endian = Endian::Big;
+                options.row_order = RowOrder::TopDown;
+                options.align_assets = true;
+            }
+        }
+    }
+}
+
+/// The keywords data is written with, as assemblers spell them differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directives {
+    /// Starts a row of bytes, like `DEFB` or armasm's `DCB`.
+    pub byte: String,
+    /// Starts a row of words, like `DEFW` or armasm's `DCD`.
+    pub word: String,
+    /// Fills a run of identical bytes with a count and a value, like `DEFS`
+    /// or armasm's `FILL`, for [`Options::fill_runs`].
+    pub fill: String,
+}
+
+impl Default for Directives {
+    fn default() -> Self {
+        Directives {
+            byte: "DEFB".into(),
+            word: "DEFW".into(),
+            fill: "DEFS".into(),
+        }
+    }
+}
+
+/// Everything needed to encode an image, which is worked out once the palette
+/// is known.
+pub(crate) struct Encoding<'a> {
+    pub palette: &'a Palette,
+    /// The index of the palette's first colour, after the reserved ones.
+    pub index_offset: usize,
+    pub directives: &'a Directives,
+    pub format: PixelFormat,
+    pub endian: Endian,
+    pub row_order: RowOrder,
+    pub row_align: Option<RowAlign>,
+    pub bits_per_colour: usize,
+    /// `None` when the palette is too big for an index to fit in a byte, so
+    /// each takes a halfword of its own.
+    pub pixels_per_byte: Option<usize>,
+    /// The shortest run of identical bytes written as a fill.
+    pub fill_runs: Option<usize>,
+}
+
+impl<'a> Encoding<'a> {
+    pub fn new(palette: &'a Palette, options: &'a Options) -> Self {
+        // the reserved indices have to fit alongside the palette
+        let indices = options.reserved_indices + palette.len();
+        let bits_per_colour = match ((indices as f64).log2().ceil() as usize).max(1) {
+            bits if bits > 8 || options.wide_indices => 16,
+            bits => bits,
+        };
+        Encoding {
+            palette,
+            index_offset: options.reserved_indices,
+            directives: &options.directives,
+            format: options.format,
+            endian: options.endian,
+            row_order: options.row_order,
+            row_align: options.row_align,
+            bits_per_colour,
+            pixels_per_byte: (bits_per_colour <= 8).then_some(8 / bits_per_colour),
+            fill_runs: options.fill_runs,
+        }
+    }
+
+    /// The index a colour is written as, after the reserved indices.
+    pub fn index(&self, colour: &Pixel) -> Option<usize> {
+        self.palette
+            .index(colour)
+            .map(|index| self.index_offset + index)
+    }
+
+    /// The number of bytes an image of this size encodes to.
+    pub fn size(&self, width: u32, height: u32) -> usize {
+        match self.row_align {
+            Some(_) => self.stride(width) * height as usize,
+            None => self.unaligned_size(width, height),
+        }
+    }
+
+    /// The number of bytes from the start of one row to the next, which is
+    /// only whole when the rows are aligned.
+    pub fn stride(&self, width: u32) -> usize {
+        let bytes = self.unaligned_size(width, 1);
+        match self.row_align {
+            Some(align) => bytes.next_multiple_of(align.bytes()),
+            None => bytes,
+        }
+    }
+
+    /// The bytes from one row to the next, when every row starts on a byte
+    /// of its own, which packed pixels only do if the rows are aligned or
+    /// fill whole bytes.
+    pub fn row_bytes(&self, width: u32) -> Option<usize> {
+        let whole = match (self.format, self.pixels_per_byte) {
+            (PixelFormat::Indexed, Some(pixels_per_byte)) => {
+                self.row_align.is_some() || (width as usize).is_multiple_of(pixels_per_byte)
+            }
+            _ => true,
+        };
+        whole.then(|| self.stride(width))
+    }
+
+    /// The number of bytes an image encodes to without any row alignment.
+    pub fn unaligned_size(&self, width: u32, height: u32) -> usize {
+        // widened first, as the product of two u32s can overflow one
+        let pixels = width as usize * height as usize;
+        match (self.format.bytes_per_pixel(), self.pixels_per_byte) {
+            (Some(bytes), _) => pixels * bytes,
+            (None, Some(pixels_per_byte)) => pixels.div_ceil(pixels_per_byte),
+            (None, None) => pixels * 2,
+        }
+    }
+
+    /// A row of words. Little endian words use the word directive, but as it
+    /// always uses the ARM's own byte order, big endian words are written out
+    /// a byte at a time.
+    pub fn words(&self, values: &[u32]) -> String {
+        match self.endian {
+            Endian::Little => {
+                let words: Vec<String> = values
+                    .iter()
+                    .map(|value| format!("0x{:08X}", value))
+                    .collect();
+                format!("{} {}", self.directives.word, words.join(", "))
+            }
+            Endian::Big => {
+                let bytes: Vec<String> = values
+                    .iter()
+                    .flat_map(|value| value.to_be_bytes())
+                    .map(|byte| format!("0x{:02X}", byte))
+                    .collect();
+                format!("{} {}", self.directives.byte, bytes.join(", "))
+            }
+        }
+    }
+
+    /// Write bytes as rows of the byte directive, with the long runs of one
+    /// byte filled instead.
+    pub fn write_bytes(&self, buf: &mut impl FmtWrite, bytes: &[u8]) -> Result<()> {
+        for (fill, bytes) in runs(bytes, self.fill_runs) {
+            if fill {
+                writeln!(
+                    buf,
+                    "\t{} {}, 0x{:02X}",
+                    self.directives.fill,
+                    bytes.len(),
+                    bytes[0]
+                )?;
+                continue;
+            }
+            for row in bytes.chunks(5) {
+                write!(buf, "\t{} 0x{:02X}", self.directives.byte, row[0])?;
+                for byte in row.iter().skip(1) {
+                    write!(buf, ", 0x{:02X}", byte)?;
+                }
+                buf.write_char('\n')?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Put the rows of the image in the order they are written in.
+    fn rows<'i>(&self, image: &'i Image) -> Cow<'i, Image> {
+        match self.row_order {
+            RowOrder::TopDown => Cow::Borrowed(image),
+            RowOrder::BottomUp => Cow::Owned(image.flip(false, true)),
+        }
+    }
+
+    /// Encode each row by itself and pad it to the row alignment with `fill`,
+    /// if there is an alignment, so no byte is shared between rows.
+    fn aligned(
+        &self,
+        image: &Image,
+        fill: u8,
+        encode: impl Fn(&Image) -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        let align = match self.row_align {
+            Some(align) => align.bytes(),
+            None => return encode(image),
+        };
+
+        let mut bytes = Vec::new();
+        for y in 0..image.height() {
+            let mut row = encode(&image.crop(String::new(), 0, y, image.width(), 1))?;
+            row.resize(row.len().next_multiple_of(align), fill);
+            bytes.extend(row);
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn encode(&self, image: &Image) -> Result<Vec<u8>> {
+        self.aligned(&self.rows(image), 0, |image| self.encode_rows(image))
+    }
+
+    fn encode_rows(&self, image: &Image) -> Result<Vec<u8>> {
+        match self.format {
+            PixelFormat::Indexed => match self.pixels_per_byte {
+                Some(pixels_per_byte) => image.pack(
+                    self.palette,
+                    self.index_offset,
+                    pixels_per_byte,
+                    self.bits_per_colour,
+                    self.endian == Endian::Big,
+                ),
+                None => {
+                    image.pack_wide(self.palette, self.index_offset, self.endian == Endian::Big)
+                }
+            },
+            format => {
+                let bytes = format.bytes_per_pixel().unwrap_or(1);
+                Ok(image
+                    .iter()
+                    .flat_map(|pixel| {
+                        let colour = match format {
+                            PixelFormat::Argb4444 => {
+                                (pixel.a as u32 >> 4) << 12
+                                    | (pixel.r as u32 >> 4) << 8
+                                    | (pixel.g as u32 >> 4) << 4
+                                    | pixel.b as u32 >> 4
+                            }
+                            PixelFormat::Argb8888 => {
+                                (pixel.a as u32) << 24
+                                    | (pixel.r as u32) << 16
+                                    | (pixel.g as u32) << 8
+                                    | pixel.b as u32
+                            }
+                            _ => {
+                                (pixel.r as u32 >> 3) << 11
+                                    | (pixel.g as u32 >> 2) << 5
+                                    | pixel.b as u32 >> 3
+                            }
+                        };
+                        // keep the low bytes, which are at the end when big endian
+                        match self.endian {
+                            Endian::Little => colour.to_le_bytes()[..bytes].to_vec(),
+                            Endian::Big => colour.to_be_bytes()[4 - bytes..].to_vec(),
+                        }
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /// The image as pairs of words for AND/OR blitting, each a mask word
+    /// with every bit of the transparent pixels set followed by the pixels
+    /// with the transparent ones cleared. Pixels less than half opaque are
+    /// transparent, and are drawn as the first palette colour before being
+    /// cleared so they needn't be in the palette.
+    pub fn masked(&self, image: &Image) -> Result<Vec<u8>> {
+        let background = self.palette.colours().first().copied().unwrap_or_default();
+        let solid = image.map(|pixel| match pixel.a < 0x80 {
+            true => background,
+            false => pixel,
+        });
+        let data = self.encode(&solid)?;
+        let mask = self.mask(image)?;
+
+        // past the end of the image and its rows the mask keeps whatever is
+        // underneath
+        let mut pairs = Vec::with_capacity(2 * data.len().next_multiple_of(4));
+        for (data, mask) in data.chunks(4).zip(mask.chunks(4)) {
+            let mask: Vec<u8> = mask.iter().copied().chain([0xFF; 4]).take(4).collect();
+            pairs.extend(&mask);
+            pairs.extend((0..4).map(|i| data.get(i).copied().unwrap_or(0) & !mask[i]));
+        }
+
+        Ok(pairs)
+    }
+
+    /// A mask with every bit of the transparent pixels set, laid out like
+    /// the pixels, and set through the padding of aligned rows too.
+    pub fn mask(&self, image: &Image) -> Result<Vec<u8>> {
+        self.aligned(&self.rows(image), 0xFF, |image| Ok(self.mask_rows(image)))
+    }
+
+    fn mask_rows(&self, image: &Image) -> Vec<u8> {
+        let transparent = |pixel: &Pixel| pixel.a < 0x80;
+        match (self.format, self.pixels_per_byte) {
+            (PixelFormat::Indexed, Some(pixels_per_byte)) => {
+                let bits = self.bits_per_colour;
+                let ones = (1 << bits) - 1;
+                let pixels: Vec<&Pixel> = image.iter().collect();
+                pixels
+                    .chunks(pixels_per_byte)
+                    .map(|chunk| {
+                        // unused bits are kept too, so start with them all set
+                        let opaque = chunk.iter().enumerate().filter(|(_, p)| !transparent(p));
+                        opaque.fold(0xFF_u32, |mask, (i, _)| {
+                            let shift = match self.endian {
+                                Endian::Big => 8 - (i + 1) * bits,
+                                Endian::Little => i * bits,
+                            };
+                            mask & !(ones << shift)
+                        }) as u8
+                    })
+                    .collect()
+            }
+            (format, _) => {
+                let bytes = format.bytes_per_pixel().unwrap_or(2);
+                image
+                    .iter()
+                    .flat_map(|pixel| {
+                        let byte = match transparent(pixel) {
+                            true => 0xFF,
+                            false => 0x00,
+                        };
+                        std::iter::repeat_n(byte, bytes)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// The runs of transparent and opaque pixels along each row, in the order
+    /// the rows are written. Pixels less than half opaque are transparent,
+    /// and the transparent pixels at the end of a row aren't in any span.
+    pub fn spans(&self, image: &Image) -> Vec<Vec<Span>> {
+        let image = self.rows(image);
+        let pixels: Vec<&Pixel> = image.iter().collect();
+        pixels
+            .chunks(image.width().max(1) as usize)
+            .map(|row| {
+                let mut spans = Vec::new();
+                let mut x = 0;
+                while x < row.len() {
+                    let skip = row[x..].iter().take_while(|pixel| pixel.a < 0x80).count();
+                    let draw = row[x + skip..]
+                        .iter()
+                        .take_while(|pixel| pixel.a >= 0x80)
+                        .count();
+                    if draw > 0 {
+                        spans.push(Span {
+                            skip: skip as u32,
+                            draw: draw as u32,
+                        });
+                    }
+                    x += skip + draw;
+                }
+                spans
+            })
+            .collect()
+    }
+
+    /// The image's alpha channel as a plane of its own, in the same row order
+    /// and alignment as the colours. Single bits are packed like indexed
+    /// pixels.
+    pub fn alpha(&self, image: &Image, plane: AlphaPlane) -> Result<Vec<u8>> {
+        self.aligned(&self.rows(image), 0, |image| {
+            Ok(self.alpha_rows(image, plane))
+        })
+    }
+
+    fn alpha_rows(&self, image: &Image, plane: AlphaPlane) -> Vec<u8> {
+        match plane {
+            AlphaPlane::Bits8 => image.iter().map(|pixel| pixel.a).collect(),
+            AlphaPlane::Bits1 => image
+                .iter()
+                .collect::<Vec<_>>()
+                .chunks(8)
+                .map(|chunk| {
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, pixel)| pixel.a >= 0x80)
+                        .fold(0, |acc, (i, _)| match self.endian {
+                            Endian::Little => acc | 1 << i,
+                            Endian::Big => acc | 0x80 >> i,
+                        })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Split bytes into the runs of at least `shortest` copies of one byte, which
+/// are marked to be filled, and the bytes between them.
+pub(crate) fn runs(bytes: &[u8], shortest: Option<usize>) -> Vec<(bool, &[u8])> {
+    let shortest = match shortest {
+        Some(shortest) => shortest,
+        None => return vec![(false, bytes)],
+    };
+
+    let mut runs = Vec::new();
+    let (mut start, mut i) = (0, 0);
+    while i < bytes.len() {
+        let length = bytes[i..]
+            .iter()
+            .take_while(|byte| **byte == bytes[i])
+            .count();
+        if length >= shortest {
+            if start < i {
+                runs.push((false, &bytes[start..i]));
+            }
+            runs.push((true, &bytes[i..i + length]));
+            start = i + length;
+        }
+        i += length;
+    }
+    if start < bytes.len() {
+        runs.push((false, &bytes[start..]));
+    }
+
+    runs
+}
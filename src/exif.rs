@@ -0,0 +1,37 @@
+//! Reading the orientation out of EXIF data, so photos saved on their side
+//! by a camera come out upright.
+
+/// The tag of the orientation in the first IFD.
+const ORIENTATION: u16 = 0x0112;
+
+/// The orientation an image is stored in, from 1 for upright to 8, as the
+/// EXIF orientation tag numbers them.
+pub(crate) fn orientation(exif: &[u8]) -> Option<u16> {
+    let big = match exif.get(..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| {
+        let b = exif.get(at..at + 2)?;
+        Some(match big {
+            true => u16::from_be_bytes([b[0], b[1]]),
+            false => u16::from_le_bytes([b[0], b[1]]),
+        })
+    };
+    let u32_at = |at: usize| {
+        let b = exif.get(at..at + 4)?;
+        Some(match big {
+            true => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            false => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        })
+    };
+
+    // each entry is a tag, a type, a count and a value which fits in 4 bytes
+    let ifd = u32_at(4)? as usize;
+    (0..u16_at(ifd)? as usize)
+        .map(|index| ifd + 2 + index * 12)
+        .find(|&entry| u16_at(entry) == Some(ORIENTATION))
+        .and_then(|entry| u16_at(entry + 8))
+        .filter(|orientation| (1..=8).contains(orientation))
+}
@@ -0,0 +1,312 @@
+//! Reading the sprites back out of an assembly file this tool wrote, for
+//! when the art is lost but the output survives.
+
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::encoding::{Endian, RowOrder};
+use crate::image::{Image, Pixel};
+use crate::merge::{byte_directive, constants, records, Existing};
+use anyhow::{ensure, Result};
+use std::path::Path;
+
+/// How a file was written, where the file itself doesn't say.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExtractOptions {
+    /// The width of every asset, for files written without `--records`.
+    pub width: Option<u32>,
+    /// The byte order the file was written with.
+    pub endian: Endian,
+    /// The order the file's rows were written in.
+    pub row_order: RowOrder,
+}
+
+/// Decode every asset in the address table of an indexed assembly file
+/// back into an image, named after its label.
+pub fn extract(path: &Path, options: &ExtractOptions) -> Result<Vec<Image>> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        Diagnostic::new(
+            FailureClass::Input,
+            format!("failed to read the file to extract from: {}", e),
+        )
+        .with_file(path)
+        .with_suggestion("check that the path exists and is readable")
+    })?;
+    let input_error = |reason: String| {
+        Diagnostic::new(FailureClass::Input, reason)
+            .with_file(path)
+            .with_suggestion("only indexed output written by image2arm can be extracted from")
+    };
+    let mut existing = Existing::parse(&text, byte_directive(&text)).map_err(input_error)?;
+
+    let constants = constants(&text);
+    ensure!(
+        !constants.contains_key("MASK_OFFSET"),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "masked sprites can't be extracted, as their masks are mixed in with the pixels"
+        )
+        .with_file(path)
+        .with_suggestion("regenerate the file without --masked")
+    );
+    // halfword indices have no pixels_per_byte
+    let pixels_per_byte = constants.get("pixels_per_byte").copied();
+    let records = records(&text, options.endian).map_err(input_error)?;
+
+    // the data is read as it was before it was scrambled
+    if let Some(cipher) = &existing.cipher {
+        for asset in existing.assets.iter_mut() {
+            cipher.decrypt(&mut asset.data);
+            if let Some(alpha) = asset.alpha.as_mut() {
+                cipher.decrypt(alpha);
+            }
+        }
+    }
+    // files from before the alpha planes were described can't be read
+    let alpha_bits = constants.get("bits_per_alpha").copied();
+    ensure!(
+        alpha_bits.is_some() || existing.assets.iter().all(|asset| asset.alpha.is_none()),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "the file has alpha planes, but doesn't say how many bits of alpha each pixel has"
+        )
+        .with_file(path)
+        .with_suggestion("regenerate the file with this version of image2arm")
+    );
+
+    let mut images = Vec::with_capacity(existing.assets.len());
+    for (index, asset) in existing.assets.iter().enumerate() {
+        let stride = constants
+            .get(format!("STRIDE{}", asset.label).as_str())
+            .copied();
+        ensure!(
+            asset.odd_field.is_none(),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "{} is split into fields, which can't be extracted",
+                    asset.name
+                )
+            )
+            .with_file(path)
+            .with_suggestion("regenerate the file without --fields")
+        );
+
+        // without records the height is however many rows the data holds
+        let (width, height) = match (records.get(index), options.width) {
+            (Some(record), _) => (record.width, record.height),
+            (None, Some(width)) => {
+                let width = width as usize;
+                let rows = match (stride, pixels_per_byte) {
+                    (Some(stride), _) => asset.data.len() / stride,
+                    (None, Some(pixels)) => asset.data.len() * pixels / width,
+                    (None, None) => asset.data.len() / 2 / width,
+                };
+                (width as u32, rows as u32)
+            }
+            (None, None) => {
+                return Err(Diagnostic::new(
+                    FailureClass::Usage,
+                    "the file has no asset records giving the sizes of its assets",
+                )
+                .with_file(path)
+                .with_suggestion("pass --width, or regenerate the file with --records")
+                .into())
+            }
+        };
+        let (width, height) = (width as usize, height as usize);
+
+        let row_bytes = match pixels_per_byte {
+            Some(pixels) => width.div_ceil(pixels),
+            None => width * 2,
+        };
+        ensure!(
+            stride.is_none_or(|stride| row_bytes <= stride),
+            input_error(format!(
+                "{} is {} bytes a row, too narrow for {} pixels",
+                asset.name,
+                stride.unwrap_or_default(),
+                width
+            ))
+        );
+        let needed = match (stride, pixels_per_byte) {
+            (Some(stride), _) => stride * height,
+            (None, Some(pixels)) => (width * height).div_ceil(pixels),
+            (None, None) => width * height * 2,
+        };
+        ensure!(
+            asset.data.len() >= needed,
+            input_error(format!(
+                "{} is {} bytes, short of the {} a {}x{} image needs",
+                asset.name,
+                asset.data.len(),
+                needed,
+                width,
+                height
+            ))
+        );
+
+        let layout = Layout {
+            palette: &existing.palette,
+            palette_offset: existing.palette_offset,
+            bits_per_colour: existing.bits_per_colour,
+            pixels_per_byte,
+            stride,
+            endian: options.endian,
+            row_order: options.row_order,
+        };
+        let mut pixels = decode(&asset.data, width, height, &layout).map_err(|colour| {
+            input_error(format!(
+                "{} uses colour {}, outside the {} colour palette after {} reserved indices",
+                asset.name,
+                colour,
+                existing.palette.len(),
+                existing.palette_offset
+            ))
+        })?;
+        if let (Some(alpha), Some(bits)) = (&asset.alpha, alpha_bits) {
+            // aligned alpha rows are padded like the colours
+            let row_bytes = match bits {
+                8 => width,
+                _ => width.div_ceil(8),
+            };
+            let alpha_stride = stride.map(|_| alpha.len() / height.max(1));
+            let needed = match alpha_stride {
+                Some(alpha_stride) => alpha_stride.max(row_bytes) * height,
+                None => (width * height * bits).div_ceil(8),
+            };
+            ensure!(
+                alpha.len() >= needed,
+                input_error(format!(
+                    "{}'s alpha plane is {} bytes, short of the {} a {}x{} image needs",
+                    asset.name,
+                    alpha.len(),
+                    needed,
+                    width,
+                    height
+                ))
+            );
+            apply_alpha(
+                &mut pixels,
+                alpha,
+                width,
+                height,
+                bits,
+                alpha_stride,
+                &layout,
+            );
+        }
+
+        images.push(Image::new(
+            asset.name.clone(),
+            width as u32,
+            height as u32,
+            pixels,
+        ));
+    }
+
+    Ok(images)
+}
+
+/// Set the alpha of each decoded pixel from an alpha plane of a byte or a bit
+/// per pixel, laid out like the data.
+fn apply_alpha(
+    pixels: &mut [Pixel],
+    alpha: &[u8],
+    width: usize,
+    height: usize,
+    bits: usize,
+    stride: Option<usize>,
+    layout: &Layout,
+) {
+    for y in 0..height {
+        let row = match layout.row_order {
+            RowOrder::TopDown => y,
+            RowOrder::BottomUp => height - 1 - y,
+        };
+        for x in 0..width {
+            let i = match stride {
+                Some(stride) => row * stride * 8 / bits + x,
+                None => row * width + x,
+            };
+            let a = match bits {
+                8 => alpha[i],
+                _ => {
+                    let bit = match layout.endian {
+                        Endian::Little => 1 << (i % 8),
+                        Endian::Big => 0x80 >> (i % 8),
+                    };
+                    match alpha[i / 8] & bit {
+                        0 => 0x00,
+                        _ => 0xFF,
+                    }
+                }
+            };
+            pixels[y * width + x].a = a;
+        }
+    }
+}
+
+/// How an asset's palette indices are packed into its data.
+pub(crate) struct Layout<'a> {
+    pub palette: &'a [Pixel],
+    /// The index of the palette's first colour, after the reserved ones.
+    pub palette_offset: usize,
+    pub bits_per_colour: usize,
+    /// `None` when each index is a halfword.
+    pub pixels_per_byte: Option<usize>,
+    /// The bytes from one row to the next, when rows are aligned.
+    pub stride: Option<usize>,
+    pub endian: Endian,
+    pub row_order: RowOrder,
+}
+
+/// Look up each of an asset's packed indices in the palette, giving the
+/// first index outside it on failure. The data has to hold every row.
+pub(crate) fn decode(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    layout: &Layout,
+) -> Result<Vec<Pixel>, usize> {
+    let bits = layout.bits_per_colour;
+    let big = layout.endian == Endian::Big;
+    let stride = layout.stride;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = match layout.row_order {
+            RowOrder::TopDown => y,
+            RowOrder::BottomUp => height - 1 - y,
+        };
+        for x in 0..width {
+            let colour = match layout.pixels_per_byte {
+                Some(pixels) => {
+                    let (byte, i) = match stride {
+                        Some(stride) => (row * stride + x / pixels, x % pixels),
+                        None => ((row * width + x) / pixels, (row * width + x) % pixels),
+                    };
+                    let shift = match big {
+                        true => 8 - (i + 1) * bits,
+                        false => i * bits,
+                    };
+                    (data[byte] as usize >> shift) & ((1 << bits) - 1)
+                }
+                None => {
+                    let start = stride.map_or(row * width * 2, |stride| row * stride) + x * 2;
+                    let bytes = [data[start], data[start + 1]];
+                    match big {
+                        true => u16::from_be_bytes(bytes) as usize,
+                        false => u16::from_le_bytes(bytes) as usize,
+                    }
+                }
+            };
+            // the reserved indices come before the palette
+            let pixel = colour
+                .checked_sub(layout.palette_offset)
+                .and_then(|index| layout.palette.get(index))
+                .copied()
+                .ok_or(colour)?;
+            pixels.push(pixel);
+        }
+    }
+
+    Ok(pixels)
+}
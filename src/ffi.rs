@@ -0,0 +1,237 @@
+//! A C interface to the conversion, so asset pipelines written in C or C++
+//! can embed the converter instead of shelling out to it.
+//!
+//! The header lives in `include/image2arm.h` and is generated with
+//! `cbindgen --config cbindgen.toml --output include/image2arm.h`.
+
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::encoding::{Endian, PixelFormat};
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+
+/// Options for `image2arm_convert`, a NULL pointer means use the defaults,
+/// as does a struct zeroed but for `size`.
+#[repr(C)]
+pub struct Image2armOptions {
+    /// Must be set to `sizeof(Image2armOptions)`, which lets fields be added
+    /// later without breaking callers compiled against an older header. The
+    /// first header's struct held only `size`, so a `size` of
+    /// `sizeof(size_t)` means that struct, and gets the defaults.
+    pub size: usize,
+    /// How each pixel is stored, one of the `IMAGE2ARM_FORMAT_` constants.
+    pub format: u32,
+    /// The byte order of pixels and tables wider than a byte, one of the
+    /// `IMAGE2ARM_ENDIAN_` constants.
+    pub endian: u32,
+    /// The names of the outputs to write, like "asm", "c-header" and "json",
+    /// each named after `out_path` and written alongside it. NULL writes only
+    /// the assembly.
+    pub emit: *const *const c_char,
+    /// How many names `emit` points to.
+    pub emit_count: usize,
+    /// Merge palette colours whose channels all differ by no more than this.
+    pub merge_tolerance: u8,
+    /// How many palette indices at the start belong to the system, which the
+    /// palette is numbered after.
+    pub reserved_indices: usize,
+}
+
+// The values of `Image2armOptions.format`, as `PixelFormat` has them. They
+// are plain integers as C can put any value in an enum.
+pub const IMAGE2ARM_FORMAT_INDEXED: u32 = 0;
+pub const IMAGE2ARM_FORMAT_RGB565: u32 = 1;
+pub const IMAGE2ARM_FORMAT_ARGB4444: u32 = 2;
+pub const IMAGE2ARM_FORMAT_ARGB8888: u32 = 3;
+
+// The values of `Image2armOptions.endian`.
+pub const IMAGE2ARM_ENDIAN_LITTLE: u32 = 0;
+pub const IMAGE2ARM_ENDIAN_BIG: u32 = 1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Compile `n` PNG files into an assembly file written to `out_path`, along
+/// with any other outputs the options name.
+///
+/// Returns 0 on success, otherwise the exit code the command line tool would
+/// have used for the same failure, with the message available from
+/// `image2arm_last_error`.
+///
+/// # Safety
+///
+/// `paths` must point to `n` valid NUL terminated strings, `out_path` must be
+/// a valid NUL terminated string and `options` must be NULL or point to an
+/// `Image2armOptions` with `size` filled in, whose `emit` points to
+/// `emit_count` valid NUL terminated strings unless it is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn image2arm_convert(
+    paths: *const *const c_char,
+    n: usize,
+    options: *const Image2armOptions,
+    out_path: *const c_char,
+) -> c_int {
+    // a panic unwinding into C is undefined behaviour, so catch it here
+    let result = std::panic::catch_unwind(|| convert(paths, n, options, out_path))
+        .unwrap_or_else(|_| Err(anyhow!("the conversion panicked")));
+
+    match result {
+        Ok(()) => {
+            set_last_error(None);
+            0
+        }
+        Err(err) => {
+            let class = err
+                .chain()
+                .find_map(|e| e.downcast_ref::<Diagnostic>())
+                .map_or(FailureClass::Internal, |d| d.class);
+            set_last_error(Some(format!("{:#}", err)));
+            class.exit_code()
+        }
+    }
+}
+
+/// The message for the last failed call on this thread, or NULL if the last
+/// call succeeded. The string is owned by the library and is only valid until
+/// the next call into it.
+#[no_mangle]
+pub extern "C" fn image2arm_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+fn set_last_error(message: Option<String>) {
+    // a message containing a NUL can't be given to C whole, so cut it short
+    let message = message.map(|message| {
+        let end = message.find('\0').unwrap_or(message.len());
+        CString::new(&message[..end]).unwrap_or_default()
+    });
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+unsafe fn convert(
+    paths: *const *const c_char,
+    n: usize,
+    options: *const Image2armOptions,
+    out_path: *const c_char,
+) -> Result<()> {
+    if (paths.is_null() && n != 0) || out_path.is_null() {
+        return Err(Diagnostic::new(FailureClass::Usage, "a required pointer was NULL").into());
+    }
+
+    let inputs = (0..n)
+        .map(|i| path_from_c(*paths.add(i)))
+        .collect::<Result<Vec<_>>>()?;
+    let output = path_from_c(out_path)?;
+    let file_name = output
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Diagnostic::new(FailureClass::Usage, "the output path has no file name"))?;
+    let options = crate::Options {
+        file_name: file_name.to_owned(),
+        ..options_from_c(options)?
+    };
+
+    let out_dir = output.parent().unwrap_or_else(|| Path::new(""));
+    for file in crate::convert_files(&inputs, &options)? {
+        let path = out_dir.join(&file.name);
+        std::fs::write(&path, file.contents).map_err(|e| {
+            Diagnostic::new(
+                FailureClass::Output,
+                format!("failed to write output: {}", e),
+            )
+            .with_file(&path)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// The options a caller asked for. Callers compiled against the first
+/// header, whose struct only held `size`, get the defaults, which any other
+/// struct of that size gets too, as there's no telling them apart.
+unsafe fn options_from_c(options: *const Image2armOptions) -> Result<crate::Options> {
+    let defaults = crate::Options::default();
+    if options.is_null() || (*options).size == std::mem::size_of::<usize>() {
+        return Ok(defaults);
+    }
+    let options = &*options;
+    if options.size < std::mem::size_of::<Image2armOptions>() {
+        return Err(Diagnostic::new(
+            FailureClass::Usage,
+            "Image2armOptions.size is smaller than the struct",
+        )
+        .with_suggestion("set size to sizeof(Image2armOptions)")
+        .into());
+    }
+    if options.emit.is_null() && options.emit_count != 0 {
+        return Err(Diagnostic::new(FailureClass::Usage, "Image2armOptions.emit was NULL").into());
+    }
+
+    let emit = match options.emit.is_null() {
+        true => defaults.emit.clone(),
+        false => (0..options.emit_count)
+            .map(|i| string_from_c(*options.emit.add(i)))
+            .collect::<Result<Vec<_>>>()?,
+    };
+    let unknown = |field: &str, value: u32, prefix: &str| {
+        Diagnostic::new(
+            FailureClass::Usage,
+            format!(
+                "Image2armOptions.{} is {}, which isn't one of the constants",
+                field, value
+            ),
+        )
+        .with_suggestion(format!("set it to one of the {} constants", prefix))
+    };
+    let format = match options.format {
+        IMAGE2ARM_FORMAT_INDEXED => PixelFormat::Indexed,
+        IMAGE2ARM_FORMAT_RGB565 => PixelFormat::Rgb565,
+        IMAGE2ARM_FORMAT_ARGB4444 => PixelFormat::Argb4444,
+        IMAGE2ARM_FORMAT_ARGB8888 => PixelFormat::Argb8888,
+        value => return Err(unknown("format", value, "IMAGE2ARM_FORMAT_").into()),
+    };
+    let endian = match options.endian {
+        IMAGE2ARM_ENDIAN_LITTLE => Endian::Little,
+        IMAGE2ARM_ENDIAN_BIG => Endian::Big,
+        value => return Err(unknown("endian", value, "IMAGE2ARM_ENDIAN_").into()),
+    };
+    Ok(crate::Options {
+        format,
+        endian,
+        emit,
+        merge_tolerance: options.merge_tolerance,
+        reserved_indices: options.reserved_indices,
+        ..defaults
+    })
+}
+
+unsafe fn path_from_c(path: *const c_char) -> Result<PathBuf> {
+    if path.is_null() {
+        return Err(Diagnostic::new(FailureClass::Usage, "a path was NULL").into());
+    }
+
+    let path = CStr::from_ptr(path)
+        .to_str()
+        .map_err(|_| Diagnostic::new(FailureClass::Input, "a path contained invalid UTF-8"))?;
+
+    Ok(PathBuf::from(path))
+}
+
+unsafe fn string_from_c(string: *const c_char) -> Result<String> {
+    if string.is_null() {
+        return Err(Diagnostic::new(FailureClass::Usage, "a name in emit was NULL").into());
+    }
+
+    let string = CStr::from_ptr(string)
+        .to_str()
+        .map_err(|_| Diagnostic::new(FailureClass::Usage, "a name contained invalid UTF-8"))?;
+
+    Ok(string.to_owned())
+}
@@ -0,0 +1,60 @@
+//! A hash of everything that went into a conversion and came out of it, so
+//! a build system can record exactly which build of the assets it used.
+
+use crate::image::Image;
+use crate::output::OutputFile;
+use crate::Options;
+use std::fmt;
+
+const OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// A 64-bit FNV-1a hash, fed each part with its length so parts which run
+/// into each other can't hash the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Fingerprint(u64);
+
+impl Fingerprint {
+    /// A fingerprint of the version of this crate and the options, leaving
+    /// out the ones which only change how the work is done.
+    pub(crate) fn new(options: &Options) -> Self {
+        let options = Options {
+            jobs: 1,
+            max_mem: None,
+            ..options.clone()
+        };
+        let mut fingerprint = Fingerprint(OFFSET_BASIS);
+        fingerprint.update(env!("CARGO_PKG_VERSION").as_bytes());
+        fingerprint.update(format!("{:?}", options).as_bytes());
+        fingerprint
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(PRIME);
+        }
+    }
+
+    /// Add a decoded input, by its name, size and pixels.
+    pub(crate) fn image(&mut self, image: &Image) {
+        self.update(image.name().as_bytes());
+        self.update(&image.width().to_le_bytes());
+        self.update(&image.height().to_le_bytes());
+        let pixels: Vec<u8> = image
+            .iter()
+            .flat_map(|pixel| [pixel.r, pixel.g, pixel.b, pixel.a])
+            .collect();
+        self.update(&pixels);
+    }
+
+    pub(crate) fn file(&mut self, file: &OutputFile) {
+        self.update(file.name.as_bytes());
+        self.update(&file.contents);
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
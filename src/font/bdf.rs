@@ -0,0 +1,112 @@
+//! Glyph Bitmap Distribution Format, the text based X11 bitmap font format.
+
+use super::{GLYPH_CLEAR, GLYPH_SET};
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::image::{Image, Size};
+use std::convert::TryFrom;
+
+/// Render every encoded glyph into a cell the size of the font's bounding
+/// box, lined up on a shared baseline.
+pub(super) fn parse(name: &str, source: &str) -> Result<(Size, Vec<(char, Image)>), Diagnostic> {
+    let invalid = |line: usize, reason: &str| {
+        Diagnostic::new(
+            FailureClass::Decode,
+            format!("invalid BDF on line {}: {}", line + 1, reason),
+        )
+        .with_suggestion("check the font was exported as BDF 2.1")
+    };
+    let numbers = |line: usize, fields: &[&str], n: usize| {
+        let values = fields
+            .iter()
+            .skip(1)
+            .take(n)
+            .map(|field| field.parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid(line, "expected a number"))?;
+        match values.len() == n {
+            true => Ok(values),
+            false => Err(invalid(line, &format!("{} needs {} numbers", fields[0], n))),
+        }
+    };
+
+    let mut lines = source.lines().enumerate();
+    let mut bounds = None;
+    let mut glyphs = Vec::new();
+
+    while let Some((line, text)) = lines.next() {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        match fields.first().copied() {
+            Some("FONTBOUNDINGBOX") => bounds = Some(numbers(line, &fields, 4)?),
+            Some("STARTCHAR") => {
+                let [width, height, x_offset, y_offset] = match bounds.as_deref() {
+                    Some(&[w, h, x, y]) => [w, h, x, y],
+                    _ => return Err(invalid(line, "STARTCHAR before FONTBOUNDINGBOX")),
+                };
+                let ascent = height + y_offset;
+
+                let mut encoding = None;
+                let mut bbx = [width, height, x_offset, y_offset];
+                let mut cell = vec![GLYPH_CLEAR; (width.max(0) * height.max(0)) as usize];
+
+                while let Some((line, text)) = lines.next() {
+                    let fields: Vec<&str> = text.split_whitespace().collect();
+                    match fields.first().copied() {
+                        // -1 marks a glyph with no standard encoding, which can't be looked up
+                        Some("ENCODING") => {
+                            encoding = numbers(line, &fields, 1)?
+                                .first()
+                                .and_then(|code| u32::try_from(*code).ok())
+                                .and_then(char::from_u32)
+                        }
+                        Some("BBX") => {
+                            let values = numbers(line, &fields, 4)?;
+                            bbx = [values[0], values[1], values[2], values[3]];
+                        }
+                        Some("BITMAP") => {
+                            let [glyph_width, glyph_height, glyph_x, glyph_y] = bbx;
+                            let top = ascent - (glyph_y + glyph_height);
+                            let left = glyph_x - x_offset;
+
+                            for row in 0..glyph_height {
+                                let (line, hex) = lines
+                                    .next()
+                                    .ok_or_else(|| invalid(line, "the bitmap is cut short"))?;
+                                let bits = u128::from_str_radix(hex.trim(), 16)
+                                    .map_err(|_| invalid(line, "expected a row of hex"))?;
+                                let row_bits = hex.trim().len() as i32 * 4;
+
+                                for column in 0..glyph_width.min(row_bits) {
+                                    let (x, y) = (left + column, top + row);
+                                    let set = bits >> (row_bits - 1 - column) & 1 == 1;
+                                    if set && (0..width).contains(&x) && (0..height).contains(&y) {
+                                        cell[(y * width + x) as usize] = GLYPH_SET;
+                                    }
+                                }
+                            }
+                        }
+                        Some("ENDCHAR") => break,
+                        _ => {}
+                    }
+                }
+
+                if let Some(c) = encoding {
+                    let image = Image::new(
+                        format!("{}[{:?}]", name, c),
+                        width as u32,
+                        height as u32,
+                        cell,
+                    );
+                    glyphs.push((c, image));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match bounds.as_deref() {
+        Some(&[width, height, ..]) if width > 0 && height > 0 => {
+            Ok((Size::new(width as u32, height as u32), glyphs))
+        }
+        _ => Err(invalid(0, "missing or empty FONTBOUNDINGBOX")),
+    }
+}
@@ -0,0 +1,581 @@
+mod bdf;
+mod psf;
+mod render;
+
+use crate::colour_space::ColourManagement;
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::encoding::Encoding;
+use crate::image::{Image, Pixel, Size};
+use crate::palette::parse_colour;
+use anyhow::Result;
+use log::{debug, info, warn};
+use std::fmt::Write as FmtWrite;
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::str::{Chars, FromStr};
+
+/// The colours bitmap font formats are drawn in, as they only store on or off.
+const GLYPH_SET: Pixel = Pixel::new(0xFF, 0xFF, 0xFF, 0xFF);
+const GLYPH_CLEAR: Pixel = Pixel::new(0, 0, 0, 0);
+
+/// The size of a glyph when it isn't given for a font image.
+const DEFAULT_GLYPH: Size = Size::new(8, 8);
+
+/// Where to find a font and how to cut it up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontOptions {
+    /// Either an image containing every glyph, left to right then top to
+    /// bottom, or a BDF or PSF bitmap font.
+    pub path: PathBuf,
+    /// The size of a single glyph in a font image, 8x8 by default. Bitmap
+    /// font files know their own size so ignore this.
+    pub glyph: Option<Size>,
+    /// The character each glyph draws. For a font image these are in the
+    /// order they appear and default to ' '..'~', for a bitmap font file they
+    /// pick out which characters to keep and default to all of them.
+    pub charset: Option<Charset>,
+    /// How to find the width of each glyph, or `None` for a monospaced font.
+    pub proportional: Option<Proportional>,
+    /// The gap in pixels between glyphs of a proportional font.
+    pub letter_spacing: u32,
+    /// Look characters up with a sorted codepoint table and a search routine,
+    /// instead of a table covering every code from the first to the last.
+    pub codepoint_table: bool,
+    /// Generate a `draw_string` routine for the font.
+    pub text_renderer: bool,
+}
+
+/// How the width of each glyph in a proportional font is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Proportional {
+    /// A glyph ends after its rightmost column that isn't background, where
+    /// background is anything fully transparent, or the colour of the top left
+    /// pixel for fonts without transparency. Empty glyphs, such as space, are
+    /// half a cell wide.
+    Trim,
+    /// A glyph ends at the first column filled with this colour, the column and
+    /// everything right of it are made transparent.
+    Separator(Pixel),
+}
+
+impl FromStr for Proportional {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None if s == "trim" => Ok(Proportional::Trim),
+            Some(("separator", colour)) => Ok(Proportional::Separator(parse_colour(colour)?)),
+            _ => Err(format!(
+                "expected 'trim' or 'separator=#RRGGBB', found '{}'",
+                s
+            )),
+        }
+    }
+}
+
+/// The characters in a font, in the order their glyphs appear.
+///
+/// Written as a comma separated list of characters and inclusive ranges,
+/// optionally quoted, e.g. `' '..'~'` or `0..9,A..F`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Charset(Vec<char>);
+
+impl Default for Charset {
+    /// Printable ASCII, ' '..'~'.
+    fn default() -> Self {
+        Charset((' '..='~').collect())
+    }
+}
+
+impl Charset {
+    #[inline]
+    pub fn chars(&self) -> &[char] {
+        &self.0
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn first(&self) -> char {
+        self.0.iter().copied().min().unwrap_or_default()
+    }
+
+    fn last(&self) -> char {
+        self.0.iter().copied().max().unwrap_or_default()
+    }
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // a character is either bare or surrounded by single quotes
+        fn parse_char(input: &mut Peekable<Chars>) -> Result<char, String> {
+            while input.next_if(|c| c.is_whitespace()).is_some() {}
+            match input.next() {
+                Some('\'') => {
+                    let c = input.next().ok_or("unterminated quote")?;
+                    input.next_if_eq(&'\'').ok_or("unterminated quote")?;
+                    Ok(c)
+                }
+                Some(c) => Ok(c),
+                None => Err("expected a character".into()),
+            }
+        }
+
+        let mut input = s.chars().peekable();
+        let mut chars = Vec::new();
+
+        loop {
+            let start = parse_char(&mut input)?;
+            while input.next_if(|c| c.is_whitespace()).is_some() {}
+
+            if input.next_if_eq(&'.').is_some() {
+                input.next_if_eq(&'.').ok_or("ranges are written a..b")?;
+                let end = parse_char(&mut input)?;
+                if end < start {
+                    return Err(format!("the range {:?}..{:?} is backwards", start, end));
+                }
+                chars.extend(start..=end);
+            } else {
+                chars.push(start);
+            }
+
+            while input.next_if(|c| c.is_whitespace()).is_some() {}
+            match input.next() {
+                None => break,
+                Some(',') => continue,
+                Some(c) => return Err(format!("expected ',' but found {:?}", c)),
+            }
+        }
+
+        // a character with two glyphs can only ever draw one of them
+        let mut seen = std::collections::HashSet::new();
+        if let Some(c) = chars.iter().find(|c| !seen.insert(**c)) {
+            return Err(format!("{:?} appears more than once", c));
+        }
+
+        Ok(Charset(chars))
+    }
+}
+
+/// A font sliced into one image per glyph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Font {
+    name: String,
+    glyph: Size,
+    charset: Charset,
+    glyphs: Vec<Image>,
+    /// The width of each glyph and the letter spacing, for proportional fonts.
+    widths: Option<(Vec<u32>, u32)>,
+    /// Whether characters are found with a sorted table rather than by offset.
+    codepoint_table: bool,
+    /// The colour left undrawn around each glyph.
+    background: Pixel,
+    /// Whether to generate a `draw_string` routine.
+    text_renderer: bool,
+}
+
+impl Font {
+    pub fn load(options: &FontOptions, colour_management: ColourManagement) -> Result<Self> {
+        let path = &options.path;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+
+        let (mut font, background) = match extension.as_deref() {
+            Some("bdf") | Some("psf") | Some("psfu") => {
+                let bytes = std::fs::read(path).map_err(|e| {
+                    Diagnostic::new(FailureClass::Input, format!("failed to open file: {}", e))
+                        .with_file(path)
+                        .with_suggestion("check that the path exists and is readable")
+                })?;
+                let name = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or("font")
+                    .to_owned();
+
+                if options.glyph.is_some() {
+                    warn!(
+                        "{} sets its own glyph size, ignoring --glyph",
+                        path.display()
+                    );
+                }
+
+                let (size, glyphs) = match psf::is_psf(&bytes) {
+                    true => psf::parse(&name, &bytes),
+                    false => bdf::parse(&name, &String::from_utf8_lossy(&bytes)),
+                }
+                .map_err(|d| d.with_file(path))?;
+
+                let font = Font::from_glyphs(name, size, glyphs, options.charset.as_ref())
+                    .map_err(|d| d.with_file(path))?;
+                (font, GLYPH_CLEAR)
+            }
+            _ => {
+                let strip = colour_management
+                    .apply(Image::load(path)?)
+                    .map_err(|d| d.with_file(path))?;
+                let glyph = options.glyph.unwrap_or(DEFAULT_GLYPH);
+                let charset = match &options.charset {
+                    Some(charset) => charset.clone(),
+                    None => Charset::default(),
+                };
+                let font = Font::slice(&strip, glyph, charset).map_err(|d| d.with_file(path))?;
+
+                // fonts drawn on an opaque background are assumed to start with a blank corner
+                let background = match strip.iter().any(|pixel| pixel.a == 0) {
+                    true => GLYPH_CLEAR,
+                    false => strip.pixel(0, 0),
+                };
+                (font, background)
+            }
+        };
+
+        font.codepoint_table = options.codepoint_table;
+        font.background = background;
+        font.text_renderer = options.text_renderer;
+        if let Some(proportional) = options.proportional {
+            font.measure(proportional, background, options.letter_spacing);
+        }
+
+        info!(
+            "loaded {} {} glyphs from {}",
+            font.glyphs.len(),
+            font.glyph,
+            path.display()
+        );
+
+        Ok(font)
+    }
+
+    /// Build a font from glyphs which are already separate, keeping those in
+    /// the charset in its order, or all of them in code order without one.
+    pub fn from_glyphs(
+        name: String,
+        glyph: Size,
+        mut glyphs: Vec<(char, Image)>,
+        charset: Option<&Charset>,
+    ) -> Result<Self, Diagnostic> {
+        let (charset, glyphs) = match charset {
+            Some(charset) => {
+                let images = charset
+                    .chars()
+                    .iter()
+                    .map(|c| {
+                        glyphs
+                            .iter()
+                            .find(|(code, _)| code == c)
+                            .map(|(_, image)| image.clone())
+                            .ok_or_else(|| {
+                                Diagnostic::new(
+                                    FailureClass::Usage,
+                                    format!("the font has no glyph for {:?}", c),
+                                )
+                                .with_suggestion("remove the character from --charset")
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                (charset.clone(), images)
+            }
+            None => {
+                // a character drawn by two glyphs keeps the first, as the console does
+                glyphs.sort_by_key(|(c, _)| *c);
+                glyphs.dedup_by_key(|(c, _)| *c);
+                let (chars, images) = glyphs.into_iter().unzip();
+                (Charset(chars), images)
+            }
+        };
+
+        if glyphs.is_empty() {
+            return Err(Diagnostic::new(
+                FailureClass::Decode,
+                "the font doesn't contain any encoded glyphs",
+            ));
+        }
+
+        Ok(Font {
+            name,
+            glyph,
+            charset,
+            glyphs,
+            widths: None,
+            codepoint_table: false,
+            background: GLYPH_CLEAR,
+            text_renderer: false,
+        })
+    }
+
+    /// Cut a font image into glyphs, reading left to right then top to bottom.
+    pub fn slice(strip: &Image, glyph: Size, charset: Charset) -> Result<Self, Diagnostic> {
+        if !strip.width().is_multiple_of(glyph.width)
+            || !strip.height().is_multiple_of(glyph.height)
+        {
+            return Err(Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "the {}x{} font isn't a whole number of {} glyphs",
+                    strip.width(),
+                    strip.height(),
+                    glyph
+                ),
+            )
+            .with_suggestion("check the --glyph size matches the font"));
+        }
+
+        let columns = strip.width() / glyph.width;
+        let rows = strip.height() / glyph.height;
+        let available = (columns * rows) as usize;
+        if available < charset.len() {
+            return Err(Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "the font has {} glyphs but the charset needs {}",
+                    available,
+                    charset.len()
+                ),
+            )
+            .with_suggestion("check the --charset matches the glyphs in the font"));
+        }
+
+        let glyphs = (0..)
+            .zip(charset.chars())
+            .map(|(i, c)| {
+                let x = (i % columns) * glyph.width;
+                let y = (i / columns) * glyph.height;
+                let name = format!("{}[{:?}]", strip.name(), c);
+                strip.crop(name, x, y, glyph.width, glyph.height)
+            })
+            .collect();
+
+        Ok(Font {
+            name: strip.name().to_owned(),
+            glyph,
+            charset,
+            glyphs,
+            widths: None,
+            codepoint_table: false,
+            background: GLYPH_CLEAR,
+            text_renderer: false,
+        })
+    }
+
+    /// Make the font proportional by finding the width of every glyph.
+    pub fn measure(&mut self, proportional: Proportional, background: Pixel, spacing: u32) {
+        let widths = self
+            .glyphs
+            .iter_mut()
+            .map(|glyph| match proportional {
+                Proportional::Trim => {
+                    let is_empty = |x| {
+                        (0..glyph.height()).all(|y| {
+                            let pixel = glyph.pixel(x, y);
+                            pixel.a == 0 || pixel == background
+                        })
+                    };
+
+                    (0..glyph.width())
+                        .rev()
+                        .find(|x| !is_empty(*x))
+                        .map_or(glyph.width().div_ceil(2), |x| x + 1)
+                }
+                Proportional::Separator(separator) => {
+                    let width = (0..glyph.width())
+                        .find(|x| (0..glyph.height()).all(|y| glyph.pixel(*x, y) == separator))
+                        .unwrap_or(glyph.width());
+
+                    // the separator isn't part of the glyph so shouldn't end up in the palette
+                    for y in 0..glyph.height() {
+                        for x in width..glyph.width() {
+                            glyph.set_pixel(x, y, Pixel::new(0, 0, 0, 0));
+                        }
+                    }
+
+                    width
+                }
+            })
+            .collect::<Vec<_>>();
+
+        debug!("glyph widths for {}: {:?}", self.name, widths);
+        self.widths = Some((widths, spacing));
+    }
+
+    #[inline]
+    pub fn glyphs(&self) -> &[Image] {
+        &self.glyphs
+    }
+
+    /// Write the font constants and the glyph data, one glyph after another.
+    pub(crate) fn to_asm(&self, encoding: &Encoding) -> Result<String> {
+        let mut buf = String::new();
+
+        let packed = self
+            .glyphs
+            .iter()
+            .map(|glyph| encoding.encode(glyph))
+            .collect::<Result<Vec<_>>>()?;
+
+        writeln!(buf, "FONT_GLYPH_WIDTH\tEQU {}", self.glyph.width)?;
+        writeln!(buf, "FONT_GLYPH_HEIGHT\tEQU {}", self.glyph.height)?;
+        writeln!(buf, "FONT_GLYPH_SIZE\tEQU {}", self.glyph_size(encoding))?;
+        writeln!(buf, "FONT_FIRST_CHAR\tEQU {}", self.charset.first() as u32)?;
+        writeln!(buf, "FONT_LAST_CHAR\tEQU {}", self.charset.last() as u32)?;
+        writeln!(buf, "FONT_GLYPH_COUNT\tEQU {}", self.charset.len())?;
+        if let Some((_, spacing)) = &self.widths {
+            writeln!(buf, "FONT_LETTER_SPACING\tEQU {}", spacing)?;
+        }
+        buf.push('\n');
+
+        writeln!(buf, "{}", self.label())?;
+        for (c, glyph) in self.charset.chars().iter().zip(packed.iter()) {
+            writeln!(buf, "; {:?}", c)?;
+            encoding.write_bytes(&mut buf, glyph)?;
+        }
+
+        if let Some((widths, spacing)) = &self.widths {
+            let advances: Vec<u32> = widths.iter().map(|width| width + spacing).collect();
+            self.write_byte_table(&mut buf, encoding, "FontWidthTable", widths)?;
+            self.write_byte_table(&mut buf, encoding, "FontAdvanceTable", &advances)?;
+        }
+
+        Ok(buf)
+    }
+
+    /// Write the tables which map characters to glyphs, which must be aligned.
+    ///
+    /// By default this is a table of each character's byte offset from the
+    /// start of the glyph data, indexed by the character minus
+    /// `FONT_FIRST_CHAR`, where characters missing from the font have an
+    /// offset of -1. Fonts with a codepoint table instead get a sorted table of
+    /// codepoint and glyph index pairs, plus a routine to search it.
+    /// The `draw_string` routine follows the tables when it's wanted.
+    pub(crate) fn tables_asm(&self, encoding: &Encoding) -> Result<String> {
+        let mut buf = String::new();
+
+        if self.codepoint_table {
+            let mut entries: Vec<(char, usize)> = self
+                .charset
+                .chars()
+                .iter()
+                .copied()
+                .enumerate()
+                .map(|(index, c)| (c, index))
+                .collect();
+            entries.sort_unstable();
+
+            writeln!(buf, "FontCodepointTable")?;
+            for (c, index) in entries {
+                let entry = encoding.words(&[c as u32, index as u32]);
+                writeln!(buf, "\t{}\t; {:?}", entry, c)?;
+            }
+            writeln!(buf, "\n{}", self.lookup_routine())?;
+        } else {
+            let glyph_size = self.glyph_size(encoding);
+
+            writeln!(buf, "FontOffsetTable")?;
+            for code in self.charset.first()..=self.charset.last() {
+                match self.charset.chars().iter().position(|c| *c == code) {
+                    Some(index) => {
+                        let offset = (index * glyph_size) as u32;
+                        writeln!(buf, "\t{}\t; {:?}", encoding.words(&[offset]), code)?
+                    }
+                    None => writeln!(buf, "\t{}\t; {:?}", encoding.words(&[u32::MAX]), code)?,
+                }
+            }
+        }
+
+        if self.text_renderer {
+            writeln!(buf, "\n{}", render::draw_string(self, encoding)?)?;
+        }
+
+        Ok(buf)
+    }
+
+    /// A binary search of `FontCodepointTable`, following the ARM procedure
+    /// call standard so it can be called from C as well as assembly.
+    fn lookup_routine(&self) -> String {
+        format!(
+            r"; font_lookup - find the glyph which draws a character
+;   in:  r0 = the character's codepoint
+;   out: r0 = the address of its glyph, or 0 if the font doesn't have one
+;        r1 = the glyph's index in the font
+;   corrupts r2, r3 and r12
+font_lookup
+	STMFD	sp!, {{r4, r5}}
+	LDR	r1, =FontCodepointTable
+	MOV	r2, #0				; the lowest entry still in range
+	LDR	r3, =FONT_GLYPH_COUNT		; one past the highest entry still in range
+font_lookup_loop
+	CMP	r2, r3
+	BHS	font_lookup_missing
+	ADD	r12, r2, r3
+	MOV	r12, r12, LSR #1		; the middle entry
+	ADD	r4, r1, r12, LSL #3		; entries are a codepoint word then an index word
+	LDR	r5, [r4]
+	CMP	r5, r0
+	ADDLO	r2, r12, #1			; too low, search above the middle
+	MOVHI	r3, r12				; too high, search below the middle
+	BNE	font_lookup_loop
+	LDR	r1, [r4, #4]
+	LDR	r2, =FONT_GLYPH_SIZE
+	MUL	r3, r1, r2
+	LDR	r0, ={label}
+	ADD	r0, r0, r3
+	LDMFD	sp!, {{r4, r5}}
+	MOV	pc, lr
+font_lookup_missing
+	MOV	r0, #0
+	LDMFD	sp!, {{r4, r5}}
+	MOV	pc, lr",
+            label = self.label()
+        )
+    }
+
+    /// Write a byte per character, indexed by the character minus
+    /// `FONT_FIRST_CHAR` where characters missing from the font are 0, or by
+    /// glyph index when the font has a codepoint table.
+    fn write_byte_table(
+        &self,
+        buf: &mut String,
+        encoding: &Encoding,
+        label: &str,
+        values: &[u32],
+    ) -> Result<()> {
+        let bytes: Vec<u8> = match self.codepoint_table {
+            true => values
+                .iter()
+                .map(|value| (*value).min(0xFF) as u8)
+                .collect(),
+            false => (self.charset.first()..=self.charset.last())
+                .map(|code| {
+                    self.charset
+                        .chars()
+                        .iter()
+                        .position(|c| *c == code)
+                        .map_or(0, |index| values[index].min(0xFF) as u8)
+                })
+                .collect(),
+        };
+
+        writeln!(buf, "\n{}", label)?;
+        encoding.write_bytes(buf, &bytes)
+    }
+
+    /// The number of bytes a packed glyph takes up.
+    fn glyph_size(&self, encoding: &Encoding) -> usize {
+        encoding.size(self.glyph.width, self.glyph.height)
+    }
+
+    pub(crate) fn label(&self) -> String {
+        format!("_{}", self.name)
+    }
+}
@@ -0,0 +1,160 @@
+//! PC Screen Font, the format of the Linux console fonts, in both versions.
+
+use super::{GLYPH_CLEAR, GLYPH_SET};
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::image::{Image, Size};
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02 | 0x04;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_SEQUENCE: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HAS_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_SEQUENCE: u8 = 0xFE;
+
+pub(super) fn is_psf(bytes: &[u8]) -> bool {
+    bytes.starts_with(&PSF1_MAGIC) || bytes.starts_with(&PSF2_MAGIC)
+}
+
+/// Decode every glyph, using the unicode table to find which characters each
+/// glyph draws. Without a table, glyph `n` is taken to be character `n`.
+pub(super) fn parse(name: &str, bytes: &[u8]) -> Result<(Size, Vec<(char, Image)>), Diagnostic> {
+    let truncated = || {
+        Diagnostic::new(FailureClass::Decode, "the PSF file is cut short")
+            .with_suggestion("the file may be truncated or corrupt")
+    };
+
+    let (size, count, glyph_bytes, header_size, table) = if bytes.starts_with(&PSF1_MAGIC) {
+        let mode = *bytes.get(2).ok_or_else(truncated)?;
+        let height = *bytes.get(3).ok_or_else(truncated)? as u32;
+        let count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        (
+            Size::new(8, height),
+            count,
+            height as usize,
+            4,
+            mode & PSF1_MODE_HAS_TABLE != 0,
+        )
+    } else if bytes.starts_with(&PSF2_MAGIC) {
+        let word = |i: usize| {
+            bytes
+                .get(i * 4..i * 4 + 4)
+                .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+                .ok_or_else(truncated)
+        };
+        let (header_size, flags, count) = (word(2)?, word(3)?, word(4)?);
+        let (glyph_bytes, height, width) = (word(5)?, word(6)?, word(7)?);
+        (
+            Size::new(width, height),
+            count as usize,
+            glyph_bytes as usize,
+            header_size as usize,
+            flags & PSF2_HAS_TABLE != 0,
+        )
+    } else {
+        return Err(
+            Diagnostic::new(FailureClass::Decode, "not a PSF file, the magic is wrong")
+                .with_suggestion("only version 1 and 2 PC Screen Fonts are supported"),
+        );
+    };
+
+    let row_bytes = size.width.div_ceil(8) as usize;
+    if size.width == 0 || size.height == 0 || glyph_bytes < row_bytes * size.height as usize {
+        return Err(Diagnostic::new(
+            FailureClass::Decode,
+            "the PSF glyph size doesn't match its dimensions",
+        )
+        .with_suggestion("the file may be corrupt"));
+    }
+
+    let glyphs_end = header_size + count * glyph_bytes;
+    let data = bytes.get(header_size..glyphs_end).ok_or_else(truncated)?;
+
+    let images: Vec<Image> = data
+        .chunks_exact(glyph_bytes)
+        .map(|glyph| {
+            let pixels = (0..size.height as usize)
+                .flat_map(|y| {
+                    (0..size.width as usize).map(move |x| {
+                        let byte = glyph.get(y * row_bytes + x / 8).copied().unwrap_or(0);
+                        match byte >> (7 - x % 8) & 1 {
+                            1 => GLYPH_SET,
+                            _ => GLYPH_CLEAR,
+                        }
+                    })
+                })
+                .collect();
+            Image::new(String::new(), size.width, size.height, pixels)
+        })
+        .collect();
+
+    let codepoints = if !table {
+        (0..count)
+            .map(|index| char::from_u32(index as u32).into_iter().collect())
+            .collect()
+    } else if bytes.starts_with(&PSF1_MAGIC) {
+        psf1_table(&bytes[glyphs_end..], count)
+    } else {
+        psf2_table(&bytes[glyphs_end..], count)
+    };
+
+    let glyphs = images
+        .into_iter()
+        .zip(codepoints)
+        .flat_map(|(image, chars): (Image, Vec<char>)| {
+            chars.into_iter().map(move |c| {
+                let name = format!("{}[{:?}]", name, c);
+                (c, image.clone().with_name(name))
+            })
+        })
+        .collect();
+
+    Ok((size, glyphs))
+}
+
+/// The characters each glyph draws, from a table of little endian UCS-2.
+/// Multi-character sequences can't be looked up by a single code so are skipped.
+fn psf1_table(table: &[u8], count: usize) -> Vec<Vec<char>> {
+    let mut values = table
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+
+    (0..count)
+        .map(|_| {
+            let mut chars = Vec::new();
+            let mut in_sequence = false;
+            for value in values.by_ref() {
+                match value {
+                    PSF1_SEPARATOR => break,
+                    PSF1_SEQUENCE => in_sequence = true,
+                    _ if in_sequence => {}
+                    _ => chars.extend(char::from_u32(value as u32)),
+                }
+            }
+            chars
+        })
+        .collect()
+}
+
+/// The characters each glyph draws, from a table of UTF-8.
+/// Multi-character sequences can't be looked up by a single code so are skipped.
+fn psf2_table(table: &[u8], count: usize) -> Vec<Vec<char>> {
+    let mut entries = table.split(|byte| *byte == PSF2_SEPARATOR);
+
+    (0..count)
+        .map(|_| {
+            let entry = entries.next().unwrap_or_default();
+            let singles = entry
+                .split(|byte| *byte == PSF2_SEQUENCE)
+                .next()
+                .unwrap_or_default();
+            String::from_utf8_lossy(singles)
+                .chars()
+                .filter(|c| *c != char::REPLACEMENT_CHARACTER)
+                .collect()
+        })
+        .collect()
+}
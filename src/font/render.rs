@@ -0,0 +1,151 @@
+//! A `draw_string` routine generated to match the layout of the font.
+
+use super::Font;
+use crate::encoding::Encoding;
+use anyhow::{anyhow, Result};
+use std::fmt::Write as FmtWrite;
+
+/// Write a routine which draws a string into a framebuffer packed the same
+/// way as the sprites, skipping the font's background pixels so text can be
+/// drawn over anything.
+pub(super) fn draw_string(font: &Font, encoding: &Encoding) -> Result<String> {
+    let mut buf = String::new();
+    // packing checks the pixels fit in bytes before the renderer is written
+    let pixels_per_byte = encoding
+        .pixels_per_byte
+        .ok_or_else(|| anyhow!("the text renderer needs pixels packed into bytes"))?;
+    let bits_per_colour = encoding.bits_per_colour;
+
+    // a background missing from the palette means there is nothing to skip
+    let clear_index = encoding
+        .index(&font.background)
+        .unwrap_or(1 << bits_per_colour);
+    let byte_shift = pixels_per_byte.trailing_zeros();
+    let mask = (1 << bits_per_colour) - 1;
+
+    // pixels per byte is always a power of two, so the byte is a shift away
+    let byte_of = |to: &str, from: &str| match byte_shift {
+        0 => format!("\tMOV\t{}, {}", to, from),
+        shift => format!("\tMOV\t{}, {}, LSR #{}", to, from, shift),
+    };
+
+    writeln!(buf, "FONT_CLEAR_INDEX\tEQU {}\n", clear_index)?;
+    writeln!(buf, "; draw_string - draw a string of 8-bit characters")?;
+    writeln!(
+        buf,
+        ";   in:  r0 = the address of the NUL terminated string"
+    )?;
+    writeln!(buf, ";        r1 = the address of the framebuffer")?;
+    writeln!(buf, ";        r2 = the width of the framebuffer in pixels")?;
+    writeln!(buf, ";        r3 = the pixel to draw from, y * width + x")?;
+    writeln!(
+        buf,
+        ";   out: r0 = the pixel after the last character drawn"
+    )?;
+    writeln!(buf, ";   corrupts r1-r3 and r12")?;
+    writeln!(buf, "draw_string")?;
+    writeln!(buf, "\tSTMFD\tsp!, {{r4-r11, lr}}")?;
+    writeln!(buf, "\tMOV\tr4, r0\t\t\t\t; the next character")?;
+    writeln!(buf, "\tMOV\tr5, r1\t\t\t\t; the framebuffer")?;
+    writeln!(buf, "\tMOV\tr6, r2\t\t\t\t; its width")?;
+    writeln!(buf, "\tMOV\tr7, r3\t\t\t\t; the pen position")?;
+
+    writeln!(buf, "draw_string_char")?;
+    writeln!(buf, "\tLDRB\tr0, [r4], #1")?;
+    writeln!(buf, "\tCMP\tr0, #0")?;
+    writeln!(buf, "\tBEQ\tdraw_string_done")?;
+
+    // find the glyph's address in r8 and its index into the width tables in r1
+    match font.codepoint_table {
+        true => {
+            writeln!(buf, "\tBL\tfont_lookup")?;
+            writeln!(buf, "\tMOVS\tr8, r0")?;
+            writeln!(buf, "\tBEQ\tdraw_string_char\t\t; the font can't draw it")?;
+        }
+        false => {
+            writeln!(buf, "\tLDR\tr2, =FONT_FIRST_CHAR")?;
+            writeln!(buf, "\tSUB\tr1, r0, r2")?;
+            writeln!(buf, "\tLDR\tr2, =FONT_LAST_CHAR - FONT_FIRST_CHAR")?;
+            writeln!(buf, "\tCMP\tr1, r2")?;
+            writeln!(buf, "\tBHI\tdraw_string_char\t\t; outside the font")?;
+            writeln!(buf, "\tLDR\tr2, =FontOffsetTable")?;
+            writeln!(buf, "\tLDR\tr8, [r2, r1, LSL #2]")?;
+            writeln!(buf, "\tCMN\tr8, #1")?;
+            writeln!(buf, "\tBEQ\tdraw_string_char\t\t; missing from the font")?;
+            writeln!(buf, "\tLDR\tr2, ={}", font.label())?;
+            writeln!(buf, "\tADD\tr8, r8, r2")?;
+        }
+    }
+
+    // r9 is the number of columns to draw and r10 how far to move the pen
+    match &font.widths {
+        Some(_) => {
+            writeln!(buf, "\tLDR\tr2, =FontWidthTable")?;
+            writeln!(buf, "\tLDRB\tr9, [r2, r1]")?;
+            writeln!(buf, "\tLDR\tr2, =FontAdvanceTable")?;
+            writeln!(buf, "\tLDRB\tr10, [r2, r1]")?;
+        }
+        None => {
+            writeln!(buf, "\tLDR\tr9, =FONT_GLYPH_WIDTH")?;
+            writeln!(buf, "\tMOV\tr10, r9")?;
+        }
+    }
+
+    // r11 is the row and lr the column being drawn
+    writeln!(buf, "\tMOV\tr11, #0")?;
+    writeln!(buf, "draw_string_row")?;
+    writeln!(buf, "\tLDR\tr0, =FONT_GLYPH_HEIGHT")?;
+    writeln!(buf, "\tCMP\tr11, r0")?;
+    writeln!(buf, "\tBHS\tdraw_string_advance")?;
+    writeln!(buf, "\tMOV\tlr, #0")?;
+    writeln!(buf, "draw_string_pixel")?;
+    writeln!(buf, "\tCMP\tlr, r9")?;
+    writeln!(buf, "\tBHS\tdraw_string_next_row")?;
+
+    // read the glyph's pixel into r1
+    writeln!(buf, "\tLDR\tr0, =FONT_GLYPH_WIDTH")?;
+    writeln!(
+        buf,
+        "\tMLA\tr0, r11, r0, lr\t\t\t; the pixel within the glyph"
+    )?;
+    writeln!(buf, "{}", byte_of("r1", "r0"))?;
+    writeln!(buf, "\tLDRB\tr1, [r8, r1]")?;
+    writeln!(buf, "\tAND\tr2, r0, #{}", pixels_per_byte - 1)?;
+    writeln!(buf, "\tMOV\tr3, #bits_per_colour")?;
+    writeln!(buf, "\tMUL\tr12, r2, r3")?;
+    writeln!(buf, "\tMOV\tr1, r1, LSR r12")?;
+    writeln!(buf, "\tAND\tr1, r1, #0x{:02X}", mask)?;
+    writeln!(buf, "\tCMP\tr1, #FONT_CLEAR_INDEX")?;
+    writeln!(buf, "\tBEQ\tdraw_string_next_pixel")?;
+
+    // and write it into the framebuffer
+    writeln!(buf, "\tMLA\tr0, r11, r6, r7")?;
+    writeln!(
+        buf,
+        "\tADD\tr0, r0, lr\t\t\t; the pixel within the framebuffer"
+    )?;
+    writeln!(buf, "{}", byte_of("r2", "r0"))?;
+    writeln!(buf, "\tAND\tr3, r0, #{}", pixels_per_byte - 1)?;
+    writeln!(buf, "\tMOV\tr12, #bits_per_colour")?;
+    writeln!(buf, "\tMUL\tr0, r3, r12")?;
+    writeln!(buf, "\tLDRB\tr3, [r5, r2]")?;
+    writeln!(buf, "\tMOV\tr12, #0x{:02X}", mask)?;
+    writeln!(buf, "\tBIC\tr3, r3, r12, LSL r0")?;
+    writeln!(buf, "\tORR\tr3, r3, r1, LSL r0")?;
+    writeln!(buf, "\tSTRB\tr3, [r5, r2]")?;
+
+    writeln!(buf, "draw_string_next_pixel")?;
+    writeln!(buf, "\tADD\tlr, lr, #1")?;
+    writeln!(buf, "\tB\tdraw_string_pixel")?;
+    writeln!(buf, "draw_string_next_row")?;
+    writeln!(buf, "\tADD\tr11, r11, #1")?;
+    writeln!(buf, "\tB\tdraw_string_row")?;
+    writeln!(buf, "draw_string_advance")?;
+    writeln!(buf, "\tADD\tr7, r7, r10")?;
+    writeln!(buf, "\tB\tdraw_string_char")?;
+    writeln!(buf, "draw_string_done")?;
+    writeln!(buf, "\tMOV\tr0, r7")?;
+    writeln!(buf, "\tLDMFD\tsp!, {{r4-r11, pc}}")?;
+
+    Ok(buf)
+}
@@ -0,0 +1,496 @@
+use crate::colour_space::{ChunkReader, ColourChunks};
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::palette::Palette;
+use anyhow::{anyhow, Result};
+use log::{debug, info};
+use memmap2::Mmap;
+use rgb::{ComponentBytes, FromSlice};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+
+pub type Pixel = rgb::RGBA<u8>;
+
+/// Files at least this big are memory mapped to decode them.
+const MAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    name: String,
+    width: u32,
+    height: u32,
+    pixels: Vec<Pixel>,
+    /// The palette of an indexed PNG, with the alpha of its `tRNS` chunk.
+    source_palette: Option<Vec<Pixel>>,
+    /// The chunks saying which colour space the pixels are in.
+    colour_chunks: ColourChunks,
+    /// The EXIF orientation the pixels are stored in, 1 being upright.
+    orientation: u16,
+}
+
+impl Image {
+    pub fn new(name: String, width: u32, height: u32, pixels: Vec<Pixel>) -> Self {
+        Self {
+            name,
+            width,
+            height,
+            pixels,
+            source_palette: None,
+            colour_chunks: ColourChunks::default(),
+            orientation: 1,
+        }
+    }
+
+    /// Decode the first frame of a PNG, naming the image after the file.
+    pub fn load(image_file: &Path) -> Result<Self> {
+        let start = Instant::now();
+
+        // get a handle to the file
+        let file = File::open(image_file).map_err(|e| {
+            Diagnostic::new(FailureClass::Input, format!("failed to open file: {}", e))
+                .with_file(image_file)
+                .with_suggestion("check that the path exists and is readable")
+        })?;
+
+        // large scans are mapped rather than read through a buffer, leaving the
+        // operating system to page them in as the decoder goes
+        let size = file.metadata().map_or(0, |metadata| metadata.len());
+        // SAFETY: the map is only read while decoding. Another process
+        // truncating the file at the same time can crash the read, the usual
+        // caveat of mapping files, which is accepted for inputs.
+        let map = match size >= MAP_THRESHOLD {
+            true => unsafe { Mmap::map(&file) }
+                .map_err(|e| debug!("failed to map {}: {}", image_file.display(), e))
+                .ok(),
+            false => None,
+        };
+        let image = match &map {
+            Some(map) => Image::decode(String::new(), &map[..]),
+            None => Image::decode(String::new(), file),
+        }
+        .map_err(|d| d.with_file(image_file))?;
+
+        // get the image name from the file name
+        let asset_name = image_file
+            .file_stem()
+            .ok_or_else(|| {
+                Diagnostic::new(
+                    FailureClass::Input,
+                    "couldn't parse a file name from the path",
+                )
+                .with_file(image_file)
+            })?
+            .to_str()
+            .ok_or_else(|| {
+                Diagnostic::new(
+                    FailureClass::Input,
+                    "the file name contains invalid unicode",
+                )
+                .with_file(image_file)
+                .with_suggestion("rename the file using only unicode characters")
+            })?
+            .to_owned();
+
+        info!(
+            "decoded {} ({}x{}) in {:?}",
+            image_file.display(),
+            image.width,
+            image.height,
+            start.elapsed()
+        );
+
+        Ok(image.with_name(asset_name))
+    }
+
+    /// Decode the first frame of a PNG from any source, such as bytes that
+    /// never touched the filesystem.
+    pub fn decode(name: String, source: impl Read) -> Result<Self, Diagnostic> {
+        // get a reader handle to the image data
+        let mut chunks = ChunkReader::new(source);
+        let decoder = png::Decoder::new(&mut chunks);
+        let (info, mut reader) = decoder.read_info().map_err(|e| {
+            Diagnostic::new(FailureClass::Decode, format!("not a valid PNG: {}", e))
+                .with_suggestion("re-export the sprite as a PNG")
+        })?;
+
+        // indexed images are expanded, to RGB when they have no transparency
+        let source = reader.info();
+        let source_palette = source
+            .palette
+            .as_ref()
+            .filter(|_| source.color_type == png::ColorType::Indexed)
+            .map(|palette| {
+                let trns = source.trns.as_deref().unwrap_or_default();
+                palette
+                    .chunks_exact(3)
+                    .enumerate()
+                    .map(|(index, c)| {
+                        Pixel::new(c[0], c[1], c[2], trns.get(index).copied().unwrap_or(0xFF))
+                    })
+                    .collect()
+            });
+
+        // the pixels are reinterpreted as RGB or RGBA below so nothing else will do
+        if !matches!(info.color_type, png::ColorType::RGBA | png::ColorType::RGB)
+            || info.bit_depth != png::BitDepth::Eight
+        {
+            return Err(Diagnostic::new(
+                FailureClass::Decode,
+                format!(
+                    "unsupported pixel format {:?} at {} bits per channel",
+                    info.color_type, info.bit_depth as u8
+                ),
+            )
+            .with_suggestion("re-export the sprite as 8-bit RGB or RGBA"));
+        }
+
+        // read in the first image frame
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf).map_err(|e| {
+            Diagnostic::new(
+                FailureClass::Decode,
+                format!("failed to read the image data: {}", e),
+            )
+            .with_suggestion("the file may be truncated or corrupt, try re-exporting it")
+        })?;
+
+        // any trailing partial pixel is dropped rather than trusted
+        let pixels = match info.color_type {
+            png::ColorType::RGB => buf.as_rgb().iter().map(|pixel| pixel.alpha(0xFF)).collect(),
+            _ => buf.as_rgba().to_vec(),
+        };
+
+        let (colour_chunks, orientation) = chunks.into_parts();
+        Ok(Image {
+            source_palette,
+            colour_chunks,
+            orientation: orientation.unwrap_or(1),
+            ..Image::new(name, info.width, info.height, pixels)
+        })
+    }
+
+    /// Encode the image as an 8-bit RGBA PNG.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let mut encoder = png::Encoder::new(&mut buf, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(self.pixels.as_bytes())?;
+        drop(writer);
+
+        Ok(buf)
+    }
+
+    pub fn with_name(self, name: String) -> Self {
+        Self { name, ..self }
+    }
+
+    /// The image with its pixels, and any source palette, converted to sRGB
+    /// from the colour space its PNG gave.
+    pub(crate) fn into_srgb(self) -> Result<Self, Diagnostic> {
+        let conversion = self.colour_chunks.conversion().map_err(|reason| {
+            Diagnostic::new(
+                FailureClass::Decode,
+                format!(
+                    "couldn't use the colour profile of {}: {}",
+                    self.name, reason
+                ),
+            )
+            .with_suggestion("re-export the sprite in sRGB, or pass --ignore-colour-profiles")
+        })?;
+        let conversion = match conversion {
+            Some(conversion) => conversion,
+            None => return Ok(self),
+        };
+
+        // sprites have few colours, so each is only converted once
+        let mut converted = HashMap::new();
+        let mut convert = |pixel: &Pixel| {
+            *converted
+                .entry(*pixel)
+                .or_insert_with(|| conversion.apply(*pixel))
+        };
+        let pixels = self.pixels.iter().map(&mut convert).collect();
+        let source_palette = self
+            .source_palette
+            .as_ref()
+            .map(|palette| palette.iter().map(&mut convert).collect());
+        debug!("converted {} to sRGB", self.name);
+
+        Ok(Self {
+            pixels,
+            source_palette,
+            colour_chunks: ColourChunks::default(),
+            ..self
+        })
+    }
+
+    /// The image turned upright by the orientation its EXIF data gave.
+    pub(crate) fn upright(self) -> Self {
+        let turned = match self.orientation {
+            2 => self.flip(true, false),
+            3 => self.flip(true, true),
+            4 => self.flip(false, true),
+            5 => self.transpose(),
+            6 => self.transpose().flip(true, false),
+            7 => self.transpose().flip(true, true),
+            8 => self.transpose().flip(false, true),
+            _ => return self,
+        };
+        debug!(
+            "turned {} upright from orientation {}",
+            self.name, self.orientation
+        );
+
+        Self {
+            source_palette: self.source_palette,
+            colour_chunks: self.colour_chunks,
+            ..turned
+        }
+    }
+
+    /// The palette the image was decoded from, if it was an indexed PNG.
+    pub fn source_palette(&self) -> Option<&[Pixel]> {
+        self.source_palette.as_deref()
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    #[inline]
+    pub fn pixel(&self, x: u32, y: u32) -> Pixel {
+        self.pixels[y as usize * self.width as usize + x as usize]
+    }
+
+    #[inline]
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: Pixel) {
+        self.pixels[y as usize * self.width as usize + x as usize] = pixel;
+    }
+
+    #[inline]
+    pub fn iter(&self) -> std::slice::Iter<'_, Pixel> {
+        self.pixels.iter()
+    }
+
+    /// Copy out a rectangle of this image as a new image.
+    pub fn crop(&self, name: String, x: u32, y: u32, width: u32, height: u32) -> Self {
+        let pixels = (y..y + height)
+            .flat_map(|row| {
+                let start = row as usize * self.width as usize + x as usize;
+                self.pixels[start..start + width as usize].iter().copied()
+            })
+            .collect();
+
+        Image::new(name, width, height, pixels)
+    }
+
+    /// Widen the image to `width` with the padding colour, putting `left`
+    /// columns of it before each row and the rest after.
+    pub(crate) fn pad(&self, left: u32, width: u32, padding: Pixel) -> Self {
+        let right = (width - left - self.width) as usize;
+        let pixels = self
+            .pixels
+            .chunks(self.width as usize)
+            .flat_map(|row| {
+                std::iter::repeat_n(padding, left as usize)
+                    .chain(row.iter().copied())
+                    .chain(std::iter::repeat_n(padding, right))
+            })
+            .collect();
+
+        Image::new(self.name.clone(), width, self.height, pixels)
+    }
+
+    /// Every other row of the image, starting from the top row or the one
+    /// below it, as a new image.
+    pub fn field(&self, odd: bool) -> Self {
+        let rows: Vec<u32> = (odd as u32..self.height).step_by(2).collect();
+        let pixels = rows
+            .iter()
+            .flat_map(|&row| {
+                let start = row as usize * self.width as usize;
+                self.pixels[start..start + self.width as usize]
+                    .iter()
+                    .copied()
+            })
+            .collect();
+
+        Image::new(self.name.clone(), self.width, rows.len() as u32, pixels)
+    }
+
+    /// Mirror the image left to right and/or top to bottom.
+    pub fn flip(&self, horizontal: bool, vertical: bool) -> Self {
+        let pixels = (0..self.height)
+            .flat_map(|y| {
+                let y = if vertical { self.height - 1 - y } else { y };
+                (0..self.width).map(move |x| {
+                    let x = if horizontal { self.width - 1 - x } else { x };
+                    (x, y)
+                })
+            })
+            .map(|(x, y)| self.pixel(x, y))
+            .collect();
+
+        Image::new(self.name.clone(), self.width, self.height, pixels)
+    }
+
+    /// The same image with every pixel passed through `f`.
+    pub fn map(&self, f: impl Fn(Pixel) -> Pixel) -> Self {
+        let pixels = self.pixels.iter().copied().map(f).collect();
+        Image::new(self.name.clone(), self.width, self.height, pixels)
+    }
+
+    /// The same image with every pixel fully opaque.
+    pub fn opaque(&self) -> Self {
+        self.map(|pixel| Pixel { a: 0xFF, ..pixel })
+    }
+
+    /// The edge of the opaque part of the image: the pixels at least half
+    /// opaque next to one which isn't, or next to the border, made opaque
+    /// with everything else made transparent.
+    pub fn outline(&self) -> Self {
+        let solid = |x: i64, y: i64| {
+            x >= 0
+                && y >= 0
+                && x < self.width as i64
+                && y < self.height as i64
+                && self.pixel(x as u32, y as u32).a >= 0x80
+        };
+        let pixels = (0..self.height as i64)
+            .flat_map(|y| (0..self.width as i64).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let edge = solid(x, y)
+                    && [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                        .iter()
+                        .any(|(dx, dy)| !solid(x + dx, y + dy));
+                Pixel {
+                    a: if edge { 0xFF } else { 0x00 },
+                    ..self.pixel(x as u32, y as u32)
+                }
+            })
+            .collect();
+
+        Image::new(self.name.clone(), self.width, self.height, pixels)
+    }
+
+    /// Swap the rows and columns of the image.
+    pub fn transpose(&self) -> Self {
+        let pixels = (0..self.width)
+            .flat_map(|x| (0..self.height).map(move |y| (x, y)))
+            .map(|(x, y)| self.pixel(x, y))
+            .collect();
+
+        Image::new(self.name.clone(), self.height, self.width, pixels)
+    }
+
+    /// Pack the pixels into bytes of palette indices, with the first pixel in
+    /// the least significant bits, or the most significant with `msb_first`.
+    /// The palette's first colour has the index `offset`.
+    pub(crate) fn pack(
+        &self,
+        palette: &Palette,
+        offset: usize,
+        pixels_per_byte: usize,
+        bits_per_colour: usize,
+        msb_first: bool,
+    ) -> Result<Vec<u8>> {
+        self.pixels
+            .chunks(pixels_per_byte)
+            .map(|chunk| {
+                // fold in a wider type as shifting a u8 by 8 overflows at 8 bits per colour
+                let byte = chunk
+                    .iter()
+                    .enumerate()
+                    .try_fold(0_u32, |acc, (i, pixel)| {
+                        let index = offset
+                            + palette
+                                .index(pixel)
+                                .ok_or_else(|| anyhow!("Palette doesn't contain this pixel."))?;
+                        let shift = match msb_first {
+                            true => 8 - (i + 1) * bits_per_colour,
+                            false => i * bits_per_colour,
+                        };
+                        Ok::<_, anyhow::Error>(acc | (index as u32) << shift)
+                    })?;
+                Ok(byte as u8)
+            })
+            .collect()
+    }
+
+    /// Write each pixel's palette index as a halfword, for palettes too big
+    /// to index with a byte.
+    pub(crate) fn pack_wide(
+        &self,
+        palette: &Palette,
+        offset: usize,
+        big_endian: bool,
+    ) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.pixels.len() * 2);
+        for pixel in self.pixels.iter() {
+            let index = offset
+                + palette
+                    .index(pixel)
+                    .ok_or_else(|| anyhow!("Palette doesn't contain this pixel."))?;
+            let index = index as u16;
+            bytes.extend(match big_endian {
+                true => index.to_be_bytes(),
+                false => index.to_le_bytes(),
+            });
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// A width and height in pixels, written `WxH` on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Size {
+    pub const fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once('x')
+            .ok_or_else(|| format!("expected WIDTHxHEIGHT, found '{}'", s))?;
+        let parse = |n: &str| match n.trim().parse::<u32>() {
+            Ok(0) => Err("sizes must be at least 1".to_owned()),
+            Ok(n) => Ok(n),
+            Err(e) => Err(format!("invalid size '{}': {}", n, e)),
+        };
+
+        Ok(Size::new(parse(width)?, parse(height)?))
+    }
+}
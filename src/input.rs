@@ -0,0 +1,183 @@
+//! Turning the paths given as inputs into the files to convert, scanning any
+//! directories among them.
+
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::{archive, aseprite, Options};
+use anyhow::{ensure, Result};
+use glob::Pattern;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The extensions of the image files which are converted.
+const EXTENSIONS: &[&str] = &["png"];
+
+/// Replace each directory among the inputs with the supported files inside
+/// it, recursively and sorted by path so the output doesn't depend on the
+/// order the file system lists them in, and download any URLs. Files named
+/// directly are kept as they are.
+pub(crate) fn expand<P: AsRef<Path>>(inputs: &[P], options: &Options) -> Result<Vec<PathBuf>> {
+    let exclude = options
+        .exclude
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern).map_err(|e| {
+                Diagnostic::new(
+                    FailureClass::Usage,
+                    format!("'{}' isn't a valid pattern: {}", pattern, e),
+                )
+                .with_suggestion("use * and ? as wildcards, and ** to match directories")
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // a name is for the assets of one file, which these hold many of
+    for path in options.names.keys() {
+        ensure!(
+            inputs.iter().any(|input| input.as_ref() == path),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("there is no input {} to name", path.display())
+            )
+            .with_suggestion("names are keyed by the path of the input as it was given")
+        );
+        ensure!(
+            !path.is_dir() && !archive::is_zip(path) && !path.to_str().is_some_and(is_url),
+            Diagnostic::new(
+                FailureClass::Usage,
+                "only a sprite file can be given a name, not a directory, archive or URL"
+            )
+            .with_file(path)
+            .with_suggestion("name the sprites inside it by giving them as inputs of their own")
+        );
+    }
+
+    let mut files = Vec::with_capacity(inputs.len());
+    for input in inputs.iter().map(AsRef::as_ref) {
+        if let Some(url) = input.to_str().filter(|input| is_url(input)) {
+            files.push(fetch(url)?);
+            continue;
+        }
+        match input.is_dir() {
+            true => scan(input, input, &exclude, options, &mut files)?,
+            false => files.push(input.to_owned()),
+        }
+    }
+
+    dedup(files, options)
+}
+
+fn is_url(input: &str) -> bool {
+    input.starts_with("https://") || input.starts_with("http://")
+}
+
+#[cfg(feature = "net")]
+fn fetch(url: &str) -> Result<PathBuf> {
+    crate::net::fetch(url)
+}
+
+#[cfg(not(feature = "net"))]
+fn fetch(url: &str) -> Result<PathBuf> {
+    Err(Diagnostic::new(
+        FailureClass::Usage,
+        "downloading inputs needs image2arm built with the net feature",
+    )
+    .with_file(url)
+    .with_suggestion("rebuild with --features net, or download the file first")
+    .into())
+}
+
+/// Drop files which were given more than once, whether by the same path,
+/// overlapping directories or symlinks, as they'd make duplicate labels.
+fn dedup(files: Vec<PathBuf>, options: &Options) -> Result<Vec<PathBuf>> {
+    let mut seen: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut unique = Vec::with_capacity(files.len());
+    for file in files {
+        // a missing file fails to load later with a better error
+        let canonical = file.canonicalize().unwrap_or_else(|_| file.clone());
+        match seen.get(&canonical) {
+            Some(first) => {
+                ensure!(
+                    !options.strict,
+                    Diagnostic::new(
+                        FailureClass::Usage,
+                        format!("this is the same file as {}", first.display())
+                    )
+                    .with_file(&file)
+                    .with_suggestion("pass each file once")
+                );
+                warn!(
+                    "skipping {}, which is the same file as {}",
+                    file.display(),
+                    first.display()
+                );
+            }
+            None => {
+                seen.insert(canonical, file.clone());
+                unique.push(file);
+            }
+        }
+    }
+
+    Ok(unique)
+}
+
+fn scan(
+    root: &Path,
+    dir: &Path,
+    exclude: &[Pattern],
+    options: &Options,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let error = |e: std::io::Error| {
+        Diagnostic::new(
+            FailureClass::Input,
+            format!("failed to read directory: {}", e),
+        )
+        .with_file(dir)
+        .with_suggestion("check that the directory exists and is readable")
+    };
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(error)?;
+    entries.sort();
+
+    for path in entries {
+        let hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'));
+        if hidden && !options.include_hidden {
+            continue;
+        }
+
+        // patterns match the path from the directory given as the input
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        if exclude.iter().any(|pattern| pattern.matches_path(relative)) {
+            debug!("excluded {}", path.display());
+            continue;
+        }
+
+        if path.is_dir() {
+            scan(root, &path, exclude, options, files)?;
+        } else if is_image(&path) || archive::is_zip(&path) {
+            files.push(path);
+        } else {
+            warn!("skipping {}, which isn't an image", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the file is an image which can be converted, going by its
+/// extension.
+pub(crate) fn is_image(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    aseprite::is_aseprite(path) || extension.is_some_and(|e| EXTENSIONS.contains(&e.as_str()))
+}
@@ -0,0 +1,1933 @@
+//! A compiler from a set of sprites to ARM assembly DEFB's where the sprites
+//! can share a global colour table.
+//!
+//! The binary is a thin wrapper around [`convert`], while [`build_assets`] is
+//! meant to be called from a Cargo build script.
+
+mod adjust;
+mod animation;
+mod archive;
+mod aseprite;
+mod cipher;
+mod colour_space;
+pub mod diagnostic;
+mod diff;
+mod encoding;
+mod exif;
+mod extract;
+pub mod ffi;
+mod fingerprint;
+mod font;
+mod image;
+mod input;
+mod lint;
+mod memory;
+mod merge;
+#[cfg(feature = "net")]
+mod net;
+mod nine_patch;
+mod output;
+mod palette;
+#[cfg(feature = "python")]
+mod python;
+mod region;
+mod strip;
+mod tilemap;
+mod timings;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use crate::adjust::Adjustment;
+pub use crate::animation::{Animation, Tick};
+pub use crate::cipher::Cipher;
+pub use crate::colour_space::ColourManagement;
+pub use crate::diagnostic::{Diagnostic, FailureClass};
+pub use crate::diff::diff;
+pub use crate::encoding::{
+    AlphaPlane, Directives, Endian, Fields, PixelFormat, RowAlign, RowOrder, Target,
+};
+pub use crate::extract::{extract, ExtractOptions};
+pub use crate::font::{Charset, Font, FontOptions, Proportional};
+pub use crate::image::{Image, Pixel, Size};
+pub use crate::lint::lint;
+pub use crate::memory::{estimate_memory, MemoryEstimate};
+pub use crate::nine_patch::NinePatch;
+pub use crate::output::{
+    Armasm, AssetTable, Backends, Binary, CHeader, Comment, Demo, Gas, Gdb, HexCase, Histogram,
+    IncludeDirective, Indent, Json, Kmd, LabelStyle, OutputBackend, OutputFile, Packed,
+    PackedAsset, PaletteOutput, RustModule, Span, Style, TableTerminator, Template,
+};
+pub use crate::palette::{parse_colour, Palette};
+pub use crate::region::Region;
+pub use crate::tilemap::{Tilemap, TilemapOptions};
+pub use crate::timings::Timings;
+
+use crate::encoding::Encoding;
+use crate::fingerprint::Fingerprint;
+use crate::merge::Existing;
+use crate::strip::Strips;
+use anyhow::{ensure, Result};
+use log::{debug, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+type ImageSet = Vec<Image>;
+type Label = String;
+
+/// Settings which control how the sprites are compiled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// The name of the file [`build_assets`] writes into the output directory.
+    pub file_name: String,
+    /// A font to slice into glyphs and emit alongside the sprites.
+    pub font: Option<FontOptions>,
+    /// A tile map and its tileset to emit as tiles and arrays of tile indices.
+    pub tilemap: Option<TilemapOptions>,
+    /// The unit of the animations' frame durations.
+    pub tick: Tick,
+    /// How each pixel is stored.
+    pub format: PixelFormat,
+    /// The byte order of pixels and tables wider than a byte.
+    pub endian: Endian,
+    /// Whether images are written from the top row down or the bottom row up.
+    pub row_order: RowOrder,
+    /// Start every row of an image on a byte or word boundary, for blitters
+    /// which address rows by themselves.
+    pub row_align: Option<RowAlign>,
+    /// Write a constant for where each row of an image starts, and a table
+    /// of them, so code can jump to a row without multiplying.
+    pub row_labels: bool,
+    /// Write each image as separate fields of its rows, for interlaced
+    /// displays, labelling where the odd rows start.
+    pub fields: Option<Fields>,
+    /// How many copies of each image to write moved right by 0, 1, 2...
+    /// pixels, for blitting packed pixels to any position without shifting.
+    pub preshift: u32,
+    /// Write each word of an image after a word masking out its transparent
+    /// pixels, for blitting with an AND and an OR.
+    pub masked: bool,
+    /// Write a list of the runs of transparent pixels to skip and opaque
+    /// pixels to draw along each row of an image.
+    pub spans: bool,
+    /// Write how many pixels of each asset use each palette index, to find
+    /// stray colours and the ones which can be cut.
+    pub histogram: bool,
+    /// Write a bit per pixel marking the edge of each image's opaque pixels,
+    /// to draw highlights without finding the edge on the target.
+    pub outlines: bool,
+    /// Write a routine for each image which draws it with a store per byte,
+    /// instead of its pixels.
+    pub compiled_sprites: bool,
+    /// Leave every frame but the first of each animation whose frames are
+    /// the same size and evenly spaced out of the asset table, writing the
+    /// bytes from one frame to the next for the animation instead.
+    pub frame_stride: bool,
+    /// Write a hash of each asset's packed data, so code can tell when an
+    /// asset changed between builds.
+    pub asset_hashes: bool,
+    /// Scramble each asset's data and alpha plane, writing the key alongside
+    /// them for the loader to unscramble them with.
+    pub cipher: Option<Cipher>,
+    /// Write the [`Conversion::fingerprint`] into the JSON output, which
+    /// has to be emitted.
+    pub json_fingerprint: bool,
+    /// Write a program alongside the assembly which draws every asset in a
+    /// grid on a framebuffer, to check them on the hardware.
+    pub demo: Option<Demo>,
+    /// Start every image on a word boundary.
+    pub align_assets: bool,
+    /// Write each image's alpha as a plane of its own, leaving the colours opaque.
+    pub alpha_plane: Option<AlphaPlane>,
+    /// How many darker and lighter copies of the palette to write.
+    pub shade_levels: u32,
+    /// Write every palette index as a halfword, which is done anyway when
+    /// the palette has more than 256 colours.
+    pub wide_indices: bool,
+    /// A file pinning colours to palette indices, a line like `#102030 -> 5` each.
+    pub index_map: Option<PathBuf>,
+    /// How many palette indices at the start belong to the system, which the
+    /// palette is numbered after, so its first colour has this index.
+    pub reserved_indices: usize,
+    /// A file recording the palette, whose colours keep their indices on the
+    /// next run with new colours added after them. It is written if missing.
+    pub palette_lock: Option<PathBuf>,
+    /// Fail if the palette differs from the lock in any way instead of
+    /// updating it, so new colours can't get in unnoticed.
+    pub palette_lock_check: bool,
+    /// Use the palette the inputs were saved with as it is, keeping every
+    /// pixel's index. The inputs must all be indexed PNGs with the same palette.
+    pub trust_source_palette: bool,
+    /// Whether PNGs saying which colour space their pixels are in are
+    /// converted to sRGB, so the same art saved by different tools matches.
+    pub colour_management: ColourManagement,
+    /// Turn PNGs upright by the orientation in their EXIF data, for photos
+    /// taken on their side.
+    pub respect_exif: bool,
+    /// Write the palette by itself, or everything but the palette.
+    pub palette_output: PaletteOutput,
+    /// A previous output to add the sprites to, keeping its assets and palette.
+    pub merge: Option<PathBuf>,
+    /// The names of the backends to write files with, each named after
+    /// `file_name`.
+    pub emit: Vec<String>,
+    /// A Handlebars template to render with the packed assets, alongside the
+    /// files `emit` asks for.
+    pub template: Option<PathBuf>,
+    /// Write each asset into a file of its own, included by the main file.
+    pub split: bool,
+    /// How the main file includes the parts of a split output.
+    pub include_directive: IncludeDirective,
+    /// Warn about any image using more unique colours than this, 0 never warns.
+    pub colour_warning: usize,
+    /// Fail if the asset table has more entries than this, for runtimes
+    /// which index it with a byte, 0 allows any number.
+    pub max_assets: usize,
+    /// Split any image whose data and alpha plane are more than this many
+    /// bytes into strips of whole rows, for loaders which copy an asset at a
+    /// time through a buffer of fixed size.
+    pub max_asset_size: Option<usize>,
+    /// Merge palette entries whose channels all differ by no more than this.
+    pub merge_tolerance: u8,
+    /// Make pixels whose alpha is within this of fully opaque fully opaque,
+    /// so colours differing only by a stray alpha like 254 share an entry.
+    pub opaque_tolerance: u8,
+    /// Whether the asset table holds addresses or offsets.
+    pub table: AssetTable,
+    /// The label offsets are from, rather than the start of the table.
+    pub table_base: Option<String>,
+    /// End the asset table with a zero word, or put its length in front of
+    /// it, for loaders which can't use `ASSET_MAX`.
+    pub table_terminator: Option<TableTerminator>,
+    /// Write a table of fixed size records after the address table, holding
+    /// each asset's pointer, size, width, height and bits per pixel.
+    pub records: bool,
+    /// The address the output is loaded at, which makes the asset table hold
+    /// absolute addresses worked out from it.
+    pub base: Option<u32>,
+    /// The keywords rows of bytes and words are written with, to suit the
+    /// assembler.
+    pub directives: Directives,
+    /// Write runs of at least this many copies of one byte, like a fully
+    /// transparent row, with the fill directive instead of rows of bytes.
+    /// The data assembles to the same bytes either way.
+    pub fill_runs: Option<usize>,
+    /// The hex case, indentation and label alignment of the assembly.
+    pub style: Style,
+    /// Start the assembly with the banner naming what the file holds.
+    pub banner: bool,
+    /// The case of the asset names in labels and constants.
+    pub label_style: Option<LabelStyle>,
+    /// The names of assets to leave out of the asset table and its constants,
+    /// whose data is only used through their labels.
+    pub private: BTreeSet<String>,
+    /// The order to draw assets in by name, from the back at the lowest,
+    /// written as a constant for each. Assets without one are at 0.
+    pub priorities: BTreeMap<String, i32>,
+    /// Also write a table of the assets sorted by their priorities, from the
+    /// back to the front.
+    pub priority_table: bool,
+    /// Glob patterns for files to skip when scanning a directory given as an
+    /// input, matched against the path from that directory.
+    pub exclude: Vec<String>,
+    /// Include files and directories starting with a `.` when scanning.
+    pub include_hidden: bool,
+    /// Make anything which would otherwise be skipped with a warning an error,
+    /// along with anything lossy: colours the format rounds, alpha it drops
+    /// or thresholds, colours merged by [`Options::merge_tolerance`] and
+    /// assets renamed to fit in a label.
+    pub strict: bool,
+    /// Skip inputs which fail to load instead of stopping, listing them in
+    /// [`Conversion::skipped`].
+    pub keep_going: bool,
+    /// The most inputs decoded at once, each on a thread of its own, which
+    /// bounds the memory the decoded images take at any one time.
+    pub jobs: usize,
+    /// Refuse to start a conversion estimated to need more bytes of memory
+    /// than this, see [`estimate_memory`].
+    pub max_mem: Option<u64>,
+    /// Colour corrections for assets by name, made before the palette is built.
+    pub adjustments: BTreeMap<String, Adjustment>,
+    /// Rectangles to cut out of assets by name, each of which becomes an
+    /// asset in the place of the one it's cut from.
+    pub regions: BTreeMap<String, Vec<Region>>,
+    /// Assets by name to cut into their corners, edges and centre, each of
+    /// which becomes an asset of its own, after the adjustments are made.
+    pub nine_patches: BTreeMap<String, NinePatch>,
+    /// Names for the assets of input files, used instead of the files' names,
+    /// keyed by the path of the input as it was given.
+    pub names: BTreeMap<PathBuf, String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            file_name: "assets.s".into(),
+            font: None,
+            tilemap: None,
+            tick: Tick::default(),
+            format: PixelFormat::default(),
+            endian: Endian::default(),
+            row_order: RowOrder::default(),
+            row_align: None,
+            row_labels: false,
+            fields: None,
+            preshift: 0,
+            masked: false,
+            spans: false,
+            histogram: false,
+            outlines: false,
+            compiled_sprites: false,
+            frame_stride: false,
+            asset_hashes: false,
+            cipher: None,
+            json_fingerprint: false,
+            demo: None,
+            align_assets: false,
+            alpha_plane: None,
+            shade_levels: 0,
+            wide_indices: false,
+            index_map: None,
+            reserved_indices: 0,
+            palette_lock: None,
+            palette_lock_check: false,
+            trust_source_palette: false,
+            colour_management: ColourManagement::default(),
+            respect_exif: false,
+            palette_output: PaletteOutput::default(),
+            merge: None,
+            emit: vec![Armasm.name().to_owned()],
+            template: None,
+            split: false,
+            include_directive: IncludeDirective::default(),
+            colour_warning: 16,
+            max_assets: 256,
+            max_asset_size: None,
+            merge_tolerance: 0,
+            opaque_tolerance: 0,
+            table: AssetTable::default(),
+            table_base: None,
+            table_terminator: None,
+            base: None,
+            records: false,
+            directives: Directives::default(),
+            fill_runs: None,
+            style: Style::default(),
+            banner: true,
+            label_style: None,
+            private: BTreeSet::new(),
+            priorities: BTreeMap::new(),
+            priority_table: false,
+            exclude: Vec::new(),
+            include_hidden: false,
+            strict: false,
+            keep_going: false,
+            jobs: 1,
+            max_mem: None,
+            adjustments: BTreeMap::new(),
+            regions: BTreeMap::new(),
+            nine_patches: BTreeMap::new(),
+            names: BTreeMap::new(),
+        }
+    }
+}
+
+/// Compile the images at the given paths into the text of an assembly file.
+///
+/// Directories are scanned for images, see [`Options::exclude`]. Aseprite
+/// files are split into a frame per asset, with their tags emitted
+/// as animations.
+pub fn convert<P: AsRef<Path>>(inputs: &[P], options: &Options) -> Result<String> {
+    let (images, animations) = load_inputs(inputs, options)?;
+    convert_animations(images, &animations, options)
+}
+
+/// Load the images at the given paths as [`convert`] does, warning about
+/// any which are skipped.
+fn load_inputs<P: AsRef<Path>>(
+    inputs: &[P],
+    options: &Options,
+) -> Result<(ImageSet, Vec<Animation>)> {
+    let mut skipped = Vec::new();
+    let loaded = load(
+        &input::expand(inputs, options)?,
+        options,
+        &mut Timings::default(),
+        &mut skipped,
+    )?;
+    warn_skipped(&skipped);
+    Ok(loaded)
+}
+
+/// Compile the images at the given paths into every file the options ask
+/// for with [`Options::emit`], in that order.
+pub fn convert_files<P: AsRef<Path>>(inputs: &[P], options: &Options) -> Result<Vec<OutputFile>> {
+    convert_files_with(inputs, options, &Backends::default())
+}
+
+/// Like [`convert_files`], but picking from backends which may include ones
+/// registered by the caller.
+pub fn convert_files_with<P: AsRef<Path>>(
+    inputs: &[P],
+    options: &Options,
+    backends: &Backends,
+) -> Result<Vec<OutputFile>> {
+    let conversion = convert_files_timed(inputs, options, backends, &mut Timings::default())?;
+    warn_skipped(&conversion.skipped);
+    write_palette_lock(options, conversion.palette_lock.as_deref())?;
+    Ok(conversion.files)
+}
+
+/// The files a conversion wrote, along with the inputs it skipped.
+#[derive(Debug)]
+pub struct Conversion {
+    pub files: Vec<OutputFile>,
+    /// Why each input skipped with [`Options::keep_going`] failed to load.
+    pub skipped: Vec<anyhow::Error>,
+    /// A hash of the version of image2arm, the options, the decoded inputs
+    /// and the files written, the same on every build of the same assets.
+    /// A JSON output holding it with [`Options::json_fingerprint`] is left
+    /// out, as it can't hold a hash of itself.
+    pub fingerprint: String,
+    /// Every file the output depends on, along with the directories scanned
+    /// for inputs, as [`build_assets`] returns them.
+    pub dependencies: Vec<PathBuf>,
+    /// What to write to [`Options::palette_lock`], which is left to the
+    /// caller so that a run only checking the outputs doesn't write it.
+    pub palette_lock: Option<String>,
+}
+
+/// Like [`convert_files_with`], recording how long each phase and asset
+/// takes into `timings` and returning the inputs which were skipped.
+pub fn convert_files_timed<P: AsRef<Path>>(
+    inputs: &[P],
+    options: &Options,
+    backends: &Backends,
+    timings: &mut Timings,
+) -> Result<Conversion> {
+    ensure!(
+        !options.json_fingerprint || options.emit.iter().any(|name| name == Json.name()),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "the fingerprint can only be written into the JSON output when it's emitted"
+        )
+        .with_suggestion("emit json as well")
+    );
+
+    let mut skipped = Vec::new();
+    let start = timings.start();
+    let expanded = input::expand(inputs, options)?;
+    let (images, animations) = load(&expanded, options, timings, &mut skipped)?;
+    timings.phase("decode", start);
+
+    // the decoded inputs cover archives and downloads as well as files
+    let mut fingerprint = Fingerprint::new(options);
+    for image in images.iter() {
+        fingerprint.image(image);
+    }
+
+    let mut packed = pack(images, animations.to_vec(), options, timings)?;
+
+    let start = timings.start();
+    let mut files = emit(&packed, backends)?;
+    let report = packed.file_name_with("json");
+    for file in files.iter() {
+        if !options.json_fingerprint || file.name != report {
+            fingerprint.file(file);
+        }
+    }
+    // the report is written again now there's a fingerprint to put in it
+    if options.json_fingerprint {
+        packed.fingerprint = Some(fingerprint.to_string());
+        for json in Json.emit(&packed)? {
+            if let Some(file) = files.iter_mut().find(|file| file.name == json.name) {
+                *file = json;
+            }
+        }
+    }
+    timings.phase("emit", start);
+
+    Ok(Conversion {
+        files,
+        skipped,
+        fingerprint: fingerprint.to_string(),
+        dependencies: dependencies(inputs, expanded, &packed),
+        palette_lock: packed.palette_lock,
+    })
+}
+
+fn load(
+    inputs: &[PathBuf],
+    options: &Options,
+    timings: &mut Timings,
+    skipped: &mut Vec<anyhow::Error>,
+) -> Result<(ImageSet, Vec<Animation>)> {
+    if let Some(max) = options.max_mem {
+        let estimate = memory::estimate(inputs, options);
+        debug!("estimated the memory needed as\n{}", estimate);
+        ensure!(
+            estimate.total() <= max,
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "the conversion needs about {} of memory, more than the {} allowed",
+                    memory::size(estimate.total()),
+                    memory::size(max)
+                )
+            )
+            .with_suggestion("raise --max-mem, lower --jobs or convert fewer inputs at once")
+        );
+    }
+
+    let decode = |input: &Path| {
+        let start = timings.start();
+        let name = options.names.get(input);
+        let loaded = if archive::is_zip(input) {
+            archive::load(input, options)
+        } else if aseprite::is_aseprite(input) {
+            aseprite::load(input, name.map(String::as_str))
+                .map(|sprite| (sprite.frames, sprite.animations))
+        } else {
+            Image::load(input).map(|image| match name {
+                Some(name) => (vec![image.with_name(name.clone())], Vec::new()),
+                None => (vec![image], Vec::new()),
+            })
+        };
+        (loaded, start.map(|start| start.elapsed()))
+    };
+
+    // workers take the next input until there are none left, and their
+    // results are put back in input order so the output doesn't change
+    let jobs = options.jobs.clamp(1, inputs.len().max(1));
+    let decoded: Vec<_> = match jobs {
+        1 => inputs.iter().map(|input| decode(input)).collect(),
+        _ => {
+            let next = AtomicUsize::new(0);
+            let mut decoded: Vec<_> = std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..jobs)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            let mut done = Vec::new();
+                            loop {
+                                let index = next.fetch_add(1, Ordering::Relaxed);
+                                match inputs.get(index) {
+                                    Some(input) => done.push((index, decode(input))),
+                                    None => return done,
+                                }
+                            }
+                        })
+                    })
+                    .collect();
+                workers
+                    .into_iter()
+                    .flat_map(|worker| {
+                        worker
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                    })
+                    .collect()
+            });
+            decoded.sort_by_key(|(index, _)| *index);
+            decoded.into_iter().map(|(_, decoded)| decoded).collect()
+        }
+    };
+
+    let mut images: ImageSet = Vec::with_capacity(inputs.len());
+    let mut animations = Vec::new();
+    for (input, (loaded, took)) in inputs.iter().zip(decoded) {
+        timings.asset_took("decode", &input.display().to_string(), took);
+        match loaded {
+            Ok((loaded, loaded_animations)) => {
+                images.extend(loaded);
+                animations.extend(loaded_animations);
+            }
+            // one damaged file shouldn't lose the rest of a big conversion
+            Err(e) if options.keep_going => {
+                debug!("skipping {}: {:#}", input.display(), e);
+                skipped.push(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok((images, animations))
+}
+
+/// Warn about skipped inputs where the caller has no way to be told.
+fn warn_skipped(skipped: &[anyhow::Error]) {
+    for e in skipped {
+        warn!("skipped an input which failed to load: {:#}", e);
+    }
+}
+
+/// Compile images which have already been decoded into the text of an
+/// assembly file.
+pub fn convert_images(images: Vec<Image>, options: &Options) -> Result<String> {
+    convert_animations(images, &[], options)
+}
+
+/// Compile decoded images along with animations made from their frames.
+pub fn convert_animations(
+    images: Vec<Image>,
+    animations: &[Animation],
+    options: &Options,
+) -> Result<String> {
+    // the parts of a split output would be lost
+    ensure!(
+        !options.split,
+        Diagnostic::new(FailureClass::Usage, "split output is more than one file")
+            .with_suggestion("use convert_files to get every file")
+    );
+
+    let packed = pack(
+        images,
+        animations.to_vec(),
+        options,
+        &mut Timings::default(),
+    )?;
+    write_palette_lock(options, packed.palette_lock.as_deref())?;
+    let mut files = Armasm.emit(&packed)?;
+    Ok(String::from_utf8(files.remove(0).contents)?)
+}
+
+/// Write the packed assets with each backend [`Options::emit`] names.
+fn emit(packed: &Packed, backends: &Backends) -> Result<Vec<OutputFile>> {
+    let mut chosen = Vec::new();
+    for name in packed.options.emit.iter() {
+        let backend = backends.get(name).ok_or_else(|| {
+            let known: Vec<&str> = backends.names().collect();
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("there is no output called {}", name),
+            )
+            .with_suggestion(format!("emit one of {}", known.join(", ")))
+        })?;
+        chosen.push(backend);
+    }
+
+    // a template is written alongside whatever else was asked for
+    let template = packed
+        .options
+        .template
+        .as_deref()
+        .map(Template::load)
+        .transpose()?;
+    if let Some(template) = &template {
+        chosen.push(template);
+    }
+    if packed.options.histogram {
+        chosen.push(&Histogram);
+    }
+
+    let mut files: Vec<OutputFile> = Vec::new();
+    for backend in chosen {
+        for file in backend.emit(packed)? {
+            ensure!(
+                !files.iter().any(|written| written.name == file.name),
+                Diagnostic::new(
+                    FailureClass::Usage,
+                    format!("two outputs both write {}", file.name)
+                )
+                .with_suggestion("emit one of them, or pick another file name")
+            );
+            files.push(file);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Build the palette and pack every asset, ready for the backends.
+fn pack(
+    images: Vec<Image>,
+    mut animations: Vec<Animation>,
+    options: &Options,
+    timings: &mut Timings,
+) -> Result<Packed> {
+    // everything before the pixels are encoded goes into building the palette
+    let start = timings.start();
+    // nothing looks at the colours before they're in sRGB
+    let images = images
+        .into_iter()
+        .map(|image| options.colour_management.apply(image))
+        .map(|image| match options.respect_exif {
+            true => image.map(Image::upright),
+            false => image,
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // taken before the adjustments, which make new images without it
+    let source_palette = match options.trust_source_palette {
+        true => Some(source_palette(&images, options)?),
+        false => None,
+    };
+    // everything else refers to the regions by name rather than their atlas
+    for name in options.regions.keys() {
+        ensure!(
+            images.iter().any(|image| image.name() == name),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("there is no asset called {} to cut regions from", name)
+            )
+            .with_suggestion("regions are cut from sprites named after their file name")
+        );
+    }
+    let images: Vec<Image> = images
+        .into_iter()
+        .map(|image| match options.regions.get(image.name()) {
+            Some(regions) => regions.iter().map(|region| region.cut(&image)).collect(),
+            None => Ok(vec![image]),
+        })
+        .collect::<Result<Vec<Vec<_>>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    for (name, adjustment) in options.adjustments.iter() {
+        ensure!(
+            images.iter().any(|image| image.name() == name),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("there is no asset called {} to adjust", name)
+            )
+            .with_suggestion(
+                "adjustments are named after the sprite's file name without the extension"
+            )
+        );
+        ensure!(
+            adjustment.gamma > 0.0,
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("the gamma for {} must be above 0", name)
+            )
+        );
+    }
+    // the assets in the file being merged into can be named as well
+    let existing = options
+        .merge
+        .as_deref()
+        .map(|path| Existing::load(path, &options.directives.byte))
+        .transpose()?;
+    let merged: BTreeSet<&str> = existing
+        .iter()
+        .flat_map(|existing| existing.assets.iter().map(|asset| asset.name.as_str()))
+        .collect();
+    for name in options.private.iter() {
+        ensure!(
+            images.iter().any(|image| image.name() == name) || merged.contains(name.as_str()),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("there is no asset called {} to make private", name)
+            )
+            .with_suggestion("private assets are named after the sprite's file name")
+        );
+    }
+    for name in options.nine_patches.keys() {
+        ensure!(
+            images.iter().any(|image| image.name() == name),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "there is no asset called {} to split into a nine-patch",
+                    name
+                )
+            )
+            .with_suggestion("nine-patches are named after the sprite's file name")
+        );
+        ensure!(
+            !options.private.contains(name),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("{} is a nine-patch so can't be private", name)
+            )
+            .with_suggestion("the constants for its insets refer to its regions in the table")
+        );
+    }
+    // an animation's constants refer to its frames' constants
+    for animation in animations.iter() {
+        let patch = animation
+            .frames()
+            .iter()
+            .find(|frame| options.nine_patches.contains_key(*frame));
+        ensure!(
+            patch.is_none(),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "{} is a frame of {} so can't be a nine-patch",
+                    patch.map_or("", String::as_str),
+                    animation.name()
+                )
+            )
+        );
+        let private = animation
+            .frames()
+            .iter()
+            .find(|frame| options.private.contains(*frame));
+        ensure!(
+            private.is_none(),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "{} is a frame of {} so can't be private",
+                    private.map_or("", String::as_str),
+                    animation.name()
+                )
+            )
+        );
+    }
+    let images: Vec<Image> = images
+        .into_iter()
+        .map(|image| match options.adjustments.get(image.name()) {
+            Some(adjustment) => adjustment.apply(&image),
+            None => image,
+        })
+        .map(|image| match options.opaque_tolerance {
+            0 => image,
+            tolerance => image.map(|pixel| match pixel.a >= 0xFF - tolerance {
+                true => Pixel { a: 0xFF, ..pixel },
+                false => pixel,
+            }),
+        })
+        .collect();
+    // the regions take the panel's place, keeping them together in the table
+    let images: Vec<Image> = images
+        .into_iter()
+        .map(|image| match options.nine_patches.get(image.name()) {
+            Some(patch) => patch.split(&image),
+            None => Ok(vec![image]),
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    // the regions and nine-patch pieces are drawn on their own so have their own priorities
+    for name in options.priorities.keys() {
+        ensure!(
+            images.iter().any(|image| image.name() == name) || merged.contains(name.as_str()),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("there is no asset called {} to give a priority", name)
+            )
+            .with_suggestion("priorities are given to assets by the names they're written with")
+        );
+        ensure!(
+            !options.private.contains(name),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("{} is private so can't have a priority", name)
+            )
+            .with_suggestion("private assets aren't in the table to be drawn from")
+        );
+    }
+
+    check_images(&images, options);
+    if options.strict {
+        check_exact(&images, options)?;
+    }
+
+    let font = options
+        .font
+        .as_ref()
+        .map(|font| Font::load(font, options.colour_management))
+        .transpose()?;
+    let tilemap = options
+        .tilemap
+        .as_ref()
+        .map(|tilemap| Tilemap::load(tilemap, options.colour_management))
+        .transpose()?;
+
+    // without this check an empty list gives a confusing divide-by-zero error
+    ensure!(
+        !images.is_empty() || font.is_some() || tilemap.is_some(),
+        Diagnostic::new(FailureClass::Usage, "No Images to process.")
+    );
+
+    /* Output format
+     * - Colour palette
+     * - I want to be able to access images by a label (which shouldn't just be a pointer to the actual pixels.
+     *   - so this implies a table from asset names to memory locations of images
+     * - num pixels per byte
+     * - number bits per colour
+     * - the actual images
+     */
+
+    let mut pins = match &options.index_map {
+        Some(path) => read_index_map(path, options)?,
+        None => Vec::new(),
+    };
+    let lock = options.palette_lock.as_ref().filter(|lock| lock.exists());
+    ensure!(
+        !options.palette_lock_check || lock.is_some(),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "there is no palette lock to check against"
+        )
+        .with_suggestion("write one by running with --palette-lock first")
+    );
+    let locked = lock.map(|lock| read_index_map(lock, options)).transpose()?;
+    if let (Some(lock), Some(locked)) = (lock, &locked) {
+        add_pins(&mut pins, locked.clone(), lock)?;
+    }
+    // every colour of the source palette stays at its index
+    if let Some(source_palette) = &source_palette {
+        pins = source_palette.iter().copied().zip(0..).collect();
+    }
+
+    // the existing assets were packed against its palette, so it mustn't move
+    if let (Some(existing), Some(path)) = (&existing, &options.merge) {
+        add_pins(
+            &mut pins,
+            existing.palette.iter().copied().zip(0..).collect(),
+            path,
+        )?;
+        for image in images.iter() {
+            ensure!(
+                !existing
+                    .assets
+                    .iter()
+                    .any(|asset| asset.name == image.name()),
+                Diagnostic::new(
+                    FailureClass::Usage,
+                    format!("{} is already in the file being merged into", image.name())
+                )
+                .with_file(path)
+                .with_suggestion("rename the sprite or regenerate the file without --merge")
+            );
+        }
+    }
+
+    // now iterate over all the pixels and collect the unique ones.
+    let glyphs = font.as_ref().map_or(&[][..], Font::glyphs);
+    let tiles = tilemap.as_ref().map_or(&[][..], Tilemap::tiles);
+    // with a separate alpha plane the sprites' colours are all opaque
+    let opaque: Vec<Image>;
+    let sprites = match options.alpha_plane {
+        Some(_) => {
+            opaque = images.iter().map(Image::opaque).collect();
+            &opaque
+        }
+        None => &images,
+    };
+    let palette = Palette::new_from_images(sprites.iter().chain(glyphs).chain(tiles))
+        .merge(
+            sprites.iter().chain(glyphs).chain(tiles),
+            options.merge_tolerance,
+        )
+        .pin(&pins);
+    if let Some(source_palette) = &source_palette {
+        let extra = palette
+            .colours()
+            .iter()
+            .find(|colour| !source_palette.contains(colour));
+        ensure!(
+            extra.is_none(),
+            Diagnostic::new(
+                FailureClass::Palette,
+                format!(
+                    "{} isn't in the source palette, so the indices can't be kept",
+                    extra.map_or_else(String::new, palette::hex)
+                )
+            )
+            .with_suggestion(
+                "leave out the adjustments and --alpha-plane, which change the colours"
+            )
+        );
+    }
+    let merged = palette
+        .merged()
+        .min_by_key(|(colour, _)| (colour.r, colour.g, colour.b, colour.a));
+    ensure!(
+        !options.strict || merged.is_none(),
+        Diagnostic::new(
+            FailureClass::Palette,
+            format!(
+                "{} would be merged into {}",
+                merged.map_or_else(String::new, |(colour, _)| palette::hex(colour)),
+                merged.map_or_else(String::new, |(_, kept)| palette::hex(kept))
+            )
+        )
+        .with_suggestion("lower --merge-tolerance, or drop --strict to merge them")
+    );
+    ensure!(
+        options.reserved_indices + palette.len() <= 1 << 16
+            || options.format != PixelFormat::Indexed,
+        Diagnostic::new(
+            FailureClass::Palette,
+            format!(
+                "the images use {} colours after {} reserved indices but at most 65536 fit in a halfword",
+                palette.len(),
+                options.reserved_indices
+            )
+        )
+        .with_suggestion(
+            "reduce the number of colours used across the sprites, or use a direct colour format"
+        )
+    );
+    ensure!(
+        !options.font.as_ref().is_some_and(|font| font.text_renderer)
+            || (options.format == PixelFormat::Indexed
+                && options.endian == Endian::Little
+                && Encoding::new(&palette, options).pixels_per_byte.is_some()),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "the text renderer only draws little endian indexed pixels packed into bytes"
+        )
+        .with_suggestion(
+            "leave out --emit-text-renderer, or use little endian indexed pixels with at most 256 colours"
+        )
+    );
+    ensure!(
+        options.base.is_none() || options.table == AssetTable::Addresses,
+        Diagnostic::new(
+            FailureClass::Usage,
+            "a base address makes the table hold absolute addresses, not offsets"
+        )
+        .with_suggestion("leave out either --base or --table offsets")
+    );
+    ensure!(
+        options.shade_levels == 0 || options.format == PixelFormat::Indexed,
+        Diagnostic::new(
+            FailureClass::Usage,
+            "shade levels are copies of the palette, which only the indexed format has"
+        )
+        .with_suggestion("leave out --shade-levels or use the indexed format")
+    );
+    ensure!(
+        options.cipher.is_none() || !options.compiled_sprites,
+        Diagnostic::new(
+            FailureClass::Usage,
+            "compiled sprites are routines, which can't run scrambled"
+        )
+        .with_suggestion("leave out either --xor-key or --compiled-sprites")
+    );
+    ensure!(
+        !options.frame_stride || !options.compiled_sprites,
+        Diagnostic::new(
+            FailureClass::Usage,
+            "compiled sprites are routines of different lengths, so have no frame stride"
+        )
+        .with_suggestion("leave out either --frame-stride or --compiled-sprites")
+    );
+    ensure!(
+        options.reserved_indices == 0 || options.format == PixelFormat::Indexed,
+        Diagnostic::new(
+            FailureClass::Usage,
+            "reserved indices come before the palette, which only the indexed format has"
+        )
+        .with_suggestion("leave out --reserve-indices or use the indexed format")
+    );
+    ensure!(
+        !options.histogram || options.format == PixelFormat::Indexed,
+        Diagnostic::new(
+            FailureClass::Usage,
+            "a histogram counts the palette indices, which only the indexed format has"
+        )
+        .with_suggestion("leave out --histogram or use the indexed format")
+    );
+    ensure!(
+        options.palette_output != PaletteOutput::Only || options.format == PixelFormat::Indexed,
+        Diagnostic::new(
+            FailureClass::Usage,
+            "direct colour formats have no palette to write"
+        )
+        .with_suggestion("leave out --palette-only or use the indexed format")
+    );
+    ensure!(
+        options.palette_output != PaletteOutput::Only || options.demo.is_none(),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "the demo draws the assets, which a palette file leaves out"
+        )
+        .with_suggestion("emit the demo alongside the data file instead")
+    );
+
+    if let (Some(existing), Some(path)) = (&existing, &options.merge) {
+        let encoding = Encoding::new(&palette, options);
+        ensure!(
+            options.reserved_indices == existing.palette_offset,
+            Diagnostic::new(
+                FailureClass::Palette,
+                format!(
+                    "the file being merged into has {} reserved indices but {} were asked for",
+                    existing.palette_offset, options.reserved_indices
+                )
+            )
+            .with_file(path)
+            .with_suggestion("pass the same --reserve-indices the file was written with")
+        );
+        ensure!(
+            options.cipher == existing.cipher,
+            Diagnostic::new(
+                FailureClass::Usage,
+                "the file being merged into is scrambled with another key"
+            )
+            .with_file(path)
+            .with_suggestion("pass the same --xor-key the file was written with")
+        );
+        ensure!(
+            options.format == PixelFormat::Indexed
+                && encoding.bits_per_colour == existing.bits_per_colour,
+            Diagnostic::new(
+                FailureClass::Palette,
+                format!(
+                    "the file being merged into has {} bits per colour but the new sprites need {}",
+                    existing.bits_per_colour, encoding.bits_per_colour
+                )
+            )
+            .with_file(path)
+            .with_suggestion("regenerate the file from every sprite without --merge")
+        );
+    }
+
+    // the lock is left for the caller to write, so checking doesn't
+    let palette_lock = match (lock, &locked, options.palette_lock_check) {
+        (Some(lock), Some(locked), true) => {
+            check_lock(&palette, locked, lock)?;
+            None
+        }
+        _ => options.palette_lock.as_ref().map(|_| palette.to_lock()),
+    };
+
+    // the assets being merged into keep their place at the front of the table
+    let encoding = Encoding::new(&palette, options);
+    let mut assets = existing.map_or_else(Vec::new, |existing| existing.assets);
+    for asset in assets.iter_mut() {
+        asset.private |= options.private.contains(&asset.name);
+        if let Some(&priority) = options.priorities.get(&asset.name) {
+            asset.priority = Some(priority);
+        }
+    }
+    let mut strips = Vec::new();
+    timings.phase("quantize", start);
+
+    let packing = timings.start();
+    let pixels_per_byte = encoding
+        .pixels_per_byte
+        .filter(|_| options.format == PixelFormat::Indexed);
+    ensure!(
+        options.preshift == 0 || pixels_per_byte.is_some_and(|pixels| pixels > 1),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "only pixels packed several to a byte can be preshifted"
+        )
+        .with_suggestion("leave out --preshift, or use the indexed format with at most 16 colours")
+    );
+    ensure!(
+        options.preshift as usize <= pixels_per_byte.unwrap_or_default(),
+        Diagnostic::new(
+            FailureClass::Usage,
+            format!(
+                "{} copies are more than the {} pixels in a byte",
+                options.preshift,
+                pixels_per_byte.unwrap_or_default()
+            )
+        )
+        .with_suggestion("shifting by a whole byte is a matter of starting a byte later")
+    );
+    ensure!(
+        !options.masked || options.alpha_plane.is_none(),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "masked sprites hold their transparency in the masks, not an alpha plane"
+        )
+        .with_suggestion("leave out --alpha-plane or --masked")
+    );
+    ensure!(
+        !options.masked || options.row_align != Some(RowAlign::Byte),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "the rows of masked sprites are made of word pairs, so can't start on any byte"
+        )
+        .with_suggestion("use --row-align word")
+    );
+    ensure!(
+        !options.compiled_sprites
+            || !options.masked && options.alpha_plane.is_none() && options.fields.is_none(),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "compiled sprites draw their pixels directly, so can't be masked, split into fields or have an alpha plane"
+        )
+        .with_suggestion("leave out --compiled-sprites, or the other layout options")
+    );
+    ensure!(
+        !options.compiled_sprites || options.base.is_none(),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "compiled sprites have no known size to work out addresses with"
+        )
+        .with_suggestion("leave out --base, and let the assembler place them")
+    );
+    ensure!(
+        options.demo.is_none() || !options.masked && options.fields.is_none(),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "the demo copies assets a row at a time, so can't draw masked sprites or fields"
+        )
+        .with_suggestion("leave out --emit-demo, or --masked and --fields")
+    );
+    // masked rows only start on a pair when every row is whole words
+    let (whole_rows, problem, fix) = match options.masked {
+        true => (
+            options.row_align == Some(RowAlign::Word),
+            "don't all start on a mask word",
+            "add --row-align word",
+        ),
+        false => (true, "share bytes", "add --row-align byte"),
+    };
+    for image in images.iter() {
+        ensure!(
+            !options.row_labels || whole_rows && encoding.row_bytes(image.width()).is_some(),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "the rows of {} {}, so they can't have labels",
+                    image.name(),
+                    problem
+                )
+            )
+            .with_suggestion(fix)
+        );
+        ensure!(
+            !options.spans || image.width() <= u16::MAX as u32,
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "{} is too wide for its spans to fit in halfwords",
+                    image.name()
+                )
+            )
+            .with_suggestion("leave out --spans, or split the image up")
+        );
+        // Instant isn't available on wasm32, where no logger is ever installed
+        let debug = log::log_enabled!(log::Level::Debug);
+        let start = match debug {
+            true => Some(Instant::now()),
+            false => timings.start(),
+        };
+
+        let asset = pack_image(image, &encoding, options)?;
+        if let Some(start) = start.filter(|_| debug) {
+            debug!("packed {} in {:?}", asset.label, start.elapsed());
+        }
+        timings.asset("pack", image.name(), start);
+        match options.max_asset_size {
+            Some(max) if size(&asset) > max => {
+                // the frames and regions are looked up by the image's name
+                let whole = animations
+                    .iter()
+                    .any(|animation| animation.frames().iter().any(|frame| frame == image.name()))
+                    || options
+                        .nine_patches
+                        .keys()
+                        .any(|name| NinePatch::regions(name).any(|region| region == image.name()));
+                ensure!(
+                    !whole,
+                    Diagnostic::new(
+                        FailureClass::Usage,
+                        format!(
+                            "{} is {} bytes, more than --max-asset-size, but it's needed whole by an animation or nine-patch",
+                            image.name(),
+                            size(&asset)
+                        )
+                    )
+                    .with_suggestion("raise --max-asset-size, or make the image smaller")
+                );
+                let (split, parts) = split_strips(image, &asset, &encoding, options, max)?;
+                debug!("split {} into {} strips", image.name(), split.count);
+                assets.extend(parts);
+                strips.push(split);
+            }
+            _ => assets.push(asset),
+        }
+
+        // the copies are only reached through their own labels
+        for shift in 0..options.preshift {
+            let shifted = preshifted(image, shift, &encoding, options);
+            assets.push(PackedAsset {
+                private: true,
+                ..pack_image(&shifted, &encoding, options)?
+            });
+        }
+    }
+
+    // a case change can give two names the same label
+    let mut labels: HashMap<&str, &str> = HashMap::new();
+    for asset in assets.iter() {
+        if let Some(other) = labels.insert(&asset.label, &asset.name) {
+            return Err(Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "{} and {} both have the label {}",
+                    other, asset.name, asset.label
+                ),
+            )
+            .with_suggestion("rename one of them, or pick another --label-style")
+            .into());
+        }
+    }
+
+    // numbered sprites are the frames of animations too
+    let runs =
+        Animation::numbered_runs(assets.iter().map(|asset| asset.name.as_str()), &animations);
+    animations.extend(runs);
+    timings.phase("pack", packing);
+
+    let mut packed = Packed {
+        // rows, mask pairs and routines can only start on words if their
+        // images do
+        options: Options {
+            align_assets: options.align_assets
+                || options.row_align == Some(RowAlign::Word)
+                || options.compiled_sprites
+                || options.masked,
+            ..options.clone()
+        },
+        palette,
+        font,
+        tilemap,
+        assets,
+        palette_lock,
+        fingerprint: None,
+        animations,
+        strips,
+    };
+    if options.frame_stride {
+        stride_frames(&mut packed);
+    }
+
+    // a runtime indexing the table with a byte would wrap around past the end
+    let mut table: Vec<&str> = packed
+        .assets
+        .iter()
+        .filter(|asset| !asset.private)
+        .map(|asset| asset.name.as_str())
+        .collect();
+    if options.max_assets > 0 && table.len() > options.max_assets {
+        table.sort_unstable();
+        return Err(Diagnostic::new(
+            FailureClass::Usage,
+            format!(
+                "the asset table has {} entries, more than the {} allowed: {}",
+                table.len(),
+                options.max_assets,
+                table.join(", ")
+            ),
+        )
+        .with_suggestion(
+            "raise --max-assets if the runtime can index them all, make some assets --private or split the sprites across outputs",
+        )
+        .into());
+    }
+
+    Ok(packed)
+}
+
+/// Leave every frame but the first of each animation with evenly spaced
+/// frames out of the table, giving the animation the bytes from one frame
+/// to the next instead. A frame stays in the table if an animation which
+/// isn't evenly spaced has it, or one starts with it.
+fn stride_frames(packed: &mut Packed) {
+    let addresses = packed.addresses(0);
+    let index = |name: &str| packed.assets.iter().position(|asset| asset.name == name);
+    let mut strides = Vec::with_capacity(packed.animations.len());
+    for animation in packed.animations.iter() {
+        let frames: Option<Vec<usize>> = animation.frames().iter().map(|f| index(f)).collect();
+        let stride = frames.and_then(|frames| {
+            let first = &packed.assets[*frames.first()?];
+            let size = first.width.zip(first.height)?;
+            let same = frames.iter().all(|&frame| {
+                let asset = &packed.assets[frame];
+                asset.width.zip(asset.height) == Some(size)
+            });
+            let steps: Vec<u32> = frames
+                .windows(2)
+                .map(|pair| addresses[pair[1]].wrapping_sub(addresses[pair[0]]))
+                .collect();
+            match steps.first() {
+                Some(&step) if same && step > 0 && steps.iter().all(|&s| s == step) => {
+                    Some(step as usize)
+                }
+                _ => None,
+            }
+        });
+        if stride.is_none() && animation.frames().len() > 1 {
+            warn!(
+                "the frames of {} aren't the same size and evenly spaced, so they keep their table entries",
+                animation.name()
+            );
+        }
+        strides.push(stride);
+    }
+
+    let hidden: HashSet<String> = packed
+        .animations
+        .iter()
+        .zip(strides.iter())
+        .filter(|(_, stride)| stride.is_some())
+        .flat_map(|(animation, _)| animation.frames().iter().skip(1).cloned())
+        .filter(|frame| {
+            packed
+                .animations
+                .iter()
+                .zip(strides.iter())
+                .all(|(animation, stride)| {
+                    !animation.frames().contains(frame)
+                        || (stride.is_some() && animation.frames().first() != Some(frame))
+                })
+        })
+        .collect();
+    for asset in packed.assets.iter_mut() {
+        asset.private |= hidden.contains(&asset.name);
+    }
+    for (animation, stride) in packed.animations.iter_mut().zip(strides) {
+        animation.frame_stride = stride;
+    }
+}
+
+/// Add pinned colours from another source, which must agree with the ones
+/// already pinned.
+fn add_pins(pins: &mut Vec<(Pixel, usize)>, extra: Vec<(Pixel, usize)>, path: &Path) -> Result<()> {
+    for (colour, index) in extra {
+        if pins.contains(&(colour, index)) {
+            continue;
+        }
+        ensure!(
+            !pins.iter().any(|(c, i)| *c == colour || *i == index),
+            Diagnostic::new(
+                FailureClass::Palette,
+                format!(
+                    "this needs palette entry {} kept where it is, but it has been moved",
+                    index
+                )
+            )
+            .with_file(path)
+            .with_suggestion("check the index map, palette lock and merged file agree")
+        );
+        pins.push((colour, index));
+    }
+
+    Ok(())
+}
+
+/// The palette every image was saved with, for
+/// [`Options::trust_source_palette`], which has to be the same for all of
+/// them and have no colour twice.
+fn source_palette(images: &[Image], options: &Options) -> Result<Vec<Pixel>> {
+    ensure!(
+        options.format == PixelFormat::Indexed,
+        Diagnostic::new(
+            FailureClass::Usage,
+            "direct colour formats have no palette to keep"
+        )
+        .with_suggestion("leave out --trust-source-palette or use the indexed format")
+    );
+    ensure!(
+        options.index_map.is_none() && options.palette_lock.is_none() && options.merge.is_none(),
+        Diagnostic::new(
+            FailureClass::Usage,
+            "the source palette already fixes the index of every colour"
+        )
+        .with_suggestion("leave out --index-map, --palette-lock and --merge")
+    );
+
+    let mut source: Option<(&Image, &[Pixel])> = None;
+    for image in images {
+        let palette = image.source_palette().ok_or_else(|| {
+            Diagnostic::new(
+                FailureClass::Input,
+                format!("{} isn't an indexed PNG, so has no palette", image.name()),
+            )
+            .with_suggestion(
+                "save every sprite as an indexed PNG, or leave out --trust-source-palette",
+            )
+        })?;
+        match source {
+            Some((first, first_palette)) => {
+                let entry = first_palette
+                    .iter()
+                    .zip(palette)
+                    .position(|(a, b)| a != b)
+                    .unwrap_or_else(|| first_palette.len().min(palette.len()));
+                ensure!(
+                    palette == first_palette,
+                    Diagnostic::new(
+                        FailureClass::Palette,
+                        format!(
+                            "the palettes of {} and {} differ from entry {}",
+                            first.name(),
+                            image.name(),
+                            entry
+                        )
+                    )
+                    .with_suggestion("save every sprite with the same palette")
+                );
+            }
+            None => source = Some((image, palette)),
+        }
+    }
+    let (_, palette) = source.ok_or_else(|| {
+        Diagnostic::new(
+            FailureClass::Usage,
+            "there are no sprites to take a palette from",
+        )
+    })?;
+
+    // pixels are decoded to colours, so two entries alike can't be told apart
+    for (index, colour) in palette.iter().enumerate() {
+        if let Some(earlier) = palette[..index].iter().position(|c| c == colour) {
+            return Err(Diagnostic::new(
+                FailureClass::Palette,
+                format!(
+                    "entries {} and {} of the source palette are both {}",
+                    earlier,
+                    index,
+                    palette::hex(colour)
+                ),
+            )
+            .with_suggestion("make every entry of the palette a different colour")
+            .into());
+        }
+    }
+
+    Ok(palette.to_vec())
+}
+
+/// Fail if the palette isn't exactly the one in the lock, naming the first
+/// locked colour which moved or the colours missing from the lock, for
+/// [`Options::palette_lock_check`].
+fn check_lock(palette: &Palette, locked: &[(Pixel, usize)], lock: &Path) -> Result<()> {
+    for (colour, index) in locked.iter() {
+        ensure!(
+            palette.colours().get(*index) == Some(colour),
+            Diagnostic::new(
+                FailureClass::Palette,
+                format!(
+                    "{} is locked to palette entry {}, but the sprites need it moved",
+                    palette::hex(colour),
+                    index
+                )
+            )
+            .with_file(lock)
+            .with_suggestion("check the index map and merged file agree with the lock")
+        );
+    }
+
+    let new: Vec<String> = palette
+        .colours()
+        .iter()
+        .filter(|colour| !locked.iter().any(|(locked, _)| locked == *colour))
+        .map(palette::hex)
+        .collect();
+    ensure!(
+        new.is_empty(),
+        Diagnostic::new(
+            FailureClass::Palette,
+            format!(
+                "{} colours aren't in the palette lock: {}",
+                new.len(),
+                new.join(", ")
+            )
+        )
+        .with_file(lock)
+        .with_suggestion("run without --palette-lock-check to add them to the lock, and commit it")
+    );
+
+    Ok(())
+}
+
+/// Write the palette lock a conversion made to [`Options::palette_lock`], if
+/// it made one, as [`convert_files`] does.
+pub fn write_palette_lock(options: &Options, contents: Option<&str>) -> Result<()> {
+    if let (Some(lock), Some(contents)) = (&options.palette_lock, contents) {
+        std::fs::write(lock, contents).map_err(|e| {
+            Diagnostic::new(
+                FailureClass::Output,
+                format!("failed to write palette lock: {}", e),
+            )
+            .with_file(lock)
+        })?;
+    }
+    Ok(())
+}
+
+/// Read a file of pinned colours, which is either an index map or a lock.
+fn read_index_map(path: &Path, options: &Options) -> Result<Vec<(Pixel, usize)>> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        Diagnostic::new(
+            FailureClass::Input,
+            format!("failed to read index map: {}", e),
+        )
+        .with_file(path)
+        .with_suggestion("check that the path exists and is readable")
+    })?;
+
+    // the reserved indices come before the palette in a halfword
+    let indices = (1 << 16) - options.reserved_indices.min(1 << 16);
+    let pins = palette::parse_index_map(&text, indices).map_err(|e| e.with_file(path))?;
+
+    Ok(pins)
+}
+
+/// Warn about images which will grow the palette more than the artist likely
+/// meant, so the file can be fixed before the palette silently fills up.
+fn check_images(images: &[Image], options: &Options) {
+    let keeps_alpha = matches!(
+        options.format,
+        PixelFormat::Argb4444 | PixelFormat::Argb8888
+    ) || options.alpha_plane.is_some();
+
+    for image in images.iter() {
+        let unique: HashSet<&Pixel> = image.iter().collect();
+        if options.format == PixelFormat::Indexed
+            && options.colour_warning > 0
+            && unique.len() > options.colour_warning
+        {
+            warn!(
+                "{} uses {} unique colours, more than the {} expected of a sprite",
+                image.name(),
+                unique.len(),
+                options.colour_warning
+            );
+        }
+
+        // every level of a soft edge or shadow is another palette entry
+        let partial = image
+            .iter()
+            .filter(|pixel| pixel.a != 0x00 && pixel.a != 0xFF)
+            .count();
+        if partial > 0 && !keeps_alpha {
+            let levels: HashSet<u8> = image.iter().map(|pixel| pixel.a).collect();
+            warn!(
+                "{} has {} partly transparent pixels across {} alpha levels",
+                image.name(),
+                partial,
+                levels.len() - levels.contains(&0x00) as usize - levels.contains(&0xFF) as usize
+            );
+        }
+    }
+}
+
+/// Encode an image and its alpha plane, split into fields if asked for,
+/// and find its spans.
+fn pack_image(image: &Image, encoding: &Encoding, options: &Options) -> Result<PackedAsset> {
+    // the alpha is left for its own plane
+    let encode = |image: &Image| -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        let data = match options.alpha_plane {
+            Some(_) => encoding.encode(&image.opaque())?,
+            None if options.masked => encoding.masked(image)?,
+            None => encoding.encode(image)?,
+        };
+        let alpha = options
+            .alpha_plane
+            .map(|plane| encoding.alpha(image, plane))
+            .transpose()?;
+        Ok((data, alpha))
+    };
+    let (mut data, mut alpha, odd_field) = match options.fields {
+        None => {
+            let (data, alpha) = encode(image)?;
+            (data, alpha, None)
+        }
+        Some(Fields::EvenOdd) => {
+            let (mut data, mut alpha) = encode(&image.field(false))?;
+            let (odd_data, odd_alpha) = encode(&image.field(true))?;
+            let odd_field = (data.len(), alpha.as_ref().map_or(0, Vec::len));
+            data.extend(odd_data);
+            if let (Some(alpha), Some(odd_alpha)) = (alpha.as_mut(), odd_alpha) {
+                alpha.extend(odd_alpha);
+            }
+            (data, alpha, Some(odd_field))
+        }
+    };
+    if let Some(cipher) = &options.cipher {
+        cipher.encrypt(&mut data);
+        if let Some(alpha) = alpha.as_mut() {
+            cipher.encrypt(alpha);
+        }
+    }
+    let mask = match options.compiled_sprites {
+        true => Some(compiled_mask(image, encoding)?),
+        false => None,
+    };
+    // the spans follow the rows through each field as they are written
+    let spans = options.spans.then(|| match options.fields {
+        None => encoding.spans(image),
+        Some(Fields::EvenOdd) => {
+            let mut spans = encoding.spans(&image.field(false));
+            spans.extend(encoding.spans(&image.field(true)));
+            spans
+        }
+    });
+
+    Ok(PackedAsset {
+        name: image.name().to_owned(),
+        label: match options.label_style {
+            Some(style) => format!("_{}", style.apply(image.name())),
+            None => format!("_{}", image.name()),
+        },
+        width: Some(image.width()),
+        height: Some(image.height()),
+        data,
+        alpha,
+        private: options.private.contains(image.name()),
+        priority: options.priorities.get(image.name()).copied(),
+        odd_field,
+        spans,
+        mask,
+        histogram: options
+            .histogram
+            .then(|| histogram(image, encoding, options)),
+        // the outline is of the whole image, even when it is split into fields
+        outline: options
+            .outlines
+            .then(|| encoding.alpha(&image.outline(), AlphaPlane::Bits1))
+            .transpose()?,
+    })
+}
+
+/// Count the pixels using each palette index, as they're packed.
+fn histogram(image: &Image, encoding: &Encoding, options: &Options) -> Vec<usize> {
+    let mut counts = vec![0; encoding.palette.len()];
+    // the alpha plane holds the alpha, leaving the pixels opaque
+    let image = match options.alpha_plane {
+        Some(_) => image.opaque(),
+        None => image.clone(),
+    };
+    for pixel in image.iter() {
+        if let Some(index) = encoding.palette.index(pixel) {
+            counts[index] += 1;
+        }
+    }
+
+    counts
+}
+
+/// The mask a compiled sprite's routine is written from, once it is known
+/// every row starts on a byte a store can reach.
+fn compiled_mask(image: &Image, encoding: &Encoding) -> Result<Vec<u8>> {
+    ensure!(
+        encoding.row_bytes(image.width()).is_some(),
+        Diagnostic::new(
+            FailureClass::Usage,
+            format!(
+                "the rows of {} share bytes, so it can't be compiled",
+                image.name()
+            )
+        )
+        .with_suggestion("add --row-align byte")
+    );
+    // a store's offset is at most 4095 bytes
+    ensure!(
+        encoding.unaligned_size(image.width(), 1) <= 4096,
+        Diagnostic::new(
+            FailureClass::Usage,
+            format!(
+                "the rows of {} are too wide to draw with immediate offsets",
+                image.name()
+            )
+        )
+        .with_suggestion("leave out --compiled-sprites for sprites this big")
+    );
+
+    encoding.mask(image)
+}
+
+/// The bytes an asset takes, with its alpha plane.
+fn size(asset: &PackedAsset) -> usize {
+    asset.data.len() + asset.alpha.as_ref().map_or(0, Vec::len)
+}
+
+/// Split an image into strips of as many whole rows as fit in `max` bytes,
+/// from the top, packing each as an asset of its own.
+fn split_strips(
+    image: &Image,
+    asset: &PackedAsset,
+    encoding: &Encoding,
+    options: &Options,
+    max: usize,
+) -> Result<(Strips, Vec<PackedAsset>)> {
+    let strip = |index: u32, rows: u32| {
+        let y = index * rows;
+        let name = Strips::name(image.name(), index as usize);
+        image.crop(name, 0, y, image.width(), rows.min(image.height() - y))
+    };
+
+    // the data only grows with the rows, so the most which fit are found by
+    // halving the range between a number which fits and one which doesn't
+    let (mut fits, mut too_many) = (0, image.height());
+    while too_many - fits > 1 {
+        let rows = fits + (too_many - fits) / 2;
+        match size(&pack_image(&strip(0, rows), encoding, options)?) <= max {
+            true => fits = rows,
+            false => too_many = rows,
+        }
+    }
+    if fits == 0 {
+        let row = size(&pack_image(&strip(0, 1), encoding, options)?);
+        return Err(Diagnostic::new(
+            FailureClass::Usage,
+            format!(
+                "a row of {} is {} bytes, more than the {} allowed by --max-asset-size",
+                image.name(),
+                row,
+                max
+            ),
+        )
+        .with_suggestion("raise --max-asset-size, or make the image narrower")
+        .into());
+    }
+
+    let count = image.height().div_ceil(fits);
+    let parts = (0..count)
+        .map(|index| {
+            Ok(PackedAsset {
+                private: asset.private,
+                priority: asset.priority,
+                ..pack_image(&strip(index, fits), encoding, options)?
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let strips = Strips {
+        name: image.name().to_owned(),
+        label: asset.label.clone(),
+        rows: fits,
+        count: count as usize,
+    };
+
+    Ok((strips, parts))
+}
+
+/// A copy of the image moved right by `shift` pixels, in rows a byte longer
+/// than it needs so every shift fits. The padding is palette index 0, and
+/// transparent in an alpha plane or mask.
+fn preshifted(image: &Image, shift: u32, encoding: &Encoding, options: &Options) -> Image {
+    let pixels_per_byte = encoding.pixels_per_byte.unwrap_or(1) as u32;
+    let width = (image.width().div_ceil(pixels_per_byte) + 1) * pixels_per_byte;
+    let background = encoding.palette.colours()[0];
+    let padding = match options.alpha_plane.is_some() || options.masked {
+        true => Pixel { a: 0, ..background },
+        false => background,
+    };
+
+    image
+        .pad(shift, width, padding)
+        .with_name(format!("{}_Shift{}", image.name(), shift))
+}
+
+/// Fail on the first pixel the output can't hold exactly, for
+/// [`Options::strict`], instead of rounding it or thresholding its alpha.
+fn check_exact(images: &[Image], options: &Options) -> Result<()> {
+    for image in images.iter() {
+        let lost = image.iter().enumerate().find_map(|(i, pixel)| {
+            let partly_transparent = pixel.a != 0x00 && pixel.a != 0xFF;
+            let lost = match (options.format, options.alpha_plane) {
+                _ if options.masked && partly_transparent => {
+                    "is partly transparent, which a mask rounds"
+                }
+                (_, Some(AlphaPlane::Bits1)) if partly_transparent => {
+                    "is partly transparent, which a 1-bit alpha plane rounds"
+                }
+                (PixelFormat::Rgb565, None) if pixel.a != 0xFF => {
+                    "is transparent, which RGB565 drops"
+                }
+                // the alpha plane holds the alpha, so the colour is opaque
+                (format, Some(_)) if format.rounds(&Pixel { a: 0xFF, ..*pixel }) => {
+                    "has more colour depth than the pixel format"
+                }
+                (format, None) if format.rounds(pixel) => {
+                    "has more colour depth than the pixel format"
+                }
+                _ => return None,
+            };
+            Some((i as u32, pixel, lost))
+        });
+
+        if let Some((i, pixel, lost)) = lost {
+            return Err(Diagnostic::new(
+                FailureClass::Palette,
+                format!(
+                    "{} at ({}, {}) in {} {}",
+                    palette::hex(pixel),
+                    i % image.width(),
+                    i / image.width(),
+                    image.name(),
+                    lost
+                ),
+            )
+            .with_suggestion("change the sprite or the format, or drop --strict to round it")
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Compile the images for a Cargo build script, writing the output into
+/// `out_dir` (normally `OUT_DIR`).
+///
+/// Returns every path the output depends on so that the build script can emit
+/// `cargo:rerun-if-changed` for each one. Failures are always returned as
+/// errors, never panics, so the build script decides how to report them.
+pub fn build_assets<P: AsRef<Path>>(
+    out_dir: impl AsRef<Path>,
+    inputs: &[P],
+    options: Options,
+) -> Result<Vec<PathBuf>> {
+    let conversion = convert_files_timed(
+        inputs,
+        &options,
+        &Backends::default(),
+        &mut Timings::default(),
+    )?;
+    warn_skipped(&conversion.skipped);
+    write_palette_lock(&options, conversion.palette_lock.as_deref())?;
+    for file in conversion.files {
+        let output = out_dir.as_ref().join(&file.name);
+        std::fs::write(&output, file.contents).map_err(|e| {
+            Diagnostic::new(
+                FailureClass::Output,
+                format!("failed to write output: {}", e),
+            )
+            .with_file(&output)
+            .with_suggestion("check that the output directory exists and is writable")
+        })?;
+    }
+
+    Ok(conversion.dependencies)
+}
+
+/// The inputs as they were expanded, the directories they were found in and
+/// every other file the options had read, for [`Conversion::dependencies`].
+fn dependencies<P: AsRef<Path>>(
+    inputs: &[P],
+    expanded: Vec<PathBuf>,
+    packed: &Packed,
+) -> Vec<PathBuf> {
+    let options = &packed.options;
+    // a directory is a dependency too, so adding a sprite to it reruns the build
+    let mut dependencies = expanded;
+    dependencies.extend(
+        inputs
+            .iter()
+            .map(|p| p.as_ref().to_owned())
+            .filter(|p| p.is_dir()),
+    );
+    dependencies.extend(options.font.as_ref().map(|font| font.path.clone()));
+    if let (Some(options), Some(tilemap)) = (&options.tilemap, &packed.tilemap) {
+        dependencies.push(options.map.clone());
+        dependencies.push(tilemap.tileset().to_owned());
+    }
+    dependencies.extend(
+        [
+            &options.index_map,
+            &options.palette_lock,
+            &options.merge,
+            &options.template,
+        ]
+        .iter()
+        .filter_map(|path| path.as_ref())
+        .cloned(),
+    );
+    dependencies
+}
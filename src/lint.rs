@@ -0,0 +1,315 @@
+//! Checking that an assembly file written before still holds together, for
+//! files which have been edited by hand or passed through other tools.
+
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::encoding::Endian;
+use crate::merge::{byte_directive, constants, fill_row, records};
+use crate::output::Comment;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// What the rows under a label hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Bytes,
+    Words,
+    Code,
+}
+
+/// A label, where it is from the start of the file and the bytes after it
+/// up to the next label.
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    offset: usize,
+    size: usize,
+    kind: Option<Kind>,
+}
+
+/// Check an armasm file written by this tool, and any files it pulls in,
+/// returning a line for each problem found. The byte order is needed to
+/// read the asset records.
+pub fn lint(path: &Path, endian: Endian) -> Result<Vec<String>> {
+    let text = read(path)?;
+    let constants = constants(&text);
+    let mut problems = Vec::new();
+
+    // walk the file adding up what each row assembles to, which for anything
+    // but data is an instruction
+    let bytes = byte_directive(&text);
+    let words = text
+        .lines()
+        .skip_while(|line| line.trim_end() != "AssetAddressTable")
+        .nth(1)
+        .and_then(|entry| entry.split_whitespace().nth(1))
+        .unwrap_or("DEFW");
+    let mut base = 0;
+    let mut offset = 0;
+    let mut blocks: HashMap<&str, Block> = HashMap::new();
+    let mut current = None;
+    for line in text.lines() {
+        let line = Comment::strip(line);
+        let mut row = line.trim();
+        if row.is_empty() {
+            continue;
+        }
+        if !line.starts_with(char::is_whitespace) {
+            let (label, rest) = row.split_once(char::is_whitespace).unwrap_or((row, ""));
+            if rest.trim_start().starts_with("EQU") {
+                continue;
+            }
+            if label != "ALIGN" {
+                blocks.insert(
+                    label,
+                    Block {
+                        offset,
+                        size: 0,
+                        kind: None,
+                    },
+                );
+                current = Some(label);
+                row = rest.trim();
+                if row.is_empty() {
+                    continue;
+                }
+            }
+        }
+
+        let (directive, values) = row.split_once(char::is_whitespace).unwrap_or((row, ""));
+        let (size, kind) = match directive {
+            "ORG" => {
+                base = parse(values).unwrap_or_default();
+                continue;
+            }
+            "ALIGN" => {
+                offset = offset.next_multiple_of(4);
+                current = None;
+                continue;
+            }
+            _ if directive == bytes || directive == "DEFB" || directive == "DCB" => {
+                (values.split(',').count(), Kind::Bytes)
+            }
+            _ if directive == words || directive == "DEFW" || directive == "DCD" => {
+                (4 * values.split(',').count(), Kind::Words)
+            }
+            _ => match fill_row(row) {
+                Some((count, _)) => (count, Kind::Bytes),
+                None => (4, Kind::Code),
+            },
+        };
+        if let Some(block) = current.and_then(|label| blocks.get_mut(label)) {
+            block.size += size;
+            block.kind = block.kind.or(Some(kind));
+        }
+        offset += size;
+    }
+
+    // anything holding words or instructions has to start on a word
+    let mut misaligned: Vec<(&str, usize)> = blocks
+        .iter()
+        .filter(|(_, block)| matches!(block.kind, Some(Kind::Words | Kind::Code)))
+        .map(|(label, block)| (*label, base + block.offset))
+        .filter(|(_, address)| !address.is_multiple_of(4))
+        .collect();
+    misaligned.sort();
+    for (label, address) in misaligned {
+        problems.push(format!(
+            "{} holds words but starts at 0x{:X}, which isn't word aligned",
+            label, address
+        ));
+    }
+
+    // the table's entries are named after the assets, and point at them
+    if !blocks.contains_key("AssetAddressTable") {
+        problems.push("there is no asset address table".to_owned());
+        return Ok(problems);
+    }
+    let entries: Vec<(&str, &str)> = text
+        .lines()
+        .skip_while(|line| line.trim_end() != "AssetAddressTable")
+        .skip(1)
+        .take_while(|line| line.trim_end() != "AssetAddressTableEnd")
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            Some((words.next()?, words.nth(1)?))
+        })
+        .collect();
+    let addresses: HashSet<usize> = blocks.values().map(|block| base + block.offset).collect();
+    for (entry, target) in entries.iter() {
+        match parse(target) {
+            Some(address) if !addresses.contains(&address) => problems.push(format!(
+                "{} points at 0x{:X}, where no label is",
+                entry, address
+            )),
+            None if !blocks.contains_key(target) => problems.push(format!(
+                "{} points at {}, which isn't defined",
+                entry, target
+            )),
+            _ => {}
+        }
+    }
+
+    // every entry has an ASSET_ constant giving its index
+    let names: HashSet<&str> = entries.iter().map(|(entry, _)| *entry).collect();
+    let mut numbers = 0;
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        let (name, value) = match (words.next(), words.next(), words.next()) {
+            (Some(name), Some("EQU"), Some(value)) if name.starts_with("ASSET") => (name, value),
+            _ => continue,
+        };
+        if name == "ASSET_MAX" {
+            continue;
+        }
+        numbers += 1;
+        let entry = value.trim_start_matches('(');
+        match constants.get(name) {
+            Some(&index) if index >= entries.len() => problems.push(format!(
+                "{} is {}, past the end of the {} entry table",
+                name,
+                index,
+                entries.len()
+            )),
+            None if !names.contains(entry) => problems.push(format!(
+                "{} refers to {}, which isn't in the table",
+                name, entry
+            )),
+            _ => {}
+        }
+    }
+    if numbers != entries.len() {
+        problems.push(format!(
+            "the table has {} entries but there are {} ASSET constants",
+            entries.len(),
+            numbers
+        ));
+    }
+    if let Some(&max) = constants.get("ASSET_MAX") {
+        if max != entries.len() {
+            problems.push(format!(
+                "ASSET_MAX is {} but the table has {} entries",
+                max,
+                entries.len()
+            ));
+        }
+    }
+
+    // the palette has to fit the bits, which the pixels have to fit in turn
+    let bits = constants.get("bits_per_colour").copied();
+    let pixels_per_byte = constants.get("pixels_per_byte").copied();
+    if let Some(bits) = bits {
+        match bits {
+            1..=8 if pixels_per_byte != Some(8 / bits) => problems.push(format!(
+                "{} bits per colour pack {} pixels to a byte, not {}",
+                bits,
+                8 / bits,
+                pixels_per_byte.map_or("none".to_owned(), |pixels| pixels.to_string())
+            )),
+            16 if constants.get("bytes_per_pixel") != Some(&2) => {
+                problems.push("16 bits per colour need bytes_per_pixel to be 2".to_owned())
+            }
+            1..=8 | 16 => {}
+            _ => problems.push(format!("{} bits per colour can't be packed", bits)),
+        }
+        let colours = blocks.get("Palette").map_or(0, |palette| palette.size / 4);
+        if bits < 16 && colours > 1 << bits {
+            problems.push(format!(
+                "the palette has {} colours, more than {} bits per colour can index",
+                colours, bits
+            ));
+        }
+    }
+
+    // the records give the sizes, with the bytes each pixel takes from the
+    // packing, and the stride says how long a row is
+    let records = records(&text, endian).map_err(|reason| {
+        Diagnostic::new(FailureClass::Input, reason)
+            .with_file(path)
+            .with_suggestion("check the records weren't edited, and the --endian is right")
+    })?;
+    let masked = constants.contains_key("PAIR_BYTES");
+    for (index, (_, label)) in entries.iter().enumerate() {
+        let block = match blocks.get(label) {
+            Some(block) if block.kind == Some(Kind::Bytes) => block,
+            _ => continue,
+        };
+        let odd = blocks.get(format!("{}_Odd", label).as_str());
+        let size = block.size + odd.map_or(0, |odd| odd.size);
+        let stride = constants.get(format!("STRIDE{}", label).as_str()).copied();
+
+        let record = match records.get(index) {
+            Some(record) => record,
+            None => {
+                if let Some(stride) = stride.filter(|&stride| !size.is_multiple_of(stride)) {
+                    problems.push(format!(
+                        "{} is {} bytes, which isn't a whole number of {} byte rows",
+                        label, size, stride
+                    ));
+                }
+                continue;
+            }
+        };
+        let (width, height) = (record.width as usize, record.height as usize);
+        // each field is packed by itself
+        let rows = |rows: usize| match (stride, bits) {
+            (Some(stride), _) => Some(stride * rows),
+            (None, _) if masked => None,
+            (None, Some(bits @ 1..=8)) => Some((width * rows).div_ceil(8 / bits)),
+            (None, _) => Some(width * rows * record.bits_per_pixel as usize / 8),
+        };
+        let expected = match odd {
+            Some(_) => rows(height.div_ceil(2))
+                .zip(rows(height / 2))
+                .map(|(a, b)| a + b),
+            None => rows(height),
+        };
+        if let Some(expected) = expected.filter(|&expected| expected != size) {
+            problems.push(format!(
+                "{} is {} bytes, but a {}x{} image takes {}",
+                label, size, width, height, expected
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Read a file, with the parts of a split output pulled in where they're
+/// included.
+fn read(path: &Path) -> Result<String> {
+    let read = |path: &Path| {
+        std::fs::read_to_string(path).map_err(|e| {
+            Diagnostic::new(
+                FailureClass::Input,
+                format!("failed to read the file to check: {}", e),
+            )
+            .with_file(path)
+            .with_suggestion("check that the path exists and is readable")
+        })
+    };
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut text = String::new();
+    for line in read(path)?.lines() {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("GET" | "INCLUDE"), Some(part)) if line.starts_with(char::is_whitespace) => {
+                text.push_str(&read(&dir.join(part))?)
+            }
+            _ => {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+    }
+
+    Ok(text)
+}
+
+/// Parse a number in hex with a `0x` prefix, or in decimal.
+fn parse(value: &str) -> Option<usize> {
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
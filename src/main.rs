@@ -1,256 +1,1020 @@
-#![feature(vec_into_raw_parts)]
-
-use anyhow::{anyhow, ensure, Context, Result};
-use std::collections::HashSet;
-use std::fmt::Write as FmtWrite;
-use std::fs::File;
-use std::io::{BufWriter, Write as IoWrite};
-use std::path::PathBuf;
-
-type Pixel = rgb::RGBA<u8>;
-type ImageSet = Vec<Image>;
-type Label = String;
-
-const PIXEL_BYTES: usize = std::mem::size_of::<Pixel>();
-
-const FILE_HEADER: &'static str = r"; ###########################################################
-;              _    ____ ____  _____ _____ ____  
-;             / \  / ___/ ___|| ____|_   _/ ___| 
-;            / _ \ \___ \___ \|  _|   | | \___ \ 
-;           / ___ \ ___) |__) | |___  | |  ___) |
-;          /_/   \_\____/____/|_____| |_| |____/ 
-; ###########################################################";
-
-fn main() -> Result<()> {
-    // first read all the images into a vector
-    let images: ImageSet = std::env::args()
-        .skip(1)
-        .map(|image_file| {
-            // get a handle to the file
-            let file = File::open(&image_file)
-                .with_context(|| format!("Failed to open {}.", &image_file))?;
-
-            // get a reader handle to the image data
-            let decoder = png::Decoder::new(file);
-            let (info, mut reader) = decoder
-                .read_info()
-                .context("Decoder failed to read info from the image file.")?;
-
-            // read in the first image frame
-            let mut buf = vec![0; info.buffer_size()];
-            reader
-                .next_frame(&mut buf)
-                .context("Failed to read the next frame of the PNG.")?;
-
-            // transmute the Vec<u8> to a Vec<Pixel>
-            let (ptr, len, cap) = buf.into_raw_parts();
-
-            // assert that we aren't going to violate any memory safety guarentees
-            ensure!(
-                len % PIXEL_BYTES == 0,
-                "Unsafe to convert vec with {} bytes to pixels.",
-                len
-            );
-            ensure!(
-                len % PIXEL_BYTES == 0,
-                "Unsafe to convert vec with a capacity {} bytes to pixels.",
-                cap
-            );
+use anyhow::{ensure, Result};
+use clap::{CommandFactory, Parser};
+use image2arm::diagnostic::{self, Diagnostic, ErrorFormat, FailureClass};
+use image2arm::{
+    AlphaPlane, AssetTable, Backends, Charset, Cipher, ColourManagement, Comment, Demo, Directives,
+    Endian, ExtractOptions, Fields, FontOptions, HexCase, IncludeDirective, Indent, LabelStyle,
+    NinePatch, Options, PaletteOutput, PixelFormat, Proportional, Region, RowAlign, RowOrder, Size,
+    Style, TableTerminator, Target, Tick, TilemapOptions, Timings,
+};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// The environment variable holding flags to add to every run.
+const FLAGS_VAR: &str = "IMAGE2ARM_FLAGS";
+
+/// Compile a set of sprites into ARM assembly sharing a global colour table.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    args_override_self = true,
+    subcommand_negates_reqs = true
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The PNG or Aseprite sprites to compile, `@args.txt` reads one argument per line from args.txt.
+    /// `name=path` names the sprite's asset rather than using the file name.
+    #[arg(required_unless_present_any = ["completions", "font", "map"])]
+    sprites: Vec<PathBuf>,
+
+    /// The name of the only sprite's asset, rather than the file name.
+    #[arg(long)]
+    name: Option<String>,
+
+    /// A font to convert, either an image of glyphs left to right then top to bottom,
+    /// or a BDF or PSF bitmap font.
+    #[arg(long, value_name = "PATH")]
+    font: Option<PathBuf>,
+
+    /// The size of each glyph in a font image [default: 8x8].
+    #[arg(long, value_name = "WxH", requires = "font")]
+    glyph: Option<Size>,
+
+    /// The characters in the font, as characters and ranges like ' '..'~'. These are in
+    /// order for a font image [default: ' '..'~'] and pick which to keep for BDF/PSF fonts.
+    #[arg(long, value_name = "SET", requires = "font")]
+    charset: Option<Charset>,
+
+    /// Make the font proportional, finding glyph widths by `trim`ming empty columns
+    /// or up to a `separator=#RRGGBB` coloured column.
+    #[arg(long, value_name = "HOW", requires = "font")]
+    proportional: Option<Proportional>,
+
+    /// The gap in pixels between glyphs of a proportional font.
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        default_value_t = 1,
+        requires = "proportional"
+    )]
+    letter_spacing: u32,
+
+    /// Find glyphs with a sorted codepoint table and a generated binary search routine,
+    /// for character sets that aren't a contiguous range.
+    #[arg(long, requires = "font")]
+    codepoint_table: bool,
+
+    /// Generate a `draw_string` routine which draws text with the font.
+    #[arg(long, requires = "font")]
+    emit_text_renderer: bool,
+
+    /// A map made in Tiled to convert, as a .tmx file or a layer exported as CSV.
+    #[arg(long, value_name = "PATH")]
+    map: Option<PathBuf>,
+
+    /// The image holding the map's tiles [default: the image the .tmx names].
+    #[arg(long, value_name = "PATH", requires = "map")]
+    tileset: Option<PathBuf>,
+
+    /// The size of each tile in pixels [default: the .tmx tile size, or 8x8].
+    #[arg(long, value_name = "WxH", requires = "map")]
+    tiles: Option<Size>,
+
+    /// Group the map into metatiles of this many tiles across and down, like 2x2.
+    #[arg(long, value_name = "WxH", requires = "map")]
+    metatiles: Option<Size>,
+
+    /// Store tiles which are mirror images of another tile once, setting flip bits in the map.
+    #[arg(long, requires = "map")]
+    flip_tiles: bool,
+
+    /// The unit animation frame durations are written in, as a time like 1ms or a rate like 60hz.
+    #[arg(long, value_name = "TICK", default_value_t = Tick::default())]
+    tick: Tick,
+
+    /// Set up the pixel format, byte order, row order and alignment for a display controller.
+    #[arg(long, value_enum)]
+    target: Option<Target>,
+
+    /// How each pixel is stored [default: indexed].
+    #[arg(long, value_enum)]
+    format: Option<PixelFormat>,
+
+    /// The byte order of pixels and tables wider than a byte, and of the pixels packed
+    /// into each byte [default: little].
+    #[arg(long, value_enum)]
+    endian: Option<Endian>,
+
+    /// The order image rows are written in [default: top-down].
+    #[arg(long, value_enum)]
+    row_order: Option<RowOrder>,
+
+    /// Start every image row on a fresh byte or word, padding the row before it.
+    #[arg(long, value_enum)]
+    row_align: Option<RowAlign>,
+
+    /// Write a label for every image row, as _<name>_Row<N>, and a table of them as _<name>_Rows.
+    #[arg(long)]
+    row_labels: bool,
+
+    /// Write each image as its even rows then its odd rows, labelling the odd field _<name>_Odd.
+    #[arg(long, value_enum)]
+    fields: Option<Fields>,
+
+    /// Write this many copies of each sprite moved right a pixel at a time, as _<name>_Shift<N>.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    preshift: u32,
+
+    /// Write a transparency mask word before every word of each sprite, for AND/OR blitting.
+    #[arg(long)]
+    masked: bool,
+
+    /// Write the runs of transparent pixels to skip and opaque ones to draw along each row, as _<name>_Spans.
+    #[arg(long)]
+    spans: bool,
+
+    /// Write a routine for each sprite which draws it to the framebuffer at r0, instead of its pixels.
+    #[arg(long)]
+    compiled_sprites: bool,
+
+    /// Keep only the first frame of animations with evenly spaced frames in the table, writing ANIM_<name>_FRAME_STRIDE instead.
+    #[arg(long)]
+    frame_stride: bool,
+
+    /// Write a CRC-32 of each asset's packed data as HASH_<name>, to tell when an asset changed between builds.
+    #[arg(long)]
+    asset_hashes: bool,
+
+    /// XOR each asset's data with this key of one to four bytes, like 0x5A or 0x5A,0x3C, used in
+    /// turn from the start of each asset, and write it as XOR_KEY.
+    #[arg(long, value_name = "KEY")]
+    xor_key: Option<Cipher>,
+
+    /// Write how many pixels of each asset use each palette index to a .histogram.txt file alongside the output.
+    #[arg(long)]
+    histogram: bool,
+
+    /// Write a bit per pixel along the edge of each sprite's opaque pixels, as _<name>_Outline.
+    #[arg(long)]
+    emit_outlines: bool,
+
+    /// Write the fingerprint printed at the end of the run into the JSON output, which must be emitted.
+    #[arg(long)]
+    json_fingerprint: bool,
+
+    /// Also write a program which draws every asset in a grid on the framebuffer, as <file>_demo.s.
+    #[arg(long, requires = "framebuffer")]
+    emit_demo: bool,
+
+    /// The address of the demo's framebuffer, which is packed the same way as the assets.
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_address)]
+    framebuffer: Option<u32>,
+
+    /// The width of the demo's framebuffer in pixels.
+    #[arg(long, value_name = "PIXELS", default_value_t = 320)]
+    framebuffer_width: u32,
+
+    /// Start every image on a word boundary.
+    #[arg(long)]
+    align_assets: bool,
+
+    /// Write each sprite's alpha as a plane of its own after its colours, with 8 bits per
+    /// pixel or 1 bit set where the pixel is at least half opaque.
+    #[arg(long, value_enum, value_name = "BITS")]
+    alpha_plane: Option<AlphaPlane>,
+
+    /// Write this many copies of the palette from darker to lighter, as Palette_Shade0 onwards.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    shade_levels: u32,
+
+    /// Write every palette index as a 16-bit halfword, as is done anyway past 256 colours.
+    #[arg(long)]
+    wide_indices: bool,
+
+    /// Pin colours to palette indices, from a file of lines like `#102030 -> 5`.
+    #[arg(long, value_name = "PATH")]
+    index_map: Option<PathBuf>,
+
+    /// Leave the palette indices in this range, which has to start at 0, to the system, numbering
+    /// the palette from the index after it.
+    #[arg(long, value_name = "0-N", value_parser = parse_reserved)]
+    reserve_indices: Option<usize>,
+
+    /// Keep colours at the palette indices recorded in this file, adding new colours after
+    /// them, and update it. The file is created on the first run.
+    #[arg(long, value_name = "PATH")]
+    palette_lock: Option<PathBuf>,
+
+    /// Fail if the sprites need any colour the palette lock doesn't have, or any locked index moved, without updating it.
+    #[arg(long, requires = "palette_lock")]
+    palette_lock_check: bool,
+
+    /// Keep the palette the inputs were saved with, and every pixel's index in it. The inputs must
+    /// all be indexed PNGs sharing one palette.
+    #[arg(long, conflicts_with_all = ["index_map", "palette_lock", "merge"])]
+    trust_source_palette: bool,
+
+    /// Use the pixels of PNGs as they're stored, rather than converting them to sRGB from the
+    /// colour space an sRGB, iCCP or gAMA chunk says they're in.
+    #[arg(long)]
+    ignore_colour_profiles: bool,
+
+    /// Rotate and flip PNGs upright by the orientation in their EXIF data, for photos taken on
+    /// their side.
+    #[arg(long)]
+    respect_exif: bool,
+
+    /// Write only the palette, to share between data files written with --no-palette.
+    #[arg(long, conflicts_with = "no_palette")]
+    palette_only: bool,
+
+    /// Write everything but the palette, for a palette written once with --palette-only.
+    #[arg(long)]
+    no_palette: bool,
+
+    /// Add the sprites to a file this tool wrote before, keeping its assets and palette.
+    #[arg(long, value_name = "PATH")]
+    merge: Option<PathBuf>,
+
+    /// The kinds of file to write, repeat to write several from the same palette and packing.
+    #[arg(
+        long,
+        value_name = "KIND",
+        default_value = "asm",
+        value_parser = ["asm", "gas", "c-header", "rust", "json", "binary", "kmd", "gdb"]
+    )]
+    emit: Vec<String>,
+
+    /// Also render this Handlebars template with the palette and packed assets, writing a
+    /// file named after it without its last extension.
+    #[arg(long, value_name = "PATH")]
+    template: Option<PathBuf>,
+
+    /// Write each asset into a file of its own, leaving the main file to include them.
+    #[arg(long)]
+    split: bool,
+
+    /// How the main file of a split output includes the others.
+    #[arg(long, value_enum, default_value_t = IncludeDirective::default(), requires = "split")]
+    include_directive: IncludeDirective,
+
+    /// Warn about any sprite using more unique colours than this, 0 turns the warning off.
+    #[arg(long, value_name = "N", default_value_t = Options::default().colour_warning)]
+    warn_colours: usize,
+
+    /// Fail if the asset table has more entries than this, as a runtime indexing it with a byte
+    /// would wrap around. 0 allows any number.
+    #[arg(long, value_name = "N", default_value_t = Options::default().max_assets)]
+    max_assets: usize,
+
+    /// Split any sprite whose data is more than this many bytes into strips of whole rows, with a
+    /// table of the strips, for loaders which copy through a buffer of fixed size.
+    #[arg(long, value_name = "BYTES")]
+    max_asset_size: Option<usize>,
+
+    /// Merge palette colours whose red, green, blue and alpha all differ by no more than this.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    merge_tolerance: u8,
+
+    /// Treat alpha within this of 255 as fully opaque, so colours only differing by a stray alpha
+    /// like 254 share a palette entry.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    opaque_tolerance: u8,
+
+    /// Whether the asset table holds each asset's address or its offset, for data which is moved.
+    #[arg(long, value_enum, default_value_t = AssetTable::default())]
+    table: AssetTable,
+
+    /// The label the table's offsets are measured from, rather than the start of the table.
+    #[arg(long, value_name = "LABEL")]
+    table_base: Option<String>,
+
+    /// End the asset table with a zero word, or put the number of entries in a word just before it.
+    #[arg(long, value_enum, value_name = "KIND")]
+    table_terminator: Option<TableTerminator>,
+
+    /// Also write a table of 16 byte records holding each asset's pointer, size, width, height and bits per pixel.
+    #[arg(long)]
+    records: bool,
+
+    /// Leave this asset out of the asset table and its constants, keeping only its label.
+    #[arg(long, value_name = "NAME")]
+    private: Vec<String>,
+
+    /// Draw this asset at a priority, as NAME=N, written as PRIORITY_<name>. Higher priorities are
+    /// drawn later, over lower ones, and assets without one are at 0.
+    #[arg(long, value_name = "NAME=N", value_parser = parse_priority)]
+    priority: Vec<(String, i32)>,
+
+    /// Also write AssetPriorityTable, the assets' numbers sorted by priority from the back to the front.
+    #[arg(long)]
+    priority_table: bool,
+
+    /// Cut named rectangles out of a sprite, each an asset of its own in its place, as
+    /// `SPRITE: NAME=X,Y,WIDTH,HEIGHT; ...` such as `icons.png: heart=0,0,8,8; coin=8,0,8,8`.
+    #[arg(long, value_name = "SPRITE: REGIONS", value_parser = parse_regions)]
+    regions: Vec<(String, Vec<Region>)>,
+
+    /// Cut this asset into its corners, edges and centre, each an asset of its own, as NAME=LEFT,TOP,RIGHT,BOTTOM.
+    /// One inset like NAME=4 is used for all four sides.
+    #[arg(long, value_name = "NAME=INSETS", value_parser = parse_nine_patch)]
+    nine_patch: Vec<(String, NinePatch)>,
+
+    /// Skip files matching this glob when scanning a directory, such as `**/wip_*.png`.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Include files starting with a dot when scanning a directory.
+    #[arg(long)]
+    hidden: bool,
+
+    /// Fail instead of skipping inputs, such as files given twice, or changing colours, alpha or names.
+    #[arg(long)]
+    strict: bool,
+
+    /// Skip inputs which fail to load and convert the rest, failing at the end with a list of them.
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Decode at most N inputs at once [default: the number of CPUs].
+    #[arg(long, value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Refuse to start if the conversion is estimated to need more memory than this, like 512M or 2G.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_mem: Option<u64>,
+
+    /// Print how much memory the conversion is estimated to need, from the image headers, and exit.
+    #[arg(long)]
+    estimate_mem: bool,
+
+    /// The address the output is loaded at, emitting an ORG and absolute addresses in the table.
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_address)]
+    base: Option<u32>,
+
+    /// The directive rows of bytes are written with, like DCB for armasm or .byte for gas.
+    #[arg(long, value_name = "KEYWORD", default_value_t = Directives::default().byte)]
+    directive_byte: String,
+
+    /// The directive rows of words are written with, like DCD for armasm or .word for gas.
+    #[arg(long, value_name = "KEYWORD", default_value_t = Directives::default().word)]
+    directive_word: String,
+
+    /// The directive runs of one byte are filled with by --fill-runs, like FILL for armasm.
+    #[arg(long, value_name = "KEYWORD", default_value_t = Directives::default().fill)]
+    directive_fill: String,
+
+    /// Write runs of at least this many copies of one byte, like blank rows, as a single fill.
+    #[arg(
+        long,
+        value_name = "BYTES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "16"
+    )]
+    fill_runs: Option<usize>,
+
+    /// The case of the digits in hex literals.
+    #[arg(long, value_enum, default_value_t = HexCase::default())]
+    hex_case: HexCase,
+
+    /// Indent with `tab` or with this many spaces, which also puts single spaces between fields.
+    #[arg(long, value_name = "tab|N", default_value = "tab")]
+    indent: Indent,
+
+    /// Pad labels with spaces so whatever follows them starts at this column.
+    #[arg(long, value_name = "N")]
+    label_column: Option<usize>,
+
+    /// The case of the asset names in labels and constants, rather than the names as they are.
+    #[arg(long, value_enum)]
+    label_style: Option<LabelStyle>,
+
+    /// What starts comments, like @ for assemblers which read ; as a statement separator.
+    #[arg(long, value_enum, value_name = "CHARS")]
+    comment_char: Option<Comment>,
+
+    /// Leave out the banner at the top of the assembly.
+    #[arg(long)]
+    no_banner: bool,
+
+    /// Fail if the output differs from the files already there, instead of writing it.
+    #[arg(long)]
+    check: bool,
+
+    /// Print how long each phase took, and the slowest assets, to stderr.
+    #[arg(long)]
+    timings: bool,
+
+    /// How errors are reported on stderr.
+    #[arg(long, value_enum, default_value = "human", global = true)]
+    error_format: ErrorFormat,
+
+    /// Log what the conversion is doing, repeat for more detail (RUST_LOG also works).
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Print a shell completion script to stdout and exit.
+    #[arg(long, value_name = "SHELL")]
+    completions: Option<clap_complete::Shell>,
+}
 
-            // do magic
-            let image = unsafe {
-                let pixel_ptr = ptr as *mut Pixel;
-                let pixel_len = len / PIXEL_BYTES;
-                let pixel_cap = cap / PIXEL_BYTES;
+/// Tools for files written before, rather than compiling sprites.
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Summarise the palette, asset and size changes between two outputs, or two PNGs.
+    Diff {
+        /// The earlier assembly file or PNG.
+        old: PathBuf,
+        /// The later assembly file or PNG.
+        new: PathBuf,
+    },
+    /// Write a PNG of every asset in an assembly file written before.
+    Extract {
+        /// The assembly file to read.
+        file: PathBuf,
+        /// The directory to write the PNGs into.
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        out_dir: PathBuf,
+        /// The width of every asset, for files written without --records.
+        #[arg(long)]
+        width: Option<u32>,
+        /// The byte order the file was written with.
+        #[arg(long, value_enum, default_value = "little")]
+        endian: Endian,
+        /// The order the file's rows were written in.
+        #[arg(long, value_enum, default_value = "top-down")]
+        row_order: RowOrder,
+        /// Overwrite PNGs already in the directory, which are otherwise an error.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check that an assembly file written before holds together.
+    Check {
+        /// The assembly file to check, along with any parts it includes.
+        file: PathBuf,
+        /// The byte order the file was written with.
+        #[arg(long, value_enum, default_value = "little")]
+        endian: Endian,
+    },
+}
 
-                Vec::from_raw_parts(pixel_ptr, pixel_len, pixel_cap)
+fn main() {
+    let raw = match arguments() {
+        Ok(raw) => raw,
+        Err(err) => {
+            let format = match wants_json(&std::env::args_os().collect::<Vec<_>>()) {
+                true => ErrorFormat::Json,
+                false => ErrorFormat::Human,
             };
+            std::process::exit(diagnostic::report(&err, format));
+        }
+    };
+    let args = match Args::try_parse_from(&raw) {
+        Ok(args) => args,
+        Err(err) => report_usage_error(err, &raw),
+    };
+
+    // RUST_LOG is parsed after the verbosity so it can still override it per module
+    let level = match args.verbose {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+
+    if let Some(shell) = args.completions {
+        let mut command = Args::command();
+        let name = command.get_name().to_owned();
+        clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        return;
+    }
 
-            // get the image name from the file name
-            let asset_name = PathBuf::from(&image_file)
-                .file_stem()
-                .ok_or(anyhow!(
-                    "Couldn't parse file name from path: {}",
-                    &image_file
-                ))?
-                .to_str()
-                .ok_or(anyhow!(
-                    "Image path name ({}) contained invalid unicode.",
-                    &image_file
-                ))?
-                .to_owned();
-
-            Ok(Image::new(asset_name, image))
-        })
-        .collect::<Result<_>>()?;
-
-    // without this check an empty list gives a confusing divide-by-zero error
-    ensure!(!images.is_empty(), "No Images to process.");
-
-    /* Output format
-     * - Colour palette
-     * - I want to be able to access images by a label (which shouldn't just be a pointer to the actual pixels.
-     *   - so this implies a table from asset names to memory locations of images
-     * - num pixels per byte
-     * - number bits per colour
-     * - the actual images
-     */
-
-    // get a handle to the file
-    let mut file = BufWriter::new(
-        File::create("assets.s").context("Failed to open output file - 'assets.s'")?,
-    );
-
-    // write the file header
-    writeln!(file, "{}\n", FILE_HEADER)?;
+    let result = match &args.command {
+        Some(command) => run_command(command),
+        None => run(&args),
+    };
+    if let Err(err) = result {
+        std::process::exit(diagnostic::report(&err, args.error_format));
+    }
+}
 
-    // now iterate over all the pixels and collect the unique ones.
-    let palette = Palette::new_from_images(&images);
-    writeln!(file, "{}", palette.to_asm()?)?;
+fn run_command(command: &Command) -> Result<()> {
+    match command {
+        Command::Diff { old, new } => print!("{}", image2arm::diff(old, new)?),
+        Command::Extract {
+            file,
+            out_dir,
+            width,
+            endian,
+            row_order,
+            force,
+        } => {
+            let options = ExtractOptions {
+                width: *width,
+                endian: *endian,
+                row_order: *row_order,
+            };
+            let images = image2arm::extract(file, &options)?;
+            let paths: Vec<PathBuf> = images
+                .iter()
+                .map(|image| out_dir.join(format!("{}.png", image.name())))
+                .collect();
+            // the sprites the file was made from are often in the same directory
+            if let Some(path) = paths.iter().find(|path| !force && path.exists()) {
+                return Err(Diagnostic::new(
+                    FailureClass::Output,
+                    "extracting would overwrite a file which already exists",
+                )
+                .with_file(path)
+                .with_suggestion("pick another --out-dir, or pass --force to overwrite it")
+                .into());
+            }
+            for (image, path) in images.iter().zip(paths) {
+                std::fs::write(&path, image.encode()?).map_err(|e| {
+                    Diagnostic::new(
+                        FailureClass::Output,
+                        format!("failed to write image: {}", e),
+                    )
+                    .with_file(&path)
+                    .with_suggestion("check that the output directory exists and is writable")
+                })?;
+            }
+        }
+        Command::Check { file, endian } => {
+            let problems = image2arm::lint(file, *endian)?;
+            for problem in problems.iter() {
+                println!("{}", problem);
+            }
+            ensure!(
+                problems.is_empty(),
+                Diagnostic::new(
+                    FailureClass::Input,
+                    format!("problems found: {}", problems.len())
+                )
+                .with_file(file)
+                .with_suggestion("regenerate the file rather than editing it by hand")
+            );
+        }
+    }
 
-    // calculate the number of pixels
-    let bits_per_colour = (palette.len() as f64).log2().ceil() as usize;
-    let pixels_per_byte = 8 / bits_per_colour;
-    writeln!(file, "bits_per_colour\tEQU {}", bits_per_colour)?;
-    writeln!(file, "pixels_per_byte\tEQU {}\n", pixels_per_byte)?;
+    Ok(())
+}
 
-    // write out the assets
-    let mut labels = Vec::new();
-    for image in images.into_iter() {
-        let (image_label, asm) = image.to_asm(&palette, pixels_per_byte, bits_per_colour)?;
-        labels.push(image_label);
+/// Parse an address in hex with a `0x` prefix, or in decimal.
+fn parse_address(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' isn't an address", s))
+}
 
-        writeln!(file, "{}", asm)?;
+/// Parse a range of reserved palette indices like `0-15` into how many
+/// there are, as only the first indices can be reserved.
+fn parse_reserved(s: &str) -> Result<usize, String> {
+    let (first, last) = s.split_once('-').unwrap_or((s, s));
+    let parse = |index: &str| {
+        index
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("'{}' isn't a palette index", index))
+    };
+    match (parse(first)?, parse(last)?) {
+        (0, last) => Ok(last + 1),
+        _ => Err(format!(
+            "expected a range starting at 0 like 0-15, found '{}'",
+            s
+        )),
     }
+}
 
-    // the address table must be aligned
-    writeln!(file, "ALIGN\n")?;
+/// Parse the regions of a sprite, which is named by its file name.
+fn parse_regions(s: &str) -> Result<(String, Vec<Region>), String> {
+    // the regions have no colons, unlike some paths
+    let (sprite, regions) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected SPRITE: NAME=X,Y,WIDTH,HEIGHT; ..., found '{}'", s))?;
+    let name = Path::new(sprite.trim())
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| format!("'{}' isn't a sprite", sprite))?;
+    Ok((name.to_owned(), Region::parse_list(regions)?))
+}
 
-    // write out the asset address table
-    let aatable = "AssetAddressTable";
-    let aaprefix = "_ADR";
-    writeln!(file, "{}", aatable)?;
-    for label in labels.iter() {
-        writeln!(file, "{}{1:<28}DEFW\t{1}", aaprefix, label)?;
-    }
-    writeln!(file, "{}End", aatable)?;
+/// Parse a `name=insets` nine-patch.
+fn parse_nine_patch(s: &str) -> Result<(String, NinePatch), String> {
+    let (name, insets) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=LEFT,TOP,RIGHT,BOTTOM, found '{}'", s))?;
+    Ok((name.to_owned(), insets.parse()?))
+}
 
-    // write out a constant for the number of assets in the table
-    writeln!(file, "\nASSET_MAX\tEQU\t({0}End - {0}) / 4\n", aatable)?;
+/// Parse a `name=priority` draw priority.
+fn parse_priority(s: &str) -> Result<(String, i32), String> {
+    let (name, priority) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=PRIORITY, found '{}'", s))?;
+    let priority = priority
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' isn't a priority", priority))?;
+    Ok((name.to_owned(), priority))
+}
 
-    // write out the asset table
-    for label in labels.iter() {
-        writeln!(
-            file,
-            "ASSET{:<27}EQU\t({}{:<24} - {}) / 4",
-            label, aaprefix, label, aatable
-        )?;
-    }
+/// Parse a number of bytes, with an optional K, M or G suffix for binary
+/// kilobytes, megabytes or gigabytes.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (number, shift) = match s.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        size if size.ends_with('K') => (size[..size.len() - 1].to_owned(), 10),
+        size if size.ends_with('M') => (size[..size.len() - 1].to_owned(), 20),
+        size if size.ends_with('G') => (size[..size.len() - 1].to_owned(), 30),
+        size => (size.to_owned(), 0),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or_else(|| format!("'{}' isn't a size", s))
+}
 
-    Ok(())
+/// Whether JSON errors were asked for, looking for the flag by hand for when
+/// the arguments couldn't be parsed.
+fn wants_json(raw: &[OsString]) -> bool {
+    raw.iter().any(|arg| arg == "--error-format=json")
+        || raw
+            .windows(2)
+            .any(|pair| pair[0] == "--error-format" && pair[1] == "json")
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct Image {
-    name: String,
-    pixels: Vec<Pixel>,
+/// Clap reports its own errors, but when JSON was asked for the usage error
+/// needs to be JSON too.
+fn report_usage_error(err: clap::Error, raw: &[OsString]) -> ! {
+    if !wants_json(raw) || !err.use_stderr() {
+        err.exit();
+    }
+
+    // only the first line of clap's message is the error, the rest is usage help
+    let rendered = err.to_string();
+    let reason = rendered
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches("error: ")
+        .to_owned();
+    let usage = Diagnostic::new(FailureClass::Usage, reason)
+        .with_suggestion("run with --help to see the accepted arguments");
+    std::process::exit(diagnostic::report(&usage.into(), ErrorFormat::Json));
 }
 
-impl Image {
-    fn new(name: String, pixels: Vec<Pixel>) -> Self {
-        Self { name, pixels }
+/// The arguments to parse, with the flags in `IMAGE2ARM_FLAGS` ahead of the
+/// command line's so the command line wins, and every `@file` replaced by the
+/// arguments in that file, one per line, as long option sets and hundreds of
+/// sprites won't fit in argv on some systems.
+fn arguments() -> Result<Vec<OsString>> {
+    let mut raw = std::env::args_os();
+    let mut arguments: Vec<OsString> = raw.next().into_iter().collect();
+    let raw: Vec<OsString> = raw.collect();
+
+    // the subcommands take none of the flags
+    let command = Args::command();
+    let subcommand = raw
+        .first()
+        .and_then(|first| first.to_str())
+        .is_some_and(|first| {
+            first == "help" || command.get_subcommands().any(|sub| sub.get_name() == first)
+        });
+    if let Some(flags) = std::env::var_os(FLAGS_VAR).filter(|_| !subcommand) {
+        let flags = flags.into_string().map_err(|_| {
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!("{} isn't valid UTF-8", FLAGS_VAR),
+            )
+        })?;
+        arguments.extend(split_flags(&flags)?);
     }
 
-    #[inline]
-    fn iter(&self) -> std::slice::Iter<'_, Pixel> {
-        self.pixels.iter()
+    for argument in raw {
+        // a lone @ is a value, like the comment character
+        let file = match argument.to_str().and_then(|s| s.strip_prefix('@')) {
+            Some(file) if !file.is_empty() => file,
+            _ => {
+                arguments.push(argument);
+                continue;
+            }
+        };
+
+        let contents = std::fs::read_to_string(file).map_err(|e| {
+            Diagnostic::new(
+                FailureClass::Input,
+                format!("failed to read response file: {}", e),
+            )
+            .with_file(file)
+            .with_suggestion("check that the path after the '@' exists and is readable")
+        })?;
+
+        arguments.extend(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(OsString::from),
+        );
     }
 
-    fn to_asm(
-        &self,
-        palette: &Palette,
-        pixels_per_byte: usize,
-        bits_per_colour: usize,
-    ) -> Result<(Label, String)> {
-        let image_label: Label = format!("_{}", self.name.clone());
-
-        let mut buf = String::new();
-
-        // first write the label for the image
-        writeln!(buf, "{}", &image_label)?;
-
-        // now collect the pixels into bytes
-        let packed: Vec<u8> = self
-            .pixels
-            .chunks(pixels_per_byte)
-            .map(|chunk| {
-                chunk.iter().rev().fold(0_u8, |acc, pixel| {
-                    (acc << bits_per_colour)
-                        | (palette
-                            .index(pixel)
-                            .expect("_Palette doesn't contain this pixel.")
-                            as u8)
-                })
-            })
-            .collect();
+    Ok(arguments)
+}
 
-        // write the bytes to the buffer
-        for row in packed.chunks_exact(5) {
-            write!(buf, "\tDEFB 0x{:02X}", row[0])?;
-            for byte in row.iter().skip(1) {
-                write!(buf, ", 0x{:02X}", byte)?;
+/// Split flags on whitespace as a shell would, with quotes keeping a value
+/// holding spaces together.
+fn split_flags(flags: &str) -> Result<Vec<OsString>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in flags.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
             }
-            buf.push('\n');
+            (None, c) if c.is_whitespace() => words.extend(word.take().map(OsString::from)),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
         }
+    }
+    ensure!(
+        quote.is_none(),
+        Diagnostic::new(
+            FailureClass::Usage,
+            format!("{} has a quote which isn't closed", FLAGS_VAR)
+        )
+        .with_suggestion("close the quote around the value holding spaces")
+    );
+    words.extend(word.map(OsString::from));
+
+    Ok(words)
+}
 
-        Ok((image_label, buf))
+/// Split a `name=path` argument into the name and the path, unless it's the
+/// path of a file which exists.
+fn split_name(sprite: &Path) -> Option<(String, PathBuf)> {
+    if sprite.exists() {
+        return None;
+    }
+    let (name, path) = sprite.to_str()?.split_once('=')?;
+    match name.is_empty() || name.contains(['/', '\\']) {
+        true => None,
+        false => Some((name.to_owned(), PathBuf::from(path))),
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct Palette {
-    colours: Vec<Pixel>,
+/// Take the names given to sprites out of the arguments.
+fn names(
+    sprites: Vec<PathBuf>,
+    name: Option<&str>,
+) -> Result<(Vec<PathBuf>, BTreeMap<PathBuf, String>)> {
+    let mut names = BTreeMap::new();
+    let sprites: Vec<PathBuf> = sprites
+        .into_iter()
+        .map(|sprite| match split_name(&sprite) {
+            Some((name, path)) => {
+                names.insert(path.clone(), name);
+                path
+            }
+            None => sprite,
+        })
+        .collect();
+
+    if let Some(name) = name {
+        ensure!(
+            sprites.len() == 1 && names.is_empty(),
+            Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "--name names the only sprite, but {} were given",
+                    sprites.len()
+                )
+            )
+            .with_suggestion("name each sprite with name=path instead")
+        );
+        names.insert(sprites[0].clone(), name.to_owned());
+    }
+
+    Ok((sprites, names))
 }
 
-impl Palette {
-    fn new_from_images(images: &[Image]) -> Self {
-        // iterate over all the pixels and collect the unique ones.
-        let colourset: HashSet<Pixel> = images
+fn run(args: &Args) -> Result<()> {
+    let (sprites, names) = names(args.sprites.clone(), args.name.as_deref())?;
+    let mut options = Options {
+        font: args.font.clone().map(|path| FontOptions {
+            path,
+            glyph: args.glyph,
+            charset: args.charset.clone(),
+            proportional: args.proportional,
+            letter_spacing: args.letter_spacing,
+            codepoint_table: args.codepoint_table,
+            text_renderer: args.emit_text_renderer,
+        }),
+        tilemap: args.map.clone().map(|map| TilemapOptions {
+            map,
+            tileset: args.tileset.clone(),
+            tile: args.tiles,
+            metatile: args.metatiles,
+            flip_tiles: args.flip_tiles,
+        }),
+        tick: args.tick,
+        alpha_plane: args.alpha_plane,
+        shade_levels: args.shade_levels,
+        row_labels: args.row_labels,
+        fields: args.fields,
+        preshift: args.preshift,
+        masked: args.masked,
+        spans: args.spans,
+        compiled_sprites: args.compiled_sprites,
+        frame_stride: args.frame_stride,
+        asset_hashes: args.asset_hashes,
+        cipher: args.xor_key.clone(),
+        json_fingerprint: args.json_fingerprint,
+        histogram: args.histogram,
+        outlines: args.emit_outlines,
+        demo: args
+            .framebuffer
+            .filter(|_| args.emit_demo)
+            .map(|framebuffer| Demo {
+                framebuffer,
+                width: args.framebuffer_width,
+            }),
+        wide_indices: args.wide_indices,
+        index_map: args.index_map.clone(),
+        reserved_indices: args.reserve_indices.unwrap_or_default(),
+        palette_lock: args.palette_lock.clone(),
+        palette_lock_check: args.palette_lock_check,
+        trust_source_palette: args.trust_source_palette,
+        colour_management: match args.ignore_colour_profiles {
+            true => ColourManagement::Ignore,
+            false => ColourManagement::Convert,
+        },
+        respect_exif: args.respect_exif,
+        palette_output: match (args.palette_only, args.no_palette) {
+            (true, _) => PaletteOutput::Only,
+            (_, true) => PaletteOutput::Omitted,
+            _ => PaletteOutput::Included,
+        },
+        merge: args.merge.clone(),
+        emit: args.emit.clone(),
+        template: args.template.clone(),
+        split: args.split,
+        include_directive: args.include_directive,
+        colour_warning: args.warn_colours,
+        max_assets: args.max_assets,
+        max_asset_size: args.max_asset_size,
+        merge_tolerance: args.merge_tolerance,
+        opaque_tolerance: args.opaque_tolerance,
+        table: args.table,
+        table_base: args.table_base.clone(),
+        table_terminator: args.table_terminator,
+        base: args.base,
+        records: args.records,
+        private: args.private.iter().cloned().collect(),
+        priorities: args.priority.iter().cloned().collect(),
+        priority_table: args.priority_table,
+        regions: args.regions.iter().cloned().collect(),
+        nine_patches: args.nine_patch.iter().cloned().collect(),
+        exclude: args.exclude.clone(),
+        include_hidden: args.hidden,
+        strict: args.strict,
+        keep_going: args.keep_going,
+        jobs: args
+            .jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get),
+        max_mem: args.max_mem,
+        names,
+        directives: Directives {
+            byte: args.directive_byte.clone(),
+            word: args.directive_word.clone(),
+            fill: args.directive_fill.clone(),
+        },
+        fill_runs: args.fill_runs,
+        style: Style {
+            hex_case: args.hex_case,
+            indent: args.indent,
+            label_column: args.label_column,
+            comment: args.comment_char,
+        },
+        banner: !args.no_banner,
+        label_style: args.label_style,
+        ..Options::default()
+    };
+
+    // the target is a starting point, which the other flags can still override
+    if let Some(target) = args.target {
+        target.apply(&mut options);
+    }
+    options.format = args.format.unwrap_or(options.format);
+    options.endian = args.endian.unwrap_or(options.endian);
+    options.row_order = args.row_order.unwrap_or(options.row_order);
+    options.row_align = args.row_align;
+    options.align_assets |= args.align_assets;
+
+    if args.estimate_mem {
+        print!("{}", image2arm::estimate_memory(&sprites, &options)?);
+        return Ok(());
+    }
+
+    let mut timings = match args.timings {
+        true => Timings::new(),
+        false => Timings::default(),
+    };
+    let conversion =
+        image2arm::convert_files_timed(&sprites, &options, &Backends::default(), &mut timings)?;
+    let files = conversion.files;
+
+    // checking compares against what's there instead of overwriting it
+    if args.check {
+        let mut stale: Vec<String> = files
             .iter()
-            .flat_map(|image| image.iter().copied())
+            .filter(|file| std::fs::read(&file.name).ok().as_ref() != Some(&file.contents))
+            .map(|file| file.name.clone())
             .collect();
-
-        Palette {
-            colours: colourset.iter().copied().collect(),
+        // the lock is reported like the outputs rather than written
+        if let (Some(lock), Some(contents)) = (&options.palette_lock, &conversion.palette_lock) {
+            if std::fs::read_to_string(lock).ok().as_ref() != Some(contents) {
+                stale.push(lock.display().to_string());
+            }
         }
-    }
-
-    fn to_asm(&self) -> Result<String> {
-        // create a buffer to write into
-        let mut buf = String::new();
-
-        // first define a label for the start of the palette
-        let palette_label: Label = "Palette".into();
-        writeln!(buf, "{}", palette_label)?;
-
-        // now write out the colours
-        for colour in self.colours.iter() {
-            writeln!(
-                buf,
-                "\tDEFB 0x{:02X}, 0x{:02X}, 0x{:02X}, 0x{:02X}",
-                colour.r, colour.g, colour.b, colour.a
-            )?;
+        if args.timings {
+            eprint!("{}", timings);
         }
+        ensure!(
+            stale.is_empty(),
+            Diagnostic::new(
+                FailureClass::Output,
+                format!("out of date: {}", stale.join(", "))
+            )
+            .with_suggestion("run again without --check to regenerate them")
+        );
+        return report_skipped(&conversion.skipped, args.error_format);
+    }
 
-        Ok(buf)
+    let start = Instant::now();
+    for file in files.iter() {
+        std::fs::write(&file.name, &file.contents).map_err(|e| {
+            Diagnostic::new(
+                FailureClass::Output,
+                format!("failed to write output: {}", e),
+            )
+            .with_file(&file.name)
+            .with_suggestion("check that the current directory is writable")
+        })?;
     }
+    image2arm::write_palette_lock(&options, conversion.palette_lock.as_deref())?;
+    timings.record("write", start.elapsed());
+
+    // a summary build systems can record, to know which build they used
+    let written: Vec<&str> = files.iter().map(|file| file.name.as_str()).collect();
+    println!("wrote {}", written.join(", "));
+    println!("fingerprint {}", conversion.fingerprint);
 
-    fn index(&self, colour: &Pixel) -> Option<usize> {
-        self.colours.iter().position(|c| c == colour)
+    if args.timings {
+        eprint!("{}", timings);
     }
 
-    #[inline]
-    fn len(&self) -> usize {
-        self.colours.len()
+    report_skipped(&conversion.skipped, args.error_format)
+}
+
+/// Report each input `--keep-going` skipped, then fail so the run still
+/// exits non-zero, with the exit code of the first failure.
+fn report_skipped(skipped: &[anyhow::Error], format: ErrorFormat) -> Result<()> {
+    let mut class = None;
+    for e in skipped {
+        diagnostic::report(e, format);
+        class = class.or_else(|| {
+            e.chain()
+                .find_map(|e| e.downcast_ref::<Diagnostic>())
+                .map(|d| d.class)
+        });
     }
+
+    ensure!(
+        skipped.is_empty(),
+        Diagnostic::new(
+            class.unwrap_or(FailureClass::Internal),
+            format!("skipped {} input(s) which failed to load", skipped.len())
+        )
+        .with_suggestion("fix or remove them, the other inputs were still converted")
+    );
+    Ok(())
 }
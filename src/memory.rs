@@ -0,0 +1,153 @@
+//! Working out how much memory a conversion needs before starting it, from
+//! the sizes in the inputs' headers, so one too big for the machine can be
+//! refused rather than killed partway through.
+
+use crate::encoding::PixelFormat;
+use crate::{archive, aseprite, input, Options};
+use anyhow::Result;
+use log::debug;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// The characters of output written for each byte of data, like `0xNN, `.
+const TEXT_PER_BYTE: u64 = 6;
+
+/// The memory a conversion is expected to need at its peak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryEstimate {
+    /// Every decoded image, all of which are held until the output is written.
+    pub images: u64,
+    /// The buffers of the inputs being decoded at once, on top of the images.
+    pub decoding: u64,
+    /// The packed pixels and the text of the output.
+    pub output: u64,
+}
+
+impl MemoryEstimate {
+    pub fn total(&self) -> u64 {
+        self.images + self.decoding + self.output
+    }
+}
+
+impl fmt::Display for MemoryEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<12} {:>10}", "images", size(self.images))?;
+        writeln!(f, "{:<12} {:>10}", "decoding", size(self.decoding))?;
+        writeln!(f, "{:<12} {:>10}", "output", size(self.output))?;
+        writeln!(f, "{:<12} {:>10}", "total", size(self.total()))
+    }
+}
+
+/// Estimate the memory converting the inputs needs, reading only the
+/// headers of the images. Directories are scanned as for [`crate::convert`].
+pub fn estimate_memory<P: AsRef<Path>>(inputs: &[P], options: &Options) -> Result<MemoryEstimate> {
+    Ok(estimate(&input::expand(inputs, options)?, options))
+}
+
+pub(crate) fn estimate(inputs: &[PathBuf], options: &Options) -> MemoryEstimate {
+    let mut pixels = 0;
+    let mut buffers = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let images = match images(input) {
+            Ok(images) => images,
+            // the input fails to load later with a better error
+            Err(e) => {
+                debug!("couldn't read the size of {}: {:#}", input.display(), e);
+                Input::default()
+            }
+        };
+        pixels += images.pixels;
+        buffers.push(images.buffer);
+    }
+
+    // the biggest inputs might all be decoded at the same time
+    buffers.sort_unstable_by(|a, b| b.cmp(a));
+    let bytes_per_pixel = match options.format {
+        PixelFormat::Indexed if options.wide_indices => 2,
+        format => format.bytes_per_pixel().unwrap_or(1) as u64,
+    };
+    MemoryEstimate {
+        images: 4 * pixels,
+        decoding: buffers.iter().take(options.jobs.max(1)).sum(),
+        output: bytes_per_pixel * pixels * (1 + TEXT_PER_BYTE),
+    }
+}
+
+/// The pixels in every image an input holds, and the most memory it needs
+/// while being decoded beyond the images themselves.
+#[derive(Debug, Clone, Copy, Default)]
+struct Input {
+    pixels: u64,
+    buffer: u64,
+}
+
+fn images(path: &Path) -> Result<Input> {
+    // archives and Aseprite files are read whole before being decoded
+    let length = std::fs::metadata(path)?.len();
+    if archive::is_zip(path) {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let mut input = Input::default();
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            let entry_path = PathBuf::from(entry.name());
+            if entry.is_dir() || archive::is_metadata(entry.name()) || !input::is_image(&entry_path)
+            {
+                continue;
+            }
+            let size = entry.size();
+            let pixels = header_pixels(&mut entry, aseprite::is_aseprite(&entry_path))?;
+            input.pixels += pixels;
+            input.buffer = input.buffer.max(size + 4 * pixels);
+        }
+        return Ok(input);
+    }
+
+    let is_aseprite = aseprite::is_aseprite(path);
+    let pixels = header_pixels(&mut File::open(path)?, is_aseprite)?;
+    Ok(Input {
+        pixels,
+        // a PNG is decoded into a buffer which is then copied
+        buffer: match is_aseprite {
+            true => length + 4 * pixels,
+            false => 4 * pixels,
+        },
+    })
+}
+
+/// The pixels in a PNG, or in every frame of an Aseprite file, from its header.
+fn header_pixels(reader: &mut impl Read, is_aseprite: bool) -> Result<u64> {
+    let mut header = [0; 24];
+    match is_aseprite {
+        true => {
+            reader.read_exact(&mut header[..12])?;
+            let field = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]) as u64;
+            Ok(field(6) * field(8) * field(10))
+        }
+        false => {
+            reader.read_exact(&mut header)?;
+            let field = |at: usize| {
+                u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+                    as u64
+            };
+            Ok(field(16) * field(20))
+        }
+    }
+}
+
+/// A number of bytes in the biggest unit it makes at least one of.
+pub(crate) fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
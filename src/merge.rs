@@ -0,0 +1,333 @@
+//! Reading back a previous output so new sprites can be added to it without
+//! touching the assets it already holds.
+
+use crate::cipher::Cipher;
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::encoding::Endian;
+use crate::image::Pixel;
+use crate::output::PackedAsset;
+use crate::palette::parse_colour;
+use anyhow::Result;
+use log::warn;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The parts of a previous output which are carried into the new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Existing {
+    /// The palette, in index order.
+    pub palette: Vec<Pixel>,
+    /// The index of the palette's first colour, after any reserved ones.
+    pub palette_offset: usize,
+    pub bits_per_colour: usize,
+    /// What the data is scrambled with, read back from its constants.
+    pub cipher: Option<Cipher>,
+    /// Each asset in address table order. Their sizes are lost.
+    pub assets: Vec<PackedAsset>,
+}
+
+impl Existing {
+    /// Read a previous output, written with `directive` for its bytes.
+    pub fn load(path: &Path, directive: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            Diagnostic::new(
+                FailureClass::Input,
+                format!("failed to read the file to merge into: {}", e),
+            )
+            .with_file(path)
+            .with_suggestion("check that the path exists and is readable")
+        })?;
+
+        let existing = Existing::parse(&text, directive).map_err(|reason| {
+            Diagnostic::new(FailureClass::Input, reason)
+                .with_file(path)
+                .with_suggestion("only indexed output written by image2arm can be merged into")
+        })?;
+
+        Ok(existing)
+    }
+
+    pub fn parse(text: &str, directive: &str) -> Result<Self, String> {
+        // the style options change the whitespace, so only the words are read
+        let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+
+        // every block is a label followed by rows of bytes
+        let mut blocks: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (i, line) in lines.iter().enumerate() {
+            if line.is_empty() || line.starts_with(char::is_whitespace) {
+                continue;
+            }
+            let rows: Vec<&str> = lines[i + 1..]
+                .iter()
+                .take_while(|row| data_row(row, directive).is_some() || fill_row(row).is_some())
+                .copied()
+                .collect();
+            if !rows.is_empty() {
+                blocks.insert(line.trim(), rows);
+            }
+        }
+
+        let palette = match blocks.get("Palette") {
+            Some(rows) => rows,
+            None => {
+                return Err(match lines.iter().position(|line| *line == "Palette") {
+                    Some(i) => format!(
+                        "the palette's first row '{}' isn't a row of {}",
+                        lines.get(i + 1).map_or("", |row| row.trim()),
+                        directive
+                    ),
+                    None => "there is no palette".to_owned(),
+                })
+            }
+        };
+        let palette = palette
+            .iter()
+            .map(|row| {
+                let values = data_row(row, directive)
+                    .ok_or_else(|| format!("'{}' isn't a row of the palette", row.trim()))?;
+                let hex: String = values
+                    .split(',')
+                    .map(|byte| byte.trim().trim_start_matches("0x"))
+                    .collect();
+                parse_colour(&hex)
+            })
+            .collect::<Result<Vec<Pixel>, String>>()?;
+
+        let bits_per_colour =
+            constant(&lines, "bits_per_colour").ok_or("there is no bits_per_colour constant")?;
+        let bits_per_colour = bits_per_colour.parse().map_err(|_| {
+            format!(
+                "the bits_per_colour constant '{}' isn't a number",
+                bits_per_colour
+            )
+        })?;
+        // files written without reserved indices have no offset
+        let palette_offset = constant(&lines, "PALETTE_OFFSET")
+            .map_or(Ok(0), str::parse)
+            .map_err(|_| "the PALETTE_OFFSET constant isn't a number")?;
+        let cipher = Cipher::from_constants(|name| constant(&lines, name));
+
+        let table = lines
+            .iter()
+            .position(|line| *line == "AssetAddressTable")
+            .ok_or("there is no asset address table")?;
+        let mut assets = Vec::new();
+        for line in lines[table + 1..].iter() {
+            if *line == "AssetAddressTableEnd" {
+                break;
+            }
+            // entries are named after their asset, whatever the table holds
+            let label = line
+                .split_whitespace()
+                .next()
+                .and_then(|entry| entry.strip_prefix("_ADR"))
+                .ok_or_else(|| format!("'{}' isn't an asset table entry", line))?;
+            let data = blocks
+                .get(label)
+                .ok_or_else(|| format!("there is no data for {}", label))?;
+
+            // an alpha plane belongs with its asset, as does an odd field
+            let block = |suffix: &str| -> Result<Option<Vec<u8>>, String> {
+                blocks
+                    .get(format!("{}{}", label, suffix).as_str())
+                    .map(|rows| bytes(rows, directive))
+                    .transpose()
+            };
+            let mut data = bytes(data, directive)?;
+            let mut alpha = block("_Alpha")?;
+            let odd_field = match block("_Odd")? {
+                Some(odd) => {
+                    let odd_field = (data.len(), alpha.as_ref().map_or(0, Vec::len));
+                    data.extend(odd);
+                    if let (Some(alpha), Some(odd)) = (alpha.as_mut(), block("_Alpha_Odd")?) {
+                        alpha.extend(odd);
+                    }
+                    Some(odd_field)
+                }
+                None => None,
+            };
+
+            // the priority came from the options, so is only found in its constant
+            let priority = constant(&lines, &format!("PRIORITY{}", label))
+                .and_then(|priority| priority.parse().ok());
+
+            assets.push(PackedAsset {
+                name: label.trim_start_matches('_').to_owned(),
+                label: label.to_owned(),
+                width: None,
+                height: None,
+                data,
+                alpha,
+                private: false,
+                priority,
+                odd_field,
+                spans: None,
+                mask: None,
+                histogram: None,
+                outline: None,
+            });
+        }
+
+        // everything else is regenerated from the options, which may not match
+        if lines.iter().any(|line| {
+            line.starts_with("FONT_") || line.starts_with("MAP_") || line.starts_with("ANIM_")
+        }) {
+            warn!("fonts, maps and animations aren't carried over when merging, only sprites");
+        }
+
+        Ok(Existing {
+            palette,
+            palette_offset,
+            bits_per_colour,
+            cipher,
+            assets,
+        })
+    }
+}
+
+/// The directive a file writes its bytes with, given away by the first row
+/// of its palette.
+pub(crate) fn byte_directive(text: &str) -> &str {
+    text.lines()
+        .skip_while(|line| line.trim_end() != "Palette")
+        .nth(1)
+        .and_then(|row| row.split_whitespace().next())
+        .unwrap_or_default()
+}
+
+/// The value of the constant called `name`, written as the name, `EQU` and
+/// the value with any whitespace between them.
+fn constant<'a>(lines: &[&'a str], name: &str) -> Option<&'a str> {
+    lines.iter().find_map(|line| {
+        let mut words = line.strip_prefix(name)?.splitn(2, "EQU");
+        match (words.next()?, words.next()?) {
+            (padding, value)
+                if padding.trim().is_empty() && value.starts_with(char::is_whitespace) =>
+            {
+                Some(value.trim())
+            }
+            _ => None,
+        }
+    })
+}
+
+/// The values of an indented row of `directive`, however it is indented.
+fn data_row<'a>(row: &'a str, directive: &str) -> Option<&'a str> {
+    if !row.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let (word, values) = row.trim().split_once(char::is_whitespace)?;
+    (word == directive).then(|| values.trim())
+}
+
+/// Read back the bytes in rows of `directive`, and in the rows filling runs
+/// of bytes between them.
+fn bytes(rows: &[&str], directive: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for row in rows {
+        let values = match (data_row(row, directive), fill_row(row)) {
+            (Some(values), _) => values,
+            (None, Some((count, value))) => {
+                bytes.resize(bytes.len() + count, value);
+                continue;
+            }
+            (None, None) => return Err(format!("'{}' isn't a row of bytes", row.trim())),
+        };
+        for byte in values.split(',') {
+            let byte = byte.trim();
+            bytes.push(
+                u8::from_str_radix(byte.trim_start_matches("0x"), 16)
+                    .map_err(|_| format!("'{}' in the row '{}' isn't a byte", byte, row.trim()))?,
+            );
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Read a row filling a run of bytes, like `DEFS 16, 0x00`, as the count and
+/// the byte, whatever the fill directive is.
+pub(crate) fn fill_row(row: &str) -> Option<(usize, u8)> {
+    let (_, values) = row.trim().split_once(char::is_whitespace)?;
+    let (count, value) = values.split_once(',')?;
+    let value = value.trim().strip_prefix("0x")?;
+    Some((
+        count.trim().parse().ok()?,
+        u8::from_str_radix(value, 16).ok()?,
+    ))
+}
+
+/// The fields of an asset record written by `--records`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Record {
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_pixel: u32,
+}
+
+/// Every constant with a number for its value, by name.
+pub(crate) fn constants(text: &str) -> HashMap<&str, usize> {
+    // constants are a name, EQU and a number, with the name padded to a column
+    let mut constants = HashMap::new();
+    for line in text
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+    {
+        let mut words = line.split_whitespace();
+        if let (Some(name), Some("EQU"), Some(value)) = (words.next(), words.next(), words.next()) {
+            if let Ok(value) = value.parse() {
+                constants.insert(name, value);
+            }
+        }
+    }
+    constants
+}
+
+/// Each asset's record, in table order, or none if the file has no records.
+pub(crate) fn records(text: &str, endian: Endian) -> Result<Vec<Record>, String> {
+    let rows: Vec<&str> = text
+        .lines()
+        .skip_while(|line| line.trim_end() != "AssetRecordTable")
+        .skip(1)
+        .take_while(|line| line.trim_end() != "AssetRecordTableEnd")
+        .collect();
+
+    // the words are written as words or as their bytes
+    let bytes = |row: &str| -> Result<[u8; 4], String> {
+        let values = row
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, values)| values)
+            .split(',')
+            .map(|value| {
+                let value = value.trim();
+                u32::from_str_radix(value.trim_start_matches("0x"), 16)
+                    .map_err(|_| format!("'{}' isn't a number", value))
+            })
+            .collect::<Result<Vec<u32>, String>>()?;
+        match (values.as_slice(), endian) {
+            ([word], Endian::Little) => Ok(word.to_le_bytes()),
+            ([word], Endian::Big) => Ok(word.to_be_bytes()),
+            ([a, b, c, d], _) => Ok([*a as u8, *b as u8, *c as u8, *d as u8]),
+            _ => Err(format!("'{}' isn't a word", row)),
+        }
+    };
+    let half = |bytes: [u8; 2]| match endian {
+        Endian::Little => u16::from_le_bytes(bytes) as u32,
+        Endian::Big => u16::from_be_bytes(bytes) as u32,
+    };
+
+    // the width comes first in memory, and the bits per pixel are a byte
+    rows.chunks(4)
+        .map(|record| {
+            let (size, bpp) = match record {
+                [_, _, size, bpp] => (bytes(size.trim())?, bytes(bpp.trim())?),
+                _ => return Err("an asset record is cut short".to_owned()),
+            };
+            Ok(Record {
+                width: half([size[0], size[1]]),
+                height: half([size[2], size[3]]),
+                bits_per_pixel: bpp[0] as u32,
+            })
+        })
+        .collect()
+}
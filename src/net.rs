@@ -0,0 +1,56 @@
+//! Downloading inputs given as URLs, for pipelines where the art lives in an
+//! artifact store rather than the repository.
+
+use crate::diagnostic::{Diagnostic, FailureClass};
+use anyhow::Result;
+use log::info;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Download the file at `url` into the temporary directory, keeping its file
+/// name so the asset is named after it. Each URL always downloads to the same
+/// place, so repeated runs overwrite rather than pile up.
+pub(crate) fn fetch(url: &str) -> Result<PathBuf> {
+    let error = |reason: String| {
+        Diagnostic::new(FailureClass::Input, reason)
+            .with_file(url)
+            .with_suggestion("check that the URL is right and the server is reachable")
+    };
+
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| error("the URL doesn't end in a file name".into()))?;
+
+    let mut bytes = Vec::new();
+    ureq::get(url)
+        .call()
+        .map_err(|e| error(format!("failed to download: {}", e)))?
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .map_err(|e| error(format!("failed to download: {}", e)))?;
+
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    let dir = std::env::temp_dir()
+        .join("image2arm")
+        .join(format!("{:016x}", hasher.finish()));
+    let path = dir.join(name);
+    std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(&path, &bytes))
+        .map_err(|e| {
+            Diagnostic::new(
+                FailureClass::Output,
+                format!("failed to save the download: {}", e),
+            )
+            .with_file(&path)
+            .with_suggestion("check that the temporary directory is writable")
+        })?;
+
+    info!("downloaded {} bytes from {}", bytes.len(), url);
+    Ok(path)
+}
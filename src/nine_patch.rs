@@ -0,0 +1,140 @@
+//! Splitting a panel into its corners, edges and centre, so the edges and
+//! centre can be repeated on the target to draw the panel at any size.
+
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::image::Image;
+use crate::output::Packed;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// The names the regions are given after the panel's, from the top left
+/// corner across each row.
+pub(crate) const REGIONS: [&str; 9] = [
+    "top_left",
+    "top",
+    "top_right",
+    "left",
+    "centre",
+    "right",
+    "bottom_left",
+    "bottom",
+    "bottom_right",
+];
+
+/// How far the corners reach in from each side of a panel, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NinePatch {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl NinePatch {
+    /// The names of the regions of the panel with the given name.
+    pub(crate) fn regions(name: &str) -> impl Iterator<Item = String> + '_ {
+        REGIONS
+            .iter()
+            .map(move |region| format!("{}_{}", name, region))
+    }
+
+    /// The constants describing the panel with the given name, as names and
+    /// values: the index of its first region and its insets.
+    pub(crate) fn constants(&self, name: &str, packed: &Packed) -> Vec<(String, String)> {
+        let symbol: String = name
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect();
+        let first = NinePatch::regions(name).next().unwrap_or_default();
+
+        vec![
+            ("FIRST", format!("ASSET{}", packed.label(&first))),
+            ("LEFT", self.left.to_string()),
+            ("TOP", self.top.to_string()),
+            ("RIGHT", self.right.to_string()),
+            ("BOTTOM", self.bottom.to_string()),
+        ]
+        .into_iter()
+        .map(|(field, value)| (format!("PATCH_{}_{}", symbol, field), value))
+        .collect()
+    }
+
+    /// Cut an image into its nine regions, every one of which needs to be at
+    /// least a pixel in each direction.
+    pub(crate) fn split(&self, image: &Image) -> Result<Vec<Image>, Diagnostic> {
+        let (width, height) = (image.width(), image.height());
+        let fits = |low: u32, high: u32, size: u32| {
+            low > 0 && high > 0 && low.checked_add(high).is_some_and(|sum| sum < size)
+        };
+        if !fits(self.left, self.right, width) || !fits(self.top, self.bottom, height) {
+            let reason = format!(
+                "the insets {},{},{},{} leave a region of the {}x{} nine-patch {} empty",
+                self.left,
+                self.top,
+                self.right,
+                self.bottom,
+                width,
+                height,
+                image.name()
+            );
+            return Err(Diagnostic::new(FailureClass::Usage, reason).with_suggestion(
+                "make every inset at least 1, with each pair of opposite insets less than the size",
+            ));
+        }
+
+        let columns = [
+            (0, self.left),
+            (self.left, width - self.left - self.right),
+            (width - self.right, self.right),
+        ];
+        let rows = [
+            (0, self.top),
+            (self.top, height - self.top - self.bottom),
+            (height - self.bottom, self.bottom),
+        ];
+        let regions = rows
+            .iter()
+            .flat_map(|row| columns.iter().map(move |column| (column, row)))
+            .zip(NinePatch::regions(image.name()))
+            .map(|(((x, width), (y, height)), name)| image.crop(name, *x, *y, *width, *height))
+            .collect();
+
+        Ok(regions)
+    }
+}
+
+impl FromStr for NinePatch {
+    type Err = String;
+
+    /// Parse the insets as `LEFT,TOP,RIGHT,BOTTOM`, or one inset for all four.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let insets = s
+            .split(',')
+            .map(|inset| inset.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid inset in '{}': {}", s, e))?;
+
+        match insets[..] {
+            [inset] => Ok(NinePatch {
+                left: inset,
+                top: inset,
+                right: inset,
+                bottom: inset,
+            }),
+            [left, top, right, bottom] => Ok(NinePatch {
+                left,
+                top,
+                right,
+                bottom,
+            }),
+            _ => Err(format!(
+                "expected LEFT,TOP,RIGHT,BOTTOM or one inset, found '{}'",
+                s
+            )),
+        }
+    }
+}
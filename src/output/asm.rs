@@ -0,0 +1,525 @@
+//! The assembly file, which is the only output holding fonts and maps.
+
+use super::{compiled, demo};
+use super::{
+    AssetTable, OutputBackend, OutputFile, Packed, PaletteOutput, Span, TableTerminator,
+    MASK_WORD_BYTES, PRIORITY_TABLE_LABEL,
+};
+use crate::encoding::{Encoding, Endian, PixelFormat};
+use crate::Options;
+use anyhow::Result;
+use log::info;
+use std::fmt::Write as FmtWrite;
+
+/// The size of each entry of the record table.
+const RECORD_BYTES: usize = 16;
+
+const FILE_HEADER: &str = r"; ###########################################################
+;              _    ____ ____  _____ _____ ____  
+;             / \  / ___/ ___|| ____|_   _/ ___| 
+;            / _ \ \___ \___ \|  _|   | | \___ \ 
+;           / ___ \ ___) |__) | |___  | |  ___) |
+;          /_/   \_\____/____/|_____| |_| |____/ 
+; ###########################################################";
+
+/// Writes armasm, with `DEFB`s (or the configured directives) for data and
+/// `EQU`s for constants.
+pub struct Armasm;
+
+impl OutputBackend for Armasm {
+    fn name(&self) -> &str {
+        "asm"
+    }
+
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>> {
+        let mut asm = String::new();
+        let mut parts = Vec::new();
+        write_assets(&mut asm, &mut parts, packed)?;
+
+        // a split output's parts follow its main file
+        let main = OutputFile {
+            name: packed.file_name().to_owned(),
+            contents: asm.into_bytes(),
+        };
+        if let Some(demo) = packed.options.demo {
+            parts.push(OutputFile {
+                name: super::part_name(packed.file_name(), "demo"),
+                contents: demo::program(packed, demo)?.into_bytes(),
+            });
+        }
+        let style = &packed.options.style;
+        Ok(std::iter::once(main)
+            .chain(parts)
+            .map(|file| OutputFile {
+                contents: style
+                    .apply(&String::from_utf8_lossy(&file.contents), ';')
+                    .into_bytes(),
+                ..file
+            })
+            .collect())
+    }
+}
+
+fn write_assets(
+    file: &mut impl FmtWrite,
+    parts: &mut Vec<OutputFile>,
+    packed: &Packed,
+) -> Result<()> {
+    let options = &packed.options;
+    let palette = &packed.palette;
+    let animations = packed.animations();
+    let font = packed.font.as_ref();
+    let tilemap = packed.tilemap.as_ref();
+
+    // write the file header
+    if options.banner {
+        writeln!(file, "{}\n", FILE_HEADER)?;
+    }
+    if let Some(base) = options.base {
+        writeln!(file, "\tORG 0x{:08X}\n", base)?;
+    }
+
+    let encoding = packed.encoding();
+
+    // direct colour has no use for the palette
+    let indexed = encoding.format == PixelFormat::Indexed;
+    if indexed && options.palette_output != PaletteOutput::Omitted {
+        writeln!(file, "{}", palette.to_asm(&encoding.directives.byte)?)?;
+        // the palette is loaded after the reserved registers
+        if options.reserved_indices > 0 {
+            writeln!(file, "PALETTE_OFFSET\tEQU {}\n", options.reserved_indices)?;
+        }
+        if options.shade_levels > 0 {
+            writeln!(
+                file,
+                "{}",
+                palette.shades_asm(options.shade_levels, &encoding.directives.byte)?
+            )?;
+        }
+    }
+    if options.palette_output == PaletteOutput::Only {
+        return Ok(());
+    }
+
+    if indexed {
+        writeln!(file, "bits_per_colour\tEQU {}", encoding.bits_per_colour)?;
+        match encoding.pixels_per_byte {
+            Some(pixels_per_byte) => {
+                info!(
+                    "{} colours need {} bits per colour, packing {} pixels per byte",
+                    palette.len(),
+                    encoding.bits_per_colour,
+                    pixels_per_byte
+                );
+                writeln!(file, "pixels_per_byte\tEQU {}\n", pixels_per_byte)?;
+            }
+            // halfword indices are written like direct colour pixels
+            None => {
+                info!(
+                    "{} colours need {} bits per colour, writing an index per halfword",
+                    palette.len(),
+                    encoding.bits_per_colour
+                );
+                writeln!(file, "bytes_per_pixel\tEQU 2\n")?;
+            }
+        }
+    }
+    // extract reads the alpha planes back by this
+    if let Some(plane) = options.alpha_plane {
+        writeln!(file, "bits_per_alpha\tEQU {}\n", plane.bits())?;
+    }
+    if let Some(cipher) = &options.cipher {
+        for (name, value) in cipher.constants() {
+            writeln!(file, "{}\tEQU {}", name, value)?;
+        }
+        writeln!(file)?;
+    }
+
+    // write out the assets
+    for asset in packed.assets() {
+        let mut asm = String::new();
+        let (odd, alpha_odd) = asset.odd_field.unzip();
+        match &asset.mask {
+            Some(mask) => asm.push_str(&compiled::routine(packed, asset, mask)?),
+            None => write_fields(&mut asm, &encoding, &asset.label, &asset.data, odd)?,
+        }
+        if let Some(alpha) = &asset.alpha {
+            let label = format!("{}_Alpha", asset.label);
+            write_fields(&mut asm, &encoding, &label, alpha, alpha_odd)?;
+        }
+
+        if options.align_assets {
+            writeln!(file, "ALIGN")?;
+        }
+        write_part(file, parts, options, &asset.name, &asm)?;
+    }
+
+    // the font's glyphs come straight after the sprites
+    if let Some(font) = font {
+        write_part(file, parts, options, "font", &font.to_asm(&encoding)?)?;
+    }
+
+    if let Some(tilemap) = tilemap {
+        write_part(file, parts, options, "tiles", &tilemap.to_asm(&encoding)?)?;
+    }
+
+    // the address table must be aligned
+    writeln!(file, "ALIGN\n")?;
+
+    if let Some(font) = font {
+        writeln!(file, "{}", font.tables_asm(&encoding)?)?;
+    }
+
+    if let Some(tilemap) = tilemap {
+        writeln!(file, "{}", tilemap.layers_asm(&encoding)?)?;
+    }
+
+    // write out the asset address table
+    let aatable = super::TABLE_LABEL;
+    let aaprefix = "_ADR";
+    // the terminators sit outside the table, so ASSET_MAX still counts assets
+    if options.table_terminator == Some(TableTerminator::Length) {
+        writeln!(file, "{}Length", aatable)?;
+        writeln!(
+            file,
+            "\t{}\t{}",
+            encoding.directives.word,
+            packed.table().count()
+        )?;
+    }
+    writeln!(file, "{}", aatable)?;
+    let addresses = options.base.map(|base| packed.addresses(base));
+    for (index, asset) in packed.table() {
+        let entry = match &addresses {
+            Some(addresses) => format!("0x{:08X}", addresses[index]),
+            None => packed.table_entry(&asset.label),
+        };
+        writeln!(
+            file,
+            "{}{:<28}{}\t{}",
+            aaprefix, asset.label, encoding.directives.word, entry
+        )?;
+    }
+    writeln!(file, "{}End", aatable)?;
+    if options.table_terminator == Some(TableTerminator::Zero) {
+        writeln!(file, "\t{}\t0", encoding.directives.word)?;
+    }
+
+    if options.records {
+        write_records(file, packed, addresses.as_deref())?;
+    }
+    if options.row_labels {
+        write_row_tables(file, packed, addresses.as_deref())?;
+    }
+    // the renderer walks the table from the back to the front
+    if options.priority_table {
+        writeln!(file, "\n{}", PRIORITY_TABLE_LABEL)?;
+        for asset in packed.by_priority() {
+            writeln!(file, "\t{}\tASSET{}", encoding.directives.word, asset.label)?;
+        }
+        writeln!(file, "{}End", PRIORITY_TABLE_LABEL)?;
+    }
+
+    // a table of the shifted copies picks one by the pixel within the byte
+    for asset in packed.assets() {
+        let copies = packed.shifted_copies(asset);
+        if copies.is_empty() {
+            continue;
+        }
+        writeln!(file, "\n{}_Shifts", asset.label)?;
+        for copy in copies {
+            writeln!(file, "\t{}\t{}", encoding.directives.word, copy.label)?;
+        }
+    }
+
+    // the strips of a split image go from the top down
+    for strips in packed.strips.iter() {
+        writeln!(file, "\n{}_Strips", strips.label)?;
+        for name in strips.names() {
+            let label = packed.label(&name);
+            writeln!(file, "\t{}\t{}", encoding.directives.word, label)?;
+        }
+    }
+
+    // a zero word ends each row's spans
+    for asset in packed.assets() {
+        if let Some(spans) = &asset.spans {
+            writeln!(file, "\n{}_Spans", asset.label)?;
+            for row in spans {
+                let words: Vec<u32> = row.iter().map(Span::word).chain([0]).collect();
+                writeln!(file, "\t{}", encoding.words(&words))?;
+            }
+        }
+    }
+
+    // the outlines are laid out like a one bit alpha plane
+    for asset in packed.assets() {
+        if let Some(outline) = &asset.outline {
+            writeln!(file)?;
+            write_fields(
+                file,
+                &encoding,
+                &format!("{}_Outline", asset.label),
+                outline,
+                None,
+            )?;
+        }
+    }
+
+    // the frame durations sit alongside the frames they time
+    for animation in animations.iter() {
+        if let Some(durations) = animation.durations_asm(options.tick, &encoding)? {
+            writeln!(file, "\n{}", durations.trim_end())?;
+        }
+    }
+
+    // write out a constant for the number of assets in the table
+    writeln!(file, "\nASSET_MAX\tEQU\t({0}End - {0}) / 4\n", aatable)?;
+
+    // write out the asset table
+    for (_, asset) in packed.table() {
+        writeln!(
+            file,
+            "ASSET{:<27}EQU\t({}{:<24} - {}) / 4",
+            asset.label, aaprefix, asset.label, aatable
+        )?;
+    }
+
+    // aligned rows are a whole number of bytes apart
+    if options.row_align.is_some() {
+        file.write_char('\n')?;
+        for (_, asset) in packed.table() {
+            if let Some(stride) = packed.stride(asset) {
+                writeln!(file, "STRIDE{:<26}EQU\t{}", asset.label, stride)?;
+            }
+        }
+    }
+
+    if options.asset_hashes {
+        file.write_char('\n')?;
+        for (_, asset) in packed.table() {
+            if let Some(hash) = packed.hash(asset) {
+                writeln!(file, "HASH{:<28}EQU\t0x{:08X}", asset.label, hash)?;
+            }
+        }
+    }
+
+    if packed.table().any(|(_, asset)| asset.priority.is_some()) {
+        file.write_char('\n')?;
+    }
+    for (_, asset) in packed.table() {
+        if let Some(priority) = asset.priority {
+            writeln!(file, "PRIORITY{:<24}EQU\t{}", asset.label, priority)?;
+        }
+    }
+
+    // each pair is a mask word to AND with the screen then a data word to OR
+    if options.masked {
+        writeln!(file, "\nMASK_OFFSET\tEQU 0")?;
+        writeln!(file, "DATA_OFFSET\tEQU {}", MASK_WORD_BYTES)?;
+        writeln!(file, "PAIR_BYTES\tEQU {}", 2 * MASK_WORD_BYTES)?;
+        for asset in packed.assets() {
+            if let Some(pairs) = packed.mask_pairs(asset) {
+                writeln!(file, "PAIRS{:<27}EQU\t{}", asset.label, pairs)?;
+            }
+        }
+    }
+
+    if !packed.strips.is_empty() {
+        file.write_char('\n')?;
+    }
+    for strips in packed.strips.iter() {
+        writeln!(file, "STRIPS{:<26}EQU\t{}", strips.label, strips.count)?;
+        writeln!(file, "STRIP_ROWS{:<22}EQU\t{}", strips.label, strips.rows)?;
+    }
+
+    if options.row_labels {
+        for asset in packed.assets() {
+            let rows = packed.row_offsets(asset).unwrap_or_default();
+            if !rows.is_empty() {
+                file.write_char('\n')?;
+            }
+            for (row, offset) in rows.into_iter().enumerate() {
+                let label = format!("{}_Row{}", asset.label, row);
+                writeln!(file, "{:<32}EQU\t{} + {}", label, asset.label, offset)?;
+            }
+        }
+    }
+
+    if let Some(addresses) = &addresses {
+        file.write_char('\n')?;
+        for (index, asset) in packed.table() {
+            writeln!(
+                file,
+                "ADDR{:<28}EQU\t0x{:08X}",
+                asset.label, addresses[index]
+            )?;
+        }
+    }
+
+    // animations refer to the constants for their frames so must come after them
+    if !animations.is_empty() {
+        file.write_char('\n')?;
+    }
+    for animation in animations.iter() {
+        write!(file, "{}", animation.to_asm(packed)?)?;
+    }
+
+    // a nine-patch's regions follow each other in the table from the top left
+    for (name, patch) in options.nine_patches.iter() {
+        file.write_char('\n')?;
+        for (constant, value) in patch.constants(name, packed) {
+            writeln!(file, "{}\tEQU\t{}", constant, value)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a record per asset after the address table, holding its pointer,
+/// size in bytes, width, height and bits per pixel in a fixed layout, with
+/// constants for where each field is.
+fn write_records(
+    file: &mut impl FmtWrite,
+    packed: &Packed,
+    addresses: Option<&[u32]>,
+) -> Result<()> {
+    let encoding = packed.encoding();
+    let bits_per_pixel = match encoding.format.bytes_per_pixel() {
+        None => encoding.bits_per_colour as u32,
+        Some(bytes) => 8 * bytes as u32,
+    };
+    // the half words and byte sit at the start of their words in memory
+    let big = encoding.endian == Endian::Big;
+    let halves = |low: u32, high: u32| match big {
+        false => low | high << 16,
+        true => low << 16 | high,
+    };
+    let byte = |byte: u32| match big {
+        false => byte,
+        true => byte << 24,
+    };
+
+    writeln!(file, "\nRECORD_POINTER\tEQU 0")?;
+    writeln!(file, "RECORD_SIZE\tEQU 4")?;
+    writeln!(file, "RECORD_WIDTH\tEQU 8")?;
+    writeln!(file, "RECORD_HEIGHT\tEQU 10")?;
+    writeln!(file, "RECORD_BPP\tEQU 12")?;
+    writeln!(file, "RECORD_BYTES\tEQU {}\n", RECORD_BYTES)?;
+
+    writeln!(file, "AssetRecordTable")?;
+    for (index, asset) in packed.table() {
+        // the pointer matches the address table's entry
+        match addresses {
+            Some(addresses) => writeln!(file, "\t{}", encoding.words(&[addresses[index]]))?,
+            None => writeln!(
+                file,
+                "\t{}\t{}",
+                encoding.directives.word,
+                packed.table_entry(&asset.label)
+            )?,
+        }
+        let size = asset.data.len() as u32;
+        let (width, height) = (asset.width.unwrap_or(0), asset.height.unwrap_or(0));
+        writeln!(file, "\t{}", encoding.words(&[size]))?;
+        writeln!(file, "\t{}", encoding.words(&[halves(width, height)]))?;
+        writeln!(file, "\t{}", encoding.words(&[byte(bits_per_pixel)]))?;
+    }
+    writeln!(file, "AssetRecordTableEnd")?;
+
+    Ok(())
+}
+
+/// Write an asset's bytes under its label, with a second label where the odd
+/// field starts if it is split into fields.
+fn write_fields(
+    asm: &mut impl FmtWrite,
+    encoding: &Encoding,
+    label: &str,
+    bytes: &[u8],
+    odd: Option<usize>,
+) -> Result<()> {
+    let (even, odd) = match odd {
+        Some(odd) => {
+            let (even, odd) = bytes.split_at(odd);
+            (even, Some(odd))
+        }
+        None => (bytes, None),
+    };
+    writeln!(asm, "{}", label)?;
+    encoding.write_bytes(asm, even)?;
+    if let Some(odd) = odd {
+        writeln!(asm, "{}_Odd", label)?;
+        encoding.write_bytes(asm, odd)?;
+    }
+
+    Ok(())
+}
+
+/// Write a table per asset with where each of its rows starts, from the top
+/// row down, so a row's address is a load away. The entries are relative to
+/// the asset with `--table offsets`.
+fn write_row_tables(
+    file: &mut impl FmtWrite,
+    packed: &Packed,
+    addresses: Option<&[u32]>,
+) -> Result<()> {
+    let encoding = packed.encoding();
+    for (index, asset) in packed.assets().iter().enumerate() {
+        let rows = match packed.row_offsets(asset) {
+            Some(rows) => rows,
+            None => continue,
+        };
+
+        writeln!(file, "\n{}_Rows", asset.label)?;
+        match (addresses, packed.options.table) {
+            (Some(addresses), _) => {
+                for offset in rows {
+                    let address = addresses[index] + offset as u32;
+                    writeln!(file, "\t{}\t0x{:08X}", encoding.directives.word, address)?;
+                }
+            }
+            (None, AssetTable::Addresses) => {
+                for offset in rows {
+                    writeln!(
+                        file,
+                        "\t{}\t{} + {}",
+                        encoding.directives.word, asset.label, offset
+                    )?;
+                }
+            }
+            (None, AssetTable::Offsets) => {
+                let offsets: Vec<u32> = rows.into_iter().map(|offset| offset as u32).collect();
+                for row in offsets.chunks(4) {
+                    writeln!(file, "\t{}", encoding.words(row))?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a block of data, or when the output is split, write it into a file
+/// of its own and include that instead.
+fn write_part(
+    file: &mut impl FmtWrite,
+    parts: &mut Vec<OutputFile>,
+    options: &Options,
+    name: &str,
+    asm: &str,
+) -> Result<()> {
+    if !options.split {
+        writeln!(file, "{}", asm)?;
+        return Ok(());
+    }
+
+    let part = super::part_name(&options.file_name, name);
+    writeln!(file, "{}", options.include_directive.line(&part))?;
+    parts.push(OutputFile {
+        name: part,
+        contents: asm.as_bytes().to_vec(),
+    });
+
+    Ok(())
+}
@@ -0,0 +1,43 @@
+//! A raw blob of the assets, for loading from storage at runtime instead of
+//! assembling into the program.
+
+use super::{OutputBackend, OutputFile, Packed};
+use crate::encoding::Endian;
+use anyhow::Result;
+
+/// Writes a word holding the number of assets, a word per asset holding its
+/// offset from the start of the blob, then the assets back to back. Words
+/// are in the output's byte order, and alpha planes and private assets are
+/// left out.
+pub struct Binary;
+
+impl OutputBackend for Binary {
+    fn name(&self) -> &str {
+        "binary"
+    }
+
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>> {
+        let word = |value: usize| match packed.endian() {
+            Endian::Little => (value as u32).to_le_bytes(),
+            Endian::Big => (value as u32).to_be_bytes(),
+        };
+
+        let assets: Vec<_> = packed.table().map(|(_, asset)| asset).collect();
+        let mut blob = Vec::new();
+        blob.extend(word(assets.len()));
+
+        let mut offset = 4 * (assets.len() + 1);
+        for asset in assets.iter() {
+            blob.extend(word(offset));
+            offset += asset.data.len();
+        }
+        for asset in assets.iter() {
+            blob.extend(&asset.data);
+        }
+
+        Ok(vec![OutputFile {
+            name: packed.file_name_with("bin"),
+            contents: blob,
+        }])
+    }
+}
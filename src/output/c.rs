@@ -0,0 +1,107 @@
+//! A C header with the constants from the assembly, for C code linked
+//! against it.
+
+use super::{OutputBackend, OutputFile, Packed, PaletteOutput};
+use anyhow::Result;
+use std::fmt::Write as FmtWrite;
+
+/// Writes the asset numbers and sizes as `#define`s, matching the `EQU`s in
+/// the assembly.
+pub struct CHeader;
+
+impl OutputBackend for CHeader {
+    fn name(&self) -> &str {
+        "c-header"
+    }
+
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>> {
+        let name = packed.file_name_with("h");
+        let guard: String = name
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect();
+
+        let mut buf = String::new();
+        writeln!(buf, "/* Generated by image2arm, do not edit. */")?;
+        writeln!(buf, "#ifndef {}", guard)?;
+        writeln!(buf, "#define {}\n", guard)?;
+
+        let palette_output = packed.options.palette_output;
+        if let Some(bits) = packed.bits_per_colour() {
+            if palette_output != PaletteOutput::Omitted {
+                writeln!(buf, "#define PALETTE_SIZE {}", packed.palette().len())?;
+                if let Some(offset) = packed.palette_offset().filter(|&offset| offset > 0) {
+                    writeln!(buf, "#define PALETTE_OFFSET {}", offset)?;
+                }
+            }
+            if palette_output != PaletteOutput::Only {
+                writeln!(buf, "#define BITS_PER_COLOUR {}", bits)?;
+                match packed.pixels_per_byte() {
+                    Some(pixels) => writeln!(buf, "#define PIXELS_PER_BYTE {}\n", pixels)?,
+                    None => writeln!(buf, "#define BYTES_PER_PIXEL 2\n")?,
+                }
+            }
+        }
+
+        // a palette file has no assets
+        if palette_output != PaletteOutput::Only {
+            if let Some(cipher) = &packed.options.cipher {
+                for (name, value) in cipher.constants() {
+                    writeln!(buf, "#define {} {}", name, value)?;
+                }
+                buf.push('\n');
+            }
+            writeln!(buf, "#define ASSET_MAX {}\n", packed.table().count())?;
+            for (index, (_, asset)) in packed.table().enumerate() {
+                writeln!(buf, "#define ASSET{} {}", asset.label, index)?;
+                if let (Some(width), Some(height)) = (asset.width, asset.height) {
+                    writeln!(buf, "#define ASSET{}_WIDTH {}", asset.label, width)?;
+                    writeln!(buf, "#define ASSET{}_HEIGHT {}", asset.label, height)?;
+                }
+                writeln!(
+                    buf,
+                    "#define ASSET{}_BYTES {}",
+                    asset.label,
+                    asset.data.len()
+                )?;
+                if let Some(stride) = packed.stride(asset) {
+                    writeln!(buf, "#define ASSET{}_STRIDE {}", asset.label, stride)?;
+                }
+                if let Some((odd, _)) = asset.odd_field {
+                    writeln!(buf, "#define ASSET{}_ODD_FIELD {}", asset.label, odd)?;
+                }
+                if let Some(hash) = packed.hash(asset) {
+                    writeln!(buf, "#define ASSET{}_HASH 0x{:08X}u", asset.label, hash)?;
+                }
+                if let Some(priority) = asset.priority {
+                    writeln!(buf, "#define ASSET{}_PRIORITY {}", asset.label, priority)?;
+                }
+                if let Some(pairs) = packed.mask_pairs(asset) {
+                    writeln!(buf, "#define ASSET{}_PAIRS {}", asset.label, pairs)?;
+                }
+            }
+            // an initialiser, as the header defines no storage
+            if packed.options.priority_table {
+                let order: Vec<String> = packed
+                    .by_priority()
+                    .into_iter()
+                    .map(|asset| format!("ASSET{}", asset.label))
+                    .collect();
+                writeln!(
+                    buf,
+                    "\n#define ASSET_PRIORITY_ORDER {{ {} }}",
+                    order.join(", ")
+                )?;
+            }
+        }
+
+        writeln!(buf, "\n#endif /* {} */", guard)?;
+        Ok(vec![OutputFile {
+            name,
+            contents: buf.into_bytes(),
+        }])
+    }
+}
@@ -0,0 +1,76 @@
+//! Routines which draw a sprite with a store per byte, for
+//! `--compiled-sprites`.
+
+use super::{Packed, PackedAsset};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+
+/// Write a routine which draws an asset into a framebuffer packed the same
+/// way as the sprites, storing every byte with an opaque pixel in it as an
+/// immediate and never touching the bytes which are all transparent.
+pub(super) fn routine(packed: &Packed, asset: &PackedAsset, mask: &[u8]) -> Result<String> {
+    let encoding = packed.encoding();
+    // packing checks every row starts on a byte before the routine is written
+    let (width, height) = asset
+        .width
+        .zip(asset.height)
+        .ok_or_else(|| anyhow!("a compiled sprite needs its size"))?;
+    let stride = encoding
+        .row_bytes(width)
+        .ok_or_else(|| anyhow!("a compiled sprite needs its rows to start on bytes"))?;
+    // the padding of aligned rows is left alone
+    let row_bytes = encoding.unaligned_size(width, 1);
+
+    let mut buf = String::new();
+    writeln!(
+        buf,
+        "; {} - draw the {}x{} sprite {}",
+        asset.label, width, height, asset.name
+    )?;
+    writeln!(
+        buf,
+        ";   in:  r0 = where its first row goes in the framebuffer"
+    )?;
+    writeln!(
+        buf,
+        ";        r1 = the bytes from one framebuffer row to the next"
+    )?;
+    writeln!(buf, ";   out: r0 = where its last row went")?;
+    writeln!(buf, ";   corrupts r2 and r3")?;
+    writeln!(buf, "{}", asset.label)?;
+
+    for row in 0..height as usize {
+        if row > 0 {
+            writeln!(buf, "\tADD\tr0, r0, r1")?;
+        }
+        let start = row * stride;
+        let data = &asset.data[start..start + row_bytes];
+        let mask = &mask[start..start + row_bytes];
+
+        // bytes of the same value share the register they're stored from
+        let mut solid: BTreeMap<u8, Vec<usize>> = BTreeMap::new();
+        for (offset, (&byte, &mask)) in data.iter().zip(mask).enumerate() {
+            match mask {
+                0xFF => {}
+                0x00 => solid.entry(byte).or_default().push(offset),
+                // only the opaque pixels of a partly transparent byte change
+                mask => {
+                    writeln!(buf, "\tLDRB\tr3, [r0, #{}]", offset)?;
+                    writeln!(buf, "\tAND\tr3, r3, #0x{:02X}", mask)?;
+                    writeln!(buf, "\tORR\tr3, r3, #0x{:02X}", byte & !mask)?;
+                    writeln!(buf, "\tSTRB\tr3, [r0, #{}]", offset)?;
+                }
+            }
+        }
+        for (byte, offsets) in solid {
+            writeln!(buf, "\tMOV\tr2, #0x{:02X}", byte)?;
+            for offset in offsets {
+                writeln!(buf, "\tSTRB\tr2, [r0, #{}]", offset)?;
+            }
+        }
+    }
+    writeln!(buf, "\tMOV\tpc, lr")?;
+
+    Ok(buf)
+}
@@ -0,0 +1,147 @@
+//! A program which draws every asset in a grid, for `--emit-demo`.
+
+use super::{AssetTable, Packed, TABLE_LABEL};
+use crate::encoding::RowOrder;
+use anyhow::Result;
+use log::warn;
+use std::fmt::Write as FmtWrite;
+
+/// Where the demo draws, which has to be packed the same way as the assets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Demo {
+    /// The address of the framebuffer's top left pixel.
+    pub framebuffer: u32,
+    /// The width of the framebuffer in pixels.
+    pub width: u32,
+}
+
+/// The pixels left between assets in the grid, across and down.
+const GAP: u32 = 2;
+
+/// Write a program which walks the asset table and copies every asset into
+/// its place in a grid on the framebuffer, then spins so it stays on screen.
+/// The assets file is included after the code so the program starts with it.
+pub(super) fn program(packed: &Packed, demo: Demo) -> Result<String> {
+    let options = &packed.options;
+    let encoding = packed.encoding();
+    let pitch = encoding.unaligned_size(demo.width, 1);
+    let gap = encoding.unaligned_size(GAP, 1);
+    let step = match options.row_order {
+        RowOrder::TopDown => pitch as i64,
+        RowOrder::BottomUp => -(pitch as i64),
+    };
+
+    // a word each for where the first row goes, the bytes in a row, the
+    // number of rows and the bytes from one row of the asset to the next
+    let (mut x, mut y, mut tallest) = (0, 0, 0);
+    let mut layout = String::new();
+    for (_, asset) in packed.table() {
+        let size = asset.width.zip(asset.height).and_then(|(width, height)| {
+            let stride = encoding.row_bytes(width)?;
+            Some((encoding.unaligned_size(width, 1), height as usize, stride))
+        });
+        let (row_bytes, rows, stride) = match size {
+            Some(size) if size.0 <= pitch => size,
+            _ => {
+                warn!("the demo can't draw {}, so leaves it out", asset.name);
+                writeln!(layout, "\t{}\t0, 0, 0, 0", encoding.directives.word)?;
+                continue;
+            }
+        };
+
+        if x + row_bytes > pitch {
+            x = 0;
+            y += tallest + GAP as usize;
+            tallest = 0;
+        }
+        let first_row = match options.row_order {
+            RowOrder::TopDown => y,
+            RowOrder::BottomUp => y + rows - 1,
+        };
+        writeln!(
+            layout,
+            "\t{}\tDEMO_FRAMEBUFFER + {}, {}, {}, {}\t; {}",
+            encoding.directives.word,
+            first_row * pitch + x,
+            row_bytes,
+            rows,
+            stride,
+            asset.name
+        )?;
+        x += row_bytes + gap;
+        tallest = tallest.max(rows);
+    }
+
+    let mut buf = String::new();
+    writeln!(
+        buf,
+        "; demo - draw every asset in a grid, to check them on the hardware"
+    )?;
+    writeln!(
+        buf,
+        ";   the framebuffer is packed the same way as the assets"
+    )?;
+    writeln!(buf, "\nDEMO_FRAMEBUFFER\tEQU 0x{:08X}", demo.framebuffer)?;
+    writeln!(buf, "DEMO_ROW_STEP\tEQU {}\n", step)?;
+
+    writeln!(buf, "demo")?;
+    writeln!(buf, "\tLDR\tr4, ={}", TABLE_LABEL)?;
+    writeln!(buf, "\tLDR\tr5, =DemoLayout")?;
+    writeln!(buf, "\tLDR\tr10, =DEMO_ROW_STEP")?;
+    writeln!(buf, "\tMOV\tr6, #0\t\t\t\t; the asset being drawn")?;
+    writeln!(buf, "demo_asset")?;
+    writeln!(buf, "\tLDR\tr0, =ASSET_MAX")?;
+    writeln!(buf, "\tCMP\tr6, r0")?;
+    writeln!(buf, "\tBHS\tdemo_done")?;
+    writeln!(buf, "\tLDR\tr0, [r4, r6, LSL #2]")?;
+    if options.base.is_none() && options.table == AssetTable::Offsets {
+        let base = options.table_base.as_deref().unwrap_or(TABLE_LABEL);
+        writeln!(buf, "\tLDR\tr1, ={}", base)?;
+        writeln!(buf, "\tADD\tr0, r0, r1")?;
+    }
+    writeln!(buf, "\tLDMIA\tr5!, {{r1-r3, r9}}")?;
+    writeln!(buf, "\tADD\tr6, r6, #1")?;
+
+    match options.compiled_sprites {
+        // each routine draws itself, stepping through the framebuffer by r1
+        true => {
+            writeln!(buf, "\tCMP\tr3, #0")?;
+            writeln!(buf, "\tBEQ\tdemo_asset\t\t\t; left out")?;
+            writeln!(buf, "\tMOV\tr12, r0")?;
+            writeln!(buf, "\tMOV\tr0, r1")?;
+            writeln!(buf, "\tMOV\tr1, r10")?;
+            writeln!(buf, "\tMOV\tlr, pc")?;
+            writeln!(buf, "\tMOV\tpc, r12")?;
+            writeln!(buf, "\tB\tdemo_asset")?;
+        }
+        false => {
+            writeln!(buf, "demo_row")?;
+            writeln!(buf, "\tSUBS\tr3, r3, #1")?;
+            writeln!(buf, "\tBMI\tdemo_asset")?;
+            writeln!(buf, "\tMOV\tr7, #0")?;
+            writeln!(buf, "demo_byte")?;
+            writeln!(buf, "\tLDRB\tr8, [r0, r7]")?;
+            writeln!(buf, "\tSTRB\tr8, [r1, r7]")?;
+            writeln!(buf, "\tADD\tr7, r7, #1")?;
+            writeln!(buf, "\tCMP\tr7, r2")?;
+            writeln!(buf, "\tBLO\tdemo_byte")?;
+            writeln!(buf, "\tADD\tr0, r0, r9")?;
+            writeln!(buf, "\tADD\tr1, r1, r10")?;
+            writeln!(buf, "\tB\tdemo_row")?;
+        }
+    }
+
+    writeln!(buf, "demo_done")?;
+    writeln!(buf, "\tB\tdemo_done\n")?;
+
+    writeln!(buf, "ALIGN")?;
+    writeln!(buf, "DemoLayout")?;
+    buf.push_str(&layout);
+    writeln!(
+        buf,
+        "\n{}",
+        options.include_directive.line(packed.file_name())
+    )?;
+
+    Ok(buf)
+}
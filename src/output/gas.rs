@@ -0,0 +1,307 @@
+//! Assembly for the GNU assembler, which spells its directives differently
+//! from armasm.
+
+use super::{
+    AssetTable, OutputBackend, OutputFile, Packed, PaletteOutput, TableTerminator, MASK_WORD_BYTES,
+    PRIORITY_TABLE_LABEL,
+};
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::encoding::runs;
+use anyhow::{ensure, Result};
+use std::fmt::Write as FmtWrite;
+
+/// Writes the palette, assets and tables with `.byte`, `.word` and `.equ`.
+pub struct Gas;
+
+impl OutputBackend for Gas {
+    fn name(&self) -> &str {
+        "gas"
+    }
+
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>> {
+        ensure!(
+            !packed.has_font_or_map(),
+            Diagnostic::new(
+                FailureClass::Usage,
+                "only the armasm output holds fonts and maps"
+            )
+            .with_suggestion("emit asm for the font and map")
+        );
+        ensure!(
+            packed.options.demo.is_none(),
+            Diagnostic::new(
+                FailureClass::Usage,
+                "only the armasm output comes with a demo"
+            )
+            .with_suggestion("emit asm for the demo")
+        );
+        ensure!(
+            !packed.options.compiled_sprites,
+            Diagnostic::new(
+                FailureClass::Usage,
+                "only the armasm output holds compiled sprites"
+            )
+            .with_suggestion("emit asm for the compiled sprites")
+        );
+        ensure!(
+            packed.options.base.is_none(),
+            Diagnostic::new(
+                FailureClass::Usage,
+                "only the armasm output can be placed at a base address"
+            )
+            .with_suggestion("place the section with the linker script instead")
+        );
+
+        let mut buf = String::new();
+        writeln!(buf, "@ Generated by image2arm, do not edit.\n")?;
+        writeln!(buf, "\t.section .rodata\n")?;
+
+        let palette_output = packed.options.palette_output;
+        if packed.bits_per_colour().is_some() && palette_output != PaletteOutput::Omitted {
+            writeln!(buf, "Palette:")?;
+            for colour in packed.palette() {
+                writeln!(
+                    buf,
+                    "\t.byte 0x{:02X}, 0x{:02X}, 0x{:02X}, 0x{:02X}",
+                    colour.r, colour.g, colour.b, colour.a
+                )?;
+            }
+            if let Some(offset) = packed.palette_offset().filter(|&offset| offset > 0) {
+                writeln!(buf, "\t.equ PALETTE_OFFSET, {}", offset)?;
+            }
+            buf.push('\n');
+        }
+        if palette_output == PaletteOutput::Only {
+            return Ok(vec![file(packed, &buf)]);
+        }
+
+        if let Some(bits) = packed.bits_per_colour() {
+            writeln!(buf, "\t.equ bits_per_colour, {}", bits)?;
+            match packed.pixels_per_byte() {
+                Some(pixels) => writeln!(buf, "\t.equ pixels_per_byte, {}\n", pixels)?,
+                None => writeln!(buf, "\t.equ bytes_per_pixel, 2\n")?,
+            }
+        }
+        if let Some(plane) = packed.options.alpha_plane {
+            writeln!(buf, "\t.equ bits_per_alpha, {}\n", plane.bits())?;
+        }
+        if let Some(cipher) = &packed.options.cipher {
+            for (name, value) in cipher.constants() {
+                writeln!(buf, "\t.equ {}, {}", name, value)?;
+            }
+            buf.push('\n');
+        }
+
+        for asset in packed.assets() {
+            if packed.options.align_assets {
+                writeln!(buf, "\t.balign 4")?;
+            }
+            let (odd, alpha_odd) = asset.odd_field.unzip();
+            write_fields(&mut buf, packed, &asset.label, &asset.data, odd)?;
+            if let Some(alpha) = &asset.alpha {
+                let label = format!("{}_Alpha", asset.label);
+                write_fields(&mut buf, packed, &label, alpha, alpha_odd)?;
+            }
+            buf.push('\n');
+        }
+
+        // the address table must be aligned
+        writeln!(buf, "\t.balign 4")?;
+        if packed.options.table_terminator == Some(TableTerminator::Length) {
+            writeln!(buf, "AssetAddressTableLength:")?;
+            writeln!(buf, "\t.word {}", packed.table().count())?;
+        }
+        writeln!(buf, "AssetAddressTable:")?;
+        for (_, asset) in packed.table() {
+            writeln!(buf, "\t.word {}", packed.table_entry(&asset.label))?;
+        }
+        writeln!(buf, "AssetAddressTableEnd:")?;
+        if packed.options.table_terminator == Some(TableTerminator::Zero) {
+            writeln!(buf, "\t.word 0")?;
+        }
+        buf.push('\n');
+
+        // rows are found from the top down, relative to the asset for offsets
+        if packed.options.row_labels {
+            for asset in packed.assets() {
+                let rows = match packed.row_offsets(asset) {
+                    Some(rows) => rows,
+                    None => continue,
+                };
+                writeln!(buf, "{}_Rows:", asset.label)?;
+                for (row, offset) in rows.iter().enumerate() {
+                    writeln!(
+                        buf,
+                        "\t.equ {}_Row{}, {} + {}",
+                        asset.label, row, asset.label, offset
+                    )?;
+                    match packed.options.table {
+                        AssetTable::Addresses => {
+                            writeln!(buf, "\t.word {}_Row{}", asset.label, row)?
+                        }
+                        AssetTable::Offsets => writeln!(buf, "\t.word {}", offset)?,
+                    }
+                }
+                buf.push('\n');
+            }
+        }
+
+        // a zero word ends each row's spans
+        for asset in packed.assets() {
+            if let Some(spans) = &asset.spans {
+                writeln!(buf, "{}_Spans:", asset.label)?;
+                for row in spans {
+                    let words: Vec<String> = row
+                        .iter()
+                        .map(|span| format!("0x{:08X}", span.word()))
+                        .chain(std::iter::once("0".to_owned()))
+                        .collect();
+                    writeln!(buf, "\t.word {}", words.join(", "))?;
+                }
+                buf.push('\n');
+            }
+        }
+
+        // the outlines are laid out like a one bit alpha plane
+        for asset in packed.assets() {
+            if let Some(outline) = &asset.outline {
+                let label = format!("{}_Outline", asset.label);
+                write_fields(&mut buf, packed, &label, outline, None)?;
+                buf.push('\n');
+            }
+        }
+
+        for asset in packed.assets() {
+            let copies = packed.shifted_copies(asset);
+            if !copies.is_empty() {
+                writeln!(buf, "{}_Shifts:", asset.label)?;
+                for copy in copies {
+                    writeln!(buf, "\t.word {}", copy.label)?;
+                }
+                buf.push('\n');
+            }
+        }
+
+        for strips in packed.strips.iter() {
+            writeln!(buf, "{}_Strips:", strips.label)?;
+            for name in strips.names() {
+                writeln!(buf, "\t.word {}", packed.label(&name))?;
+            }
+            buf.push('\n');
+        }
+
+        // the renderer walks the table from the back to the front
+        if packed.options.priority_table {
+            writeln!(buf, "{}:", PRIORITY_TABLE_LABEL)?;
+            for asset in packed.by_priority() {
+                writeln!(buf, "\t.word ASSET{}", asset.label)?;
+            }
+            writeln!(buf, "{}End:\n", PRIORITY_TABLE_LABEL)?;
+        }
+
+        writeln!(
+            buf,
+            "\t.equ ASSET_MAX, (AssetAddressTableEnd - AssetAddressTable) / 4"
+        )?;
+        for (index, (_, asset)) in packed.table().enumerate() {
+            writeln!(buf, "\t.equ ASSET{}, {}", asset.label, index)?;
+            if let Some(stride) = packed.stride(asset) {
+                writeln!(buf, "\t.equ STRIDE{}, {}", asset.label, stride)?;
+            }
+            if let Some(hash) = packed.hash(asset) {
+                writeln!(buf, "\t.equ HASH{}, 0x{:08X}", asset.label, hash)?;
+            }
+            if let Some(priority) = asset.priority {
+                writeln!(buf, "\t.equ PRIORITY{}, {}", asset.label, priority)?;
+            }
+        }
+
+        if packed.options.masked {
+            writeln!(buf, "\t.equ MASK_OFFSET, 0")?;
+            writeln!(buf, "\t.equ DATA_OFFSET, {}", MASK_WORD_BYTES)?;
+            writeln!(buf, "\t.equ PAIR_BYTES, {}", 2 * MASK_WORD_BYTES)?;
+            for asset in packed.assets() {
+                if let Some(pairs) = packed.mask_pairs(asset) {
+                    writeln!(buf, "\t.equ PAIRS{}, {}", asset.label, pairs)?;
+                }
+            }
+        }
+
+        for strips in packed.strips.iter() {
+            writeln!(buf, "\t.equ STRIPS{}, {}", strips.label, strips.count)?;
+            writeln!(buf, "\t.equ STRIP_ROWS{}, {}", strips.label, strips.rows)?;
+        }
+
+        for animation in packed.animations() {
+            let upper = animation.name().to_uppercase();
+            let first = packed.label(animation.frames().first().map_or("", String::as_str));
+            let count = animation.frames().len();
+            writeln!(buf, "\t.equ ANIM_{}_START, ASSET{}", upper, first)?;
+            writeln!(buf, "\t.equ ANIM_{}_FIRST, ASSET{}", upper, first)?;
+            writeln!(buf, "\t.equ ANIM_{}_LENGTH, {}", upper, count)?;
+            writeln!(buf, "\t.equ ANIM_{}_COUNT, {}", upper, count)?;
+            if let Some(stride) = animation.frame_stride() {
+                writeln!(buf, "\t.equ ANIM_{}_FRAME_STRIDE, {}", upper, stride)?;
+            }
+        }
+
+        for (name, patch) in packed.options.nine_patches.iter() {
+            for (constant, value) in patch.constants(name, packed) {
+                writeln!(buf, "\t.equ {}, {}", constant, value)?;
+            }
+        }
+
+        Ok(vec![file(packed, &buf)])
+    }
+}
+
+/// The assembly file, in the configured style.
+fn file(packed: &Packed, buf: &str) -> OutputFile {
+    OutputFile {
+        name: packed.file_name().to_owned(),
+        contents: packed.options.style.apply(buf, '@').into_bytes(),
+    }
+}
+
+/// Write an asset's bytes under its label, with a second label where the odd
+/// field starts if it is split into fields.
+fn write_fields(
+    buf: &mut String,
+    packed: &Packed,
+    label: &str,
+    bytes: &[u8],
+    odd: Option<usize>,
+) -> Result<()> {
+    let (even, odd) = match odd {
+        Some(odd) => {
+            let (even, odd) = bytes.split_at(odd);
+            (even, Some(odd))
+        }
+        None => (bytes, None),
+    };
+    writeln!(buf, "{}:", label)?;
+    write_bytes(buf, packed, even)?;
+    if let Some(odd) = odd {
+        writeln!(buf, "{}_Odd:", label)?;
+        write_bytes(buf, packed, odd)?;
+    }
+
+    Ok(())
+}
+
+/// Write bytes as rows of `.byte`s, with the long runs of one byte as a
+/// `.fill`.
+fn write_bytes(buf: &mut String, packed: &Packed, bytes: &[u8]) -> Result<()> {
+    for (fill, bytes) in runs(bytes, packed.options.fill_runs) {
+        if fill {
+            writeln!(buf, "\t.fill {}, 1, 0x{:02X}", bytes.len(), bytes[0])?;
+            continue;
+        }
+        for row in bytes.chunks(8) {
+            let row: Vec<String> = row.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            writeln!(buf, "\t.byte {}", row.join(", "))?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,233 @@
+//! A GDB script which reads assets back out of the target's memory, to check
+//! the data really is what the sprites hold.
+
+use super::{AssetTable, OutputBackend, OutputFile, Packed, TABLE_LABEL};
+use crate::cipher::Cipher;
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::encoding::{Endian, PixelFormat, RowOrder};
+use anyhow::{ensure, Result};
+use serde::Serialize;
+
+/// Writes a Python script for GDB with a `dump-asset NAME FILE.png` command,
+/// which finds an asset through the asset table, reads it from the target
+/// and decodes it into a PNG on the host.
+pub struct Gdb;
+
+/// Everything the script needs to find and decode the assets.
+#[derive(Serialize)]
+struct Layout<'a> {
+    table: &'a str,
+    /// The label table offsets are measured from, when it holds offsets.
+    table_base: Option<&'a str>,
+    format: PixelFormat,
+    bits_per_colour: usize,
+    /// The index of the palette's first colour.
+    palette_offset: usize,
+    /// The bytes the data is XORed with, in turn from the start of each asset.
+    xor_key: Option<&'a [u8]>,
+    big_endian: bool,
+    bottom_up: bool,
+    palette: Vec<[u8; 4]>,
+    assets: Vec<AssetLayout<'a>>,
+}
+
+#[derive(Serialize)]
+struct AssetLayout<'a> {
+    name: &'a str,
+    label: &'a str,
+    /// Where it is in the asset table, which private assets aren't.
+    index: Option<usize>,
+    /// Where it is loaded, when the output is placed with `--base`.
+    address: Option<u32>,
+    width: u32,
+    height: u32,
+    bytes: usize,
+    /// The bytes from one row to the next, when rows are aligned.
+    stride: Option<usize>,
+}
+
+impl OutputBackend for Gdb {
+    fn name(&self) -> &str {
+        "gdb"
+    }
+
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>> {
+        let options = &packed.options;
+        ensure!(
+            !options.masked && !options.compiled_sprites && options.fields.is_none(),
+            Diagnostic::new(
+                FailureClass::Usage,
+                "the GDB script only decodes pixels written a row after another"
+            )
+            .with_suggestion("leave out --masked, --compiled-sprites and --fields")
+        );
+
+        let encoding = packed.encoding();
+        let addresses = options.base.map(|base| packed.addresses(base));
+        let table: Vec<usize> = packed.table().map(|(index, _)| index).collect();
+        // assets carried over by a merge have no size to decode them with
+        let assets = packed
+            .assets()
+            .iter()
+            .enumerate()
+            .filter_map(|(index, asset)| {
+                Some(AssetLayout {
+                    name: &asset.name,
+                    label: &asset.label,
+                    index: table.iter().position(|&entry| entry == index),
+                    address: addresses.as_ref().map(|addresses| addresses[index]),
+                    width: asset.width?,
+                    height: asset.height?,
+                    bytes: asset.data.len(),
+                    stride: packed.stride(asset),
+                })
+            })
+            .collect();
+
+        let layout = Layout {
+            table: TABLE_LABEL,
+            table_base: match options.table {
+                AssetTable::Addresses => None,
+                AssetTable::Offsets => Some(options.table_base.as_deref().unwrap_or(TABLE_LABEL)),
+            },
+            format: packed.format(),
+            bits_per_colour: encoding.bits_per_colour,
+            palette_offset: encoding.index_offset,
+            xor_key: options.cipher.as_ref().map(|cipher| match cipher {
+                Cipher::Xor(key) => key.as_slice(),
+            }),
+            big_endian: packed.endian() == Endian::Big,
+            bottom_up: options.row_order == RowOrder::BottomUp,
+            palette: packed
+                .palette()
+                .iter()
+                .map(|c| [c.r, c.g, c.b, c.a])
+                .collect(),
+            assets,
+        };
+
+        let script = SCRIPT.replace("@LAYOUT@", &serde_json::to_string(&layout)?);
+        Ok(vec![OutputFile {
+            name: packed.file_name_with("gdb.py"),
+            contents: script.into_bytes(),
+        }])
+    }
+}
+
+const SCRIPT: &str = r#"# Generated by image2arm, do not edit.
+#
+# Load it into GDB with `source` then run `dump-asset NAME FILE.png` to read
+# an asset out of the target's memory and save it as a PNG.
+
+import json
+import struct
+import zlib
+
+import gdb
+
+LAYOUT = json.loads(r'''@LAYOUT@''')
+ASSETS = {asset["name"]: asset for asset in LAYOUT["assets"]}
+
+
+def symbol(name):
+    return int(gdb.parse_and_eval("(unsigned long)&" + name))
+
+
+def word(address):
+    # the table's words are in the target's own byte order
+    return int(gdb.parse_and_eval("*(unsigned int *)%d" % address))
+
+
+def address_of(asset):
+    if asset["address"] is not None:
+        return asset["address"]
+    if asset["index"] is None:
+        return symbol(asset["label"])
+    entry = word(symbol(LAYOUT["table"]) + 4 * asset["index"])
+    if LAYOUT["table_base"] is not None:
+        entry = (entry + symbol(LAYOUT["table_base"])) & 0xFFFFFFFF
+    return entry
+
+
+def pixel(data, asset, x, y):
+    width, bits = asset["width"], LAYOUT["bits_per_colour"]
+    endian = ">" if LAYOUT["big_endian"] else "<"
+    sizes = {"rgb565": 2, "argb4444": 2, "argb8888": 4}
+    size = sizes.get(LAYOUT["format"], 2 if bits > 8 else None)
+
+    if size is not None:
+        start = (asset["stride"] or width * size) * y + x * size
+        raw = data[start:start + size]
+        value = struct.unpack(endian + ("I" if size == 4 else "H"), raw)[0]
+        if LAYOUT["format"] == "rgb565":
+            r, g, b = value >> 11, (value >> 5) & 0x3F, value & 0x1F
+            return (r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 0xFF)
+        if LAYOUT["format"] == "argb4444":
+            return tuple(((value >> shift) & 0xF) * 0x11 for shift in (8, 4, 0, 12))
+        if LAYOUT["format"] == "argb8888":
+            return tuple((value >> shift) & 0xFF for shift in (16, 8, 0, 24))
+        return tuple(LAYOUT["palette"][value - LAYOUT["palette_offset"]])
+
+    # packed indices run on from one row to the next unless rows are aligned
+    per_byte = 8 // bits
+    if asset["stride"] is not None:
+        start, within = asset["stride"] * y, x
+    else:
+        start, within = 0, y * width + x
+    byte = data[start + within // per_byte]
+    slot = within % per_byte
+    shift = 8 - (slot + 1) * bits if LAYOUT["big_endian"] else slot * bits
+    index = (byte >> shift) & ((1 << bits) - 1)
+    return tuple(LAYOUT["palette"][index - LAYOUT["palette_offset"]])
+
+
+def write_png(path, width, height, rows):
+    def chunk(kind, body):
+        crc = zlib.crc32(kind + body) & 0xFFFFFFFF
+        return struct.pack(">I", len(body)) + kind + body + struct.pack(">I", crc)
+
+    raw = b"".join(b"\0" + bytes(channel for p in row for channel in p) for row in rows)
+    header = struct.pack(">IIBBBBB", width, height, 8, 6, 0, 0, 0)
+    with open(path, "wb") as png:
+        png.write(b"\x89PNG\r\n\x1a\n")
+        png.write(chunk(b"IHDR", header))
+        png.write(chunk(b"IDAT", zlib.compress(raw)))
+        png.write(chunk(b"IEND", b""))
+
+
+class DumpAsset(gdb.Command):
+    """dump-asset NAME FILE.png: save an asset in the target's memory as a PNG."""
+
+    def __init__(self):
+        super().__init__("dump-asset", gdb.COMMAND_DATA)
+
+    def complete(self, text, word):
+        return [name for name in ASSETS if name.startswith(text)]
+
+    def invoke(self, argument, from_tty):
+        args = gdb.string_to_argv(argument)
+        if len(args) != 2:
+            raise gdb.GdbError("usage: dump-asset NAME FILE.png")
+        name, path = args
+        if name not in ASSETS:
+            raise gdb.GdbError("no asset named %s, try one of %s" % (name, ", ".join(ASSETS)))
+
+        asset = ASSETS[name]
+        address = address_of(asset)
+        data = bytes(gdb.selected_inferior().read_memory(address, asset["bytes"]))
+        if LAYOUT["xor_key"]:
+            key = LAYOUT["xor_key"]
+            data = bytes(byte ^ key[i % len(key)] for i, byte in enumerate(data))
+        rows = [
+            [pixel(data, asset, x, y) for x in range(asset["width"])]
+            for y in range(asset["height"])
+        ]
+        if LAYOUT["bottom_up"]:
+            rows.reverse()
+
+        write_png(path, asset["width"], asset["height"], rows)
+        print("wrote %s from 0x%08X to %s" % (name, address, path))
+
+
+DumpAsset()
+"#;
@@ -0,0 +1,51 @@
+//! How many pixels of each asset use each palette index, for `--histogram`,
+//! to find stray pixels in colours the art shouldn't use and to see which
+//! colours are worth cutting.
+
+use super::{OutputBackend, OutputFile, Packed};
+use crate::palette::hex;
+use anyhow::Result;
+use std::fmt::Write as FmtWrite;
+
+/// Lists the palette indices each asset uses, with how many pixels use them.
+pub struct Histogram;
+
+impl OutputBackend for Histogram {
+    fn name(&self) -> &str {
+        "histogram"
+    }
+
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>> {
+        let palette = packed.palette();
+        let offset = packed.palette_offset().unwrap_or_default();
+        let mut buf = String::new();
+        for asset in packed.assets() {
+            // assets carried over by a merge have no pixels to count
+            let (counts, width, height) = match (&asset.histogram, asset.width, asset.height) {
+                (Some(counts), Some(width), Some(height)) => (counts, width, height),
+                _ => continue,
+            };
+
+            writeln!(
+                buf,
+                "{} ({}x{}, {} pixels)",
+                asset.name,
+                width,
+                height,
+                width * height
+            )?;
+            for (index, count) in counts.iter().enumerate() {
+                if *count > 0 {
+                    let colour = hex(&palette[index]);
+                    writeln!(buf, "{:>5}  {}  {:>8}", offset + index, colour, count)?;
+                }
+            }
+            buf.push('\n');
+        }
+
+        Ok(vec![OutputFile {
+            name: packed.file_name_with("histogram.txt"),
+            contents: buf.into_bytes(),
+        }])
+    }
+}
@@ -0,0 +1,170 @@
+//! A JSON description of the output, for tools which don't read assembly.
+
+use super::{OutputBackend, OutputFile, Packed};
+use crate::cipher::Cipher;
+use crate::encoding::PixelFormat;
+use crate::nine_patch::NinePatch;
+use anyhow::Result;
+use serde::Serialize;
+
+/// Describes the palette, assets, animations, nine-patches and strips as
+/// JSON.
+pub struct Json;
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    format: PixelFormat,
+    palette: Vec<String>,
+    /// The index of the first colour, after the reserved indices.
+    palette_offset: Option<usize>,
+    bits_per_colour: Option<usize>,
+    /// The bytes the data is XORed with, in turn from the start of each
+    /// asset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xor_key: Option<&'a [u8]>,
+    assets: Vec<AssetInfo<'a>>,
+    animations: Vec<AnimationInfo<'a>>,
+    nine_patches: Vec<NinePatchInfo<'a>>,
+    strips: Vec<StripsInfo<'a>>,
+    /// The names of the assets in the table from the back to the front, when
+    /// the priority table is asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority_order: Option<Vec<&'a str>>,
+    /// Only set when the fingerprint is asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct AssetInfo<'a> {
+    name: &'a str,
+    label: &'a str,
+    width: Option<u32>,
+    height: Option<u32>,
+    bytes: usize,
+    /// Only set when rows are aligned.
+    stride: Option<usize>,
+    /// Where the odd rows start, when the image is split into fields.
+    odd_field: Option<usize>,
+    /// How many mask and data word pairs there are, for masked sprites.
+    mask_pairs: Option<usize>,
+    /// The CRC-32 of the data, when hashes are asked for.
+    hash: Option<u32>,
+    /// Where it is drawn from back to front, when it has a priority.
+    priority: Option<i32>,
+    /// The pixels using each palette index, when a histogram is asked for.
+    histogram: Option<&'a [usize]>,
+    private: bool,
+}
+
+#[derive(Serialize)]
+struct AnimationInfo<'a> {
+    name: &'a str,
+    frames: &'a [String],
+    durations: Option<&'a [u32]>,
+    /// The bytes from one frame to the next, when only the first frame is
+    /// in the table.
+    frame_stride: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct NinePatchInfo<'a> {
+    name: &'a str,
+    #[serde(flatten)]
+    insets: NinePatch,
+    /// The assets cut from it, from the top left corner across each row.
+    regions: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct StripsInfo<'a> {
+    name: &'a str,
+    /// The rows in every strip but the last.
+    rows: u32,
+    /// The assets cut from it, from the top.
+    strips: Vec<String>,
+}
+
+impl OutputBackend for Json {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>> {
+        let manifest = Manifest {
+            format: packed.format(),
+            palette: packed
+                .palette()
+                .iter()
+                .map(|c| format!("#{:02X}{:02X}{:02X}{:02X}", c.r, c.g, c.b, c.a))
+                .collect(),
+            palette_offset: packed.palette_offset(),
+            bits_per_colour: packed.bits_per_colour(),
+            xor_key: packed.options.cipher.as_ref().map(|cipher| match cipher {
+                Cipher::Xor(key) => key.as_slice(),
+            }),
+            assets: packed
+                .assets()
+                .iter()
+                .map(|asset| AssetInfo {
+                    name: &asset.name,
+                    label: &asset.label,
+                    width: asset.width,
+                    height: asset.height,
+                    bytes: asset.data.len(),
+                    stride: packed.stride(asset),
+                    odd_field: asset.odd_field.map(|(odd, _)| odd),
+                    mask_pairs: packed.mask_pairs(asset),
+                    hash: packed.hash(asset),
+                    priority: asset.priority,
+                    histogram: asset.histogram.as_deref(),
+                    private: asset.private,
+                })
+                .collect(),
+            animations: packed
+                .animations()
+                .iter()
+                .map(|animation| AnimationInfo {
+                    name: animation.name(),
+                    frames: animation.frames(),
+                    durations: animation.durations(),
+                    frame_stride: animation.frame_stride(),
+                })
+                .collect(),
+            nine_patches: packed
+                .options
+                .nine_patches
+                .iter()
+                .map(|(name, patch)| NinePatchInfo {
+                    name,
+                    insets: *patch,
+                    regions: NinePatch::regions(name).collect(),
+                })
+                .collect(),
+            strips: packed
+                .strips
+                .iter()
+                .map(|strips| StripsInfo {
+                    name: &strips.name,
+                    rows: strips.rows,
+                    strips: strips.names().collect(),
+                })
+                .collect(),
+            priority_order: packed.options.priority_table.then(|| {
+                packed
+                    .by_priority()
+                    .into_iter()
+                    .map(|asset| asset.name.as_str())
+                    .collect()
+            }),
+            fingerprint: packed.fingerprint.as_deref(),
+        };
+
+        let mut json = serde_json::to_string_pretty(&manifest)?;
+        json.push('\n');
+        Ok(vec![OutputFile {
+            name: packed.file_name_with("json"),
+            contents: json.into_bytes(),
+        }])
+    }
+}
@@ -0,0 +1,131 @@
+//! A Komodo memory image, which loads the palette and assets straight into
+//! the emulator's memory without assembling anything.
+
+use super::{OutputBackend, OutputFile, Packed, PaletteOutput, TableTerminator, TABLE_LABEL};
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::encoding::Endian;
+use anyhow::{ensure, Result};
+use std::fmt::Write as FmtWrite;
+
+/// Writes a KMD file placing the palette, its shades, the assets and the
+/// asset table at the addresses `--base` gives them, in the same layout as
+/// the assembly, with a comment line naming each label.
+pub struct Kmd;
+
+impl OutputBackend for Kmd {
+    fn name(&self) -> &str {
+        "kmd"
+    }
+
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>> {
+        let base = packed.options.base;
+        ensure!(
+            base.is_some(),
+            Diagnostic::new(
+                FailureClass::Usage,
+                "a memory image has to be placed at a fixed address"
+            )
+            .with_suggestion("add --base with the address to load the assets at")
+        );
+        ensure!(
+            !packed.has_font_or_map() && !packed.options.compiled_sprites,
+            Diagnostic::new(
+                FailureClass::Usage,
+                "fonts, maps and compiled sprites have to be assembled"
+            )
+            .with_suggestion("emit asm for them and assemble it instead")
+        );
+        let base = base.unwrap_or_default();
+
+        let mut image = MemoryImage {
+            buf: String::from("KMD\n"),
+            address: base,
+        };
+
+        let palette_output = packed.options.palette_output;
+        if !packed.palette().is_empty() && palette_output != PaletteOutput::Omitted {
+            image.label("Palette")?;
+            for colour in packed.palette() {
+                image.bytes(&[colour.r, colour.g, colour.b, colour.a])?;
+            }
+            for (level, colours) in packed
+                .palette
+                .shades(packed.options.shade_levels)
+                .iter()
+                .enumerate()
+            {
+                image.label(&format!("Palette_Shade{}", level))?;
+                for colour in colours {
+                    image.bytes(&[colour.r, colour.g, colour.b, colour.a])?;
+                }
+            }
+        }
+
+        if palette_output == PaletteOutput::Only {
+            return Ok(vec![image.file(packed)]);
+        }
+
+        let addresses = packed.addresses(base);
+        for (asset, &address) in packed.assets().iter().zip(addresses.iter()) {
+            image.address = address;
+            image.label(&asset.label)?;
+            image.bytes(&asset.data)?;
+            if let Some(alpha) = &asset.alpha {
+                image.label(&format!("{}_Alpha", asset.label))?;
+                image.bytes(alpha)?;
+            }
+        }
+
+        // the address table must be aligned
+        image.address = image.address.next_multiple_of(4);
+        let word = |value: u32| match packed.endian() {
+            Endian::Little => value.to_le_bytes(),
+            Endian::Big => value.to_be_bytes(),
+        };
+        if packed.options.table_terminator == Some(TableTerminator::Length) {
+            image.label(&format!("{}Length", TABLE_LABEL))?;
+            image.bytes(&word(packed.table().count() as u32))?;
+        }
+        image.label(TABLE_LABEL)?;
+        for (index, _) in packed.table() {
+            image.bytes(&word(addresses[index]))?;
+        }
+        image.label(&format!("{}End", TABLE_LABEL))?;
+        if packed.options.table_terminator == Some(TableTerminator::Zero) {
+            image.bytes(&word(0))?;
+        }
+
+        Ok(vec![image.file(packed)])
+    }
+}
+
+/// The lines of a KMD file, written from `address` onwards.
+struct MemoryImage {
+    buf: String,
+    address: u32,
+}
+
+impl MemoryImage {
+    /// A line holding no bytes, naming the address.
+    fn label(&mut self, label: &str) -> Result<()> {
+        writeln!(self.buf, "{:08X}: {:11} ; {}", self.address, "", label)?;
+        Ok(())
+    }
+
+    /// Lines of up to four bytes each.
+    fn bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        for row in bytes.chunks(4) {
+            let row: Vec<String> = row.iter().map(|byte| format!("{:02X}", byte)).collect();
+            writeln!(self.buf, "{:08X}: {}", self.address, row.join(" "))?;
+            self.address += row.len() as u32;
+        }
+        Ok(())
+    }
+
+    fn file(self, packed: &Packed) -> OutputFile {
+        OutputFile {
+            name: packed.file_name_with("kmd"),
+            contents: self.buf.into_bytes(),
+        }
+    }
+}
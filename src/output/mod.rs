@@ -0,0 +1,449 @@
+//! The files a conversion writes. Each kind of file is written by an
+//! [`OutputBackend`] from the same packed assets, so they always agree, and
+//! library users can register backends of their own.
+
+mod asm;
+mod binary;
+mod c;
+mod compiled;
+mod demo;
+mod gas;
+mod gdb;
+mod histogram;
+mod json;
+mod kmd;
+mod rust;
+mod style;
+mod template;
+
+pub use self::asm::Armasm;
+pub use self::binary::Binary;
+pub use self::c::CHeader;
+pub use self::demo::Demo;
+pub use self::gas::Gas;
+pub use self::gdb::Gdb;
+pub use self::histogram::Histogram;
+pub use self::json::Json;
+pub use self::kmd::Kmd;
+pub use self::rust::RustModule;
+pub use self::style::{Comment, HexCase, Indent, LabelStyle, Style};
+pub use self::template::Template;
+
+use crate::animation::Animation;
+use crate::encoding::{Encoding, Endian, PixelFormat, RowOrder};
+use crate::font::Font;
+use crate::image::Pixel;
+use crate::palette::Palette;
+use crate::strip::Strips;
+use crate::tilemap::Tilemap;
+use crate::{Label, Options};
+use anyhow::Result;
+
+/// A file of output, named relative to the directory it is written into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputFile {
+    pub name: String,
+    pub contents: Vec<u8>,
+}
+
+/// Something which writes packed assets out as files, picked by its name
+/// with `--emit`.
+pub trait OutputBackend {
+    /// The name `--emit` picks the backend by.
+    fn name(&self) -> &str;
+
+    /// Write the assets, normally into a single file named after
+    /// [`Packed::file_name`].
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>>;
+}
+
+/// The backends which can be picked by name, starting with the built in
+/// ones.
+pub struct Backends {
+    backends: Vec<Box<dyn OutputBackend>>,
+}
+
+impl Default for Backends {
+    fn default() -> Self {
+        let mut backends = Backends {
+            backends: Vec::new(),
+        };
+        backends.register(Armasm);
+        backends.register(Gas);
+        backends.register(CHeader);
+        backends.register(RustModule);
+        backends.register(Json);
+        backends.register(Binary);
+        backends.register(Kmd);
+        backends.register(Gdb);
+        backends
+    }
+}
+
+impl Backends {
+    /// Add a backend, replacing any with the same name.
+    pub fn register(&mut self, backend: impl OutputBackend + 'static) {
+        self.backends
+            .retain(|registered| registered.name() != backend.name());
+        self.backends.push(Box::new(backend));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn OutputBackend> {
+        self.backends
+            .iter()
+            .find(|backend| backend.name() == name)
+            .map(AsRef::as_ref)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.backends.iter().map(|backend| backend.name())
+    }
+}
+
+/// An asset after packing, as it is written into the asset table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedAsset {
+    pub name: String,
+    pub label: Label,
+    /// The size is lost for assets carried over by a merge.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// The pixels in the output's format.
+    pub data: Vec<u8>,
+    /// The alpha plane, when it is written separately.
+    pub alpha: Option<Vec<u8>>,
+    /// Left out of the asset table and its constants, for data only used by
+    /// generated code.
+    pub private: bool,
+    /// Where it is drawn in the order from back to front, for
+    /// [`Options::priorities`].
+    pub priority: Option<i32>,
+    /// Where the odd rows start in the data and in the alpha plane, when the
+    /// image is written as two fields.
+    pub odd_field: Option<(usize, usize)>,
+    /// The pixels to skip and then draw along each row, in the order the
+    /// rows are written, for [`Options::spans`].
+    pub spans: Option<Vec<Vec<Span>>>,
+    /// Every bit of the transparent pixels set, laid out like the data, when
+    /// the asset is drawn by a routine written in its place, for
+    /// [`Options::compiled_sprites`].
+    pub mask: Option<Vec<u8>>,
+    /// How many pixels use each palette index, for [`Options::histogram`].
+    pub histogram: Option<Vec<usize>>,
+    /// A bit per pixel set along the edge of the opaque pixels, for
+    /// [`Options::outlines`].
+    pub outline: Option<Vec<u8>>,
+}
+
+/// A run of transparent pixels to skip followed by a run of opaque pixels
+/// to draw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub skip: u32,
+    pub draw: u32,
+}
+
+impl Span {
+    /// The span as it is written, with the pixels to skip in the low
+    /// halfword and the pixels to draw in the high one.
+    pub(crate) fn word(&self) -> u32 {
+        self.skip | self.draw << 16
+    }
+}
+
+/// Everything the backends write, once the palette is built and the pixels
+/// are packed.
+pub struct Packed {
+    pub(crate) options: Options,
+    pub(crate) palette: Palette,
+    pub(crate) font: Option<Font>,
+    pub(crate) tilemap: Option<Tilemap>,
+    pub(crate) assets: Vec<PackedAsset>,
+    pub(crate) animations: Vec<Animation>,
+    /// The images split up by [`Options::max_asset_size`].
+    pub(crate) strips: Vec<Strips>,
+    /// The palette lock to write, for [`Options::palette_lock`].
+    pub(crate) palette_lock: Option<String>,
+    /// The [`crate::Conversion::fingerprint`], once it's known, for
+    /// [`Options::json_fingerprint`].
+    pub(crate) fingerprint: Option<String>,
+}
+
+impl Packed {
+    /// The name of the assembly file, which other files are named after.
+    pub fn file_name(&self) -> &str {
+        &self.options.file_name
+    }
+
+    /// The assembly file's name with a different extension.
+    pub fn file_name_with(&self, extension: &str) -> String {
+        let name = self.file_name();
+        let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+        format!("{}.{}", stem, extension)
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.options.format
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.options.endian
+    }
+
+    /// The shared palette, which is empty for direct colour formats.
+    pub fn palette(&self) -> &[Pixel] {
+        match self.format() {
+            PixelFormat::Indexed => self.palette.colours(),
+            _ => &[],
+        }
+    }
+
+    /// How many bits each palette index takes, for the indexed format.
+    pub fn bits_per_colour(&self) -> Option<usize> {
+        let encoding = self.encoding();
+        (encoding.format == PixelFormat::Indexed).then_some(encoding.bits_per_colour)
+    }
+
+    /// The index of the palette's first colour, after the indices reserved
+    /// for the system, for the indexed format.
+    pub fn palette_offset(&self) -> Option<usize> {
+        (self.format() == PixelFormat::Indexed).then_some(self.options.reserved_indices)
+    }
+
+    /// How many palette indices are packed into each byte, for the indexed
+    /// format with at most 256 colours. Past that each index is a halfword.
+    pub fn pixels_per_byte(&self) -> Option<usize> {
+        let encoding = self.encoding();
+        encoding
+            .pixels_per_byte
+            .filter(|_| encoding.format == PixelFormat::Indexed)
+    }
+
+    /// Every asset, in the order they are written.
+    pub fn assets(&self) -> &[PackedAsset] {
+        &self.assets
+    }
+
+    /// The assets in the asset table, which leaves out private ones, along
+    /// with where they are in [`Packed::assets`].
+    pub fn table(&self) -> impl Iterator<Item = (usize, &PackedAsset)> {
+        self.assets
+            .iter()
+            .enumerate()
+            .filter(|(_, asset)| !asset.private)
+    }
+
+    /// The assets in the asset table from the lowest priority to the
+    /// highest, which is the order to draw them in from back to front.
+    /// Assets without a priority are at 0, and ties keep their table order.
+    pub fn by_priority(&self) -> Vec<&PackedAsset> {
+        let mut assets: Vec<&PackedAsset> = self.table().map(|(_, asset)| asset).collect();
+        assets.sort_by_key(|asset| asset.priority.unwrap_or_default());
+        assets
+    }
+
+    pub fn animations(&self) -> &[Animation] {
+        &self.animations
+    }
+
+    /// Whether there is a font or map, which only the assembly holds.
+    pub fn has_font_or_map(&self) -> bool {
+        self.font.is_some() || self.tilemap.is_some()
+    }
+
+    /// The bytes from one row of an asset to the next, when rows are
+    /// aligned with [`Options::row_align`].
+    pub fn stride(&self, asset: &PackedAsset) -> Option<usize> {
+        self.options.row_align?;
+        asset
+            .width
+            .map(|width| self.encoding().stride(width) * self.interleave())
+    }
+
+    /// How far into an asset's data each of its rows starts, from the top
+    /// row down whatever order they're written in, for [`Options::row_labels`].
+    pub fn row_offsets(&self, asset: &PackedAsset) -> Option<Vec<usize>> {
+        let (width, height) = asset.width.zip(asset.height)?;
+        let stride = self.encoding().row_bytes(width)? * self.interleave();
+        let height = height as usize;
+
+        // each field is written in the row order by itself
+        let rows = (0..height)
+            .map(|row| {
+                let (start, row, rows) = match asset.odd_field {
+                    Some((odd, _)) if row % 2 == 1 => (odd, row / 2, height / 2),
+                    Some(_) => (0, row / 2, height.div_ceil(2)),
+                    None => (0, row, height),
+                };
+                match self.options.row_order {
+                    RowOrder::TopDown => start + row * stride,
+                    RowOrder::BottomUp => start + (rows - 1 - row) * stride,
+                }
+            })
+            .collect();
+        Some(rows)
+    }
+
+    /// How many bytes each byte of pixels takes up, which is two when a
+    /// mask is written alongside them.
+    fn interleave(&self) -> usize {
+        match self.options.masked {
+            true => 2,
+            false => 1,
+        }
+    }
+
+    /// The number of mask and data word pairs an asset's data is made of,
+    /// for [`Options::masked`].
+    pub fn mask_pairs(&self, asset: &PackedAsset) -> Option<usize> {
+        // assets carried over by a merge were packed by another run
+        asset.width.filter(|_| self.options.masked)?;
+        Some(asset.data.len() / (2 * MASK_WORD_BYTES))
+    }
+
+    /// The CRC-32 of an asset's data and alpha plane, for
+    /// [`Options::asset_hashes`], which is the same on every build of the
+    /// same pixels.
+    pub fn hash(&self, asset: &PackedAsset) -> Option<u32> {
+        if !self.options.asset_hashes {
+            return None;
+        }
+
+        let mut crc = flate2::Crc::new();
+        crc.update(&asset.data);
+        crc.update(asset.alpha.as_deref().unwrap_or_default());
+        Some(crc.sum())
+    }
+
+    /// The label of the asset called `name`, which is the name after an
+    /// underscore unless [`Options::label_style`] changed its case.
+    pub(crate) fn label(&self, name: &str) -> Label {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .map_or_else(|| format!("_{}", name), |asset| asset.label.clone())
+    }
+
+    /// The copies of an asset moved right a pixel at a time by
+    /// [`Options::preshift`], in order of the shift.
+    pub fn shifted_copies(&self, asset: &PackedAsset) -> Vec<&PackedAsset> {
+        (0..self.options.preshift)
+            .filter_map(|shift| {
+                let name = format!("{}_Shift{}", asset.name, shift);
+                self.assets.iter().find(|copy| copy.name == name)
+            })
+            .collect()
+    }
+
+    /// Where each asset ends up when the output is loaded at `base`, following
+    /// the layout the assembly is written in: the palette and its shades, then
+    /// the assets in the order they are written.
+    pub(crate) fn addresses(&self, base: u32) -> Vec<u32> {
+        let palette = match self.options.palette_output {
+            PaletteOutput::Omitted => 0,
+            _ => 4 * self.palette().len() as u32,
+        };
+        let mut address = base + palette * (1 + self.options.shade_levels);
+
+        let mut addresses = Vec::new();
+        for asset in self.assets() {
+            if self.options.align_assets {
+                address = address.next_multiple_of(4);
+            }
+            addresses.push(address);
+            address += (asset.data.len() + asset.alpha.as_ref().map_or(0, Vec::len)) as u32;
+        }
+
+        addresses
+    }
+
+    /// What the asset table holds for an asset.
+    pub(crate) fn table_entry(&self, label: &str) -> String {
+        match self.options.table {
+            AssetTable::Addresses => label.to_owned(),
+            AssetTable::Offsets => {
+                let base = self.options.table_base.as_deref();
+                format!("{} - {}", label, base.unwrap_or(TABLE_LABEL))
+            }
+        }
+    }
+
+    pub(crate) fn encoding(&self) -> Encoding<'_> {
+        Encoding::new(&self.palette, &self.options)
+    }
+}
+
+/// The bytes in each mask word and each data word of masked sprites.
+pub(crate) const MASK_WORD_BYTES: usize = 4;
+
+/// The label at the start of the asset table.
+pub(crate) const TABLE_LABEL: &str = "AssetAddressTable";
+
+/// The label at the start of the table of assets sorted by priority.
+pub(crate) const PRIORITY_TABLE_LABEL: &str = "AssetPriorityTable";
+
+/// What each entry of the asset table holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum AssetTable {
+    /// The asset's address.
+    #[default]
+    Addresses,
+    /// How far the asset is from the start of the table, or from another
+    /// label, so the data can be moved without fixing up the table.
+    Offsets,
+}
+
+/// How a loader walking the asset table at runtime finds its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TableTerminator {
+    /// A zero word after the last entry.
+    Zero,
+    /// A word holding the number of entries just before the first.
+    Length,
+}
+
+/// Whether the palette is written with the assets, by itself or not at all,
+/// so it can live in a file of its own which rarely changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaletteOutput {
+    /// The palette and the assets together.
+    #[default]
+    Included,
+    /// Only the palette and its shades.
+    Only,
+    /// Everything but the palette, which is written elsewhere.
+    Omitted,
+}
+
+/// How the main file pulls in the parts of a split output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum IncludeDirective {
+    /// `GET file`, as armasm writes it.
+    #[default]
+    Get,
+    /// `INCLUDE file`, armasm's other spelling.
+    Include,
+    /// `.include "file"`, for the GNU assembler.
+    Gas,
+}
+
+impl IncludeDirective {
+    pub(crate) fn line(self, file: &str) -> String {
+        match self {
+            IncludeDirective::Get => format!("\tGET {}", file),
+            IncludeDirective::Include => format!("\tINCLUDE {}", file),
+            IncludeDirective::Gas => format!("\t.include \"{}\"", file),
+        }
+    }
+}
+
+/// The name of the part of a split output holding one asset, next to the
+/// main file and named after it.
+pub(crate) fn part_name(file_name: &str, asset: &str) -> String {
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) => (stem, format!(".{}", extension)),
+        None => (file_name, String::new()),
+    };
+
+    format!("{}_{}{}", stem, asset, extension)
+}
@@ -0,0 +1,163 @@
+//! A Rust module with the assets as byte arrays, for `include!`ing into
+//! firmware written in Rust.
+
+use super::{OutputBackend, OutputFile, Packed, PaletteOutput};
+use anyhow::Result;
+use std::fmt::Write as FmtWrite;
+
+/// Writes the palette and assets as `static` arrays, with constants for the
+/// asset numbers.
+pub struct RustModule;
+
+impl OutputBackend for RustModule {
+    fn name(&self) -> &str {
+        "rust"
+    }
+
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>> {
+        let mut buf = String::new();
+        writeln!(buf, "// Generated by image2arm, do not edit.\n")?;
+
+        let palette_output = packed.options.palette_output;
+        if palette_output != PaletteOutput::Only {
+            if let Some(bits) = packed.bits_per_colour() {
+                writeln!(buf, "pub const BITS_PER_COLOUR: usize = {};", bits)?;
+                match packed.pixels_per_byte() {
+                    Some(pixels) => {
+                        writeln!(buf, "pub const PIXELS_PER_BYTE: usize = {};", pixels)?
+                    }
+                    None => writeln!(buf, "pub const BYTES_PER_PIXEL: usize = 2;")?,
+                }
+            }
+        }
+        if packed.bits_per_colour().is_some() && palette_output != PaletteOutput::Omitted {
+            if let Some(offset) = packed.palette_offset().filter(|&offset| offset > 0) {
+                writeln!(buf, "pub const PALETTE_OFFSET: usize = {};", offset)?;
+            }
+            writeln!(
+                buf,
+                "pub static PALETTE: [[u8; 4]; {}] = [",
+                packed.palette().len()
+            )?;
+            for colour in packed.palette() {
+                writeln!(
+                    buf,
+                    "    [0x{:02X}, 0x{:02X}, 0x{:02X}, 0x{:02X}],",
+                    colour.r, colour.g, colour.b, colour.a
+                )?;
+            }
+            writeln!(buf, "];\n")?;
+        }
+        if palette_output == PaletteOutput::Only {
+            return Ok(vec![module(packed, buf)]);
+        }
+
+        if let Some(cipher) = &packed.options.cipher {
+            for (name, value) in cipher.constants() {
+                writeln!(buf, "pub const {}: u32 = {};", name, value)?;
+            }
+        }
+        writeln!(
+            buf,
+            "pub const ASSET_MAX: usize = {};",
+            packed.table().count()
+        )?;
+        for (index, (_, asset)) in packed.table().enumerate() {
+            writeln!(
+                buf,
+                "pub const ASSET_{}: usize = {};",
+                ident(&asset.name),
+                index
+            )?;
+        }
+
+        for asset in packed.assets() {
+            let ident = ident(&asset.name);
+            buf.push('\n');
+            if let (Some(width), Some(height)) = (asset.width, asset.height) {
+                writeln!(buf, "pub const {}_WIDTH: u32 = {};", ident, width)?;
+                writeln!(buf, "pub const {}_HEIGHT: u32 = {};", ident, height)?;
+            }
+            if let Some(stride) = packed.stride(asset) {
+                writeln!(buf, "pub const {}_STRIDE: usize = {};", ident, stride)?;
+            }
+            if let Some((odd, _)) = asset.odd_field {
+                writeln!(buf, "pub const {}_ODD_FIELD: usize = {};", ident, odd)?;
+            }
+            if let Some(hash) = packed.hash(asset) {
+                writeln!(buf, "pub const {}_HASH: u32 = 0x{:08X};", ident, hash)?;
+            }
+            if let Some(priority) = asset.priority {
+                writeln!(buf, "pub const {}_PRIORITY: i32 = {};", ident, priority)?;
+            }
+            if let Some(pairs) = packed.mask_pairs(asset) {
+                writeln!(buf, "pub const {}_PAIRS: usize = {};", ident, pairs)?;
+            }
+            write_array(&mut buf, &ident, &asset.data)?;
+            if let Some(alpha) = &asset.alpha {
+                write_array(&mut buf, &format!("{}_ALPHA", ident), alpha)?;
+            }
+        }
+
+        // the table lets code pick an asset by its number, like the assembly's
+        writeln!(
+            buf,
+            "\npub static ASSETS: [&[u8]; {}] = [",
+            packed.table().count()
+        )?;
+        for (_, asset) in packed.table() {
+            writeln!(buf, "    &{},", ident(&asset.name))?;
+        }
+        writeln!(buf, "];")?;
+
+        // the numbers of the assets from the back to the front
+        if packed.options.priority_table {
+            let order = packed.by_priority();
+            writeln!(
+                buf,
+                "\npub static PRIORITY_ORDER: [usize; {}] = [",
+                order.len()
+            )?;
+            for asset in order {
+                writeln!(buf, "    ASSET_{},", ident(&asset.name))?;
+            }
+            writeln!(buf, "];")?;
+        }
+
+        Ok(vec![module(packed, buf)])
+    }
+}
+
+fn module(packed: &Packed, buf: String) -> OutputFile {
+    OutputFile {
+        name: packed.file_name_with("rs"),
+        contents: buf.into_bytes(),
+    }
+}
+
+fn write_array(buf: &mut String, ident: &str, bytes: &[u8]) -> Result<()> {
+    writeln!(buf, "pub static {}: [u8; {}] = [", ident, bytes.len())?;
+    for row in bytes.chunks(12) {
+        let row: Vec<String> = row.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+        writeln!(buf, "    {},", row.join(", "))?;
+    }
+    writeln!(buf, "];")?;
+
+    Ok(())
+}
+
+/// Turn an asset name into an upper case Rust identifier.
+fn ident(name: &str) -> String {
+    let ident: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect();
+
+    match ident.starts_with(|c: char| c.is_ascii_digit()) {
+        true => format!("_{}", ident),
+        false => ident,
+    }
+}
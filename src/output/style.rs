@@ -0,0 +1,252 @@
+//! Reformatting finished assembly to match a project's formatting rules,
+//! which is done once over the text rather than by every writer.
+
+use std::str::FromStr;
+
+/// The case of the digits in hex literals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum HexCase {
+    /// `0xAB`.
+    #[default]
+    Upper,
+    /// `0xab`.
+    Lower,
+}
+
+/// The case asset names are written in where they make up labels and
+/// constants. Without one, the names are used as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LabelStyle {
+    /// Every letter upper case, so `hero_walk` becomes `HERO_WALK`.
+    Upper,
+    /// Every letter lower case, so `Hero_Walk` becomes `hero_walk`.
+    Lower,
+    /// The words run together with capitals, so `hero_walk` becomes `heroWalk`.
+    Camel,
+    /// The words in lower case joined by underscores, so `heroWalk` becomes
+    /// `hero_walk`.
+    Snake,
+}
+
+impl LabelStyle {
+    pub(crate) fn apply(self, name: &str) -> String {
+        match self {
+            LabelStyle::Upper => name.to_uppercase(),
+            LabelStyle::Lower => name.to_lowercase(),
+            LabelStyle::Camel => words(name)
+                .iter()
+                .enumerate()
+                .map(|(index, word)| {
+                    let word = word.to_lowercase();
+                    let mut chars = word.chars();
+                    match (index, chars.next()) {
+                        (0, _) | (_, None) => word,
+                        (_, Some(first)) => first.to_uppercase().chain(chars).collect(),
+                    }
+                })
+                .collect(),
+            LabelStyle::Snake => words(name)
+                .iter()
+                .map(|word| word.to_lowercase())
+                .collect::<Vec<_>>()
+                .join("_"),
+        }
+    }
+}
+
+/// Split a name into words at anything but a letter or digit, and where a
+/// capital starts a word after a lower case letter or digit, or starts the
+/// last word of a run of capitals like `HTTPServer`.
+fn words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (index, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+
+        let previous = index.checked_sub(1).map(|index| chars[index]);
+        let next = chars.get(index + 1);
+        let starts = c.is_uppercase()
+            && (previous.is_some_and(|p| p.is_lowercase() || p.is_ascii_digit())
+                || (previous.is_some_and(char::is_uppercase)
+                    && next.is_some_and(|n| n.is_lowercase())));
+        if starts && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+/// What indents instructions and separates their fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Indent {
+    #[default]
+    Tab,
+    /// Indent with this many spaces, and separate fields with a single space.
+    Spaces(usize),
+}
+
+impl FromStr for Indent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tab" => Ok(Indent::Tab),
+            _ => s
+                .parse()
+                .map(Indent::Spaces)
+                .map_err(|_| format!("expected 'tab' or a number of spaces, found '{}'", s)),
+        }
+    }
+}
+
+/// What starts a comment, for assemblers or teams which want another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Comment {
+    #[value(name = ";")]
+    Semicolon,
+    #[value(name = "@")]
+    At,
+    #[value(name = "//")]
+    Slashes,
+}
+
+impl Comment {
+    /// A line without whichever comment it has.
+    pub(crate) fn strip(line: &str) -> &str {
+        let start = [Comment::Semicolon, Comment::At, Comment::Slashes]
+            .iter()
+            .filter_map(|comment| line.find(comment.marker()))
+            .min();
+        match start {
+            Some(start) => &line[..start],
+            None => line,
+        }
+    }
+
+    pub(crate) fn marker(self) -> &'static str {
+        match self {
+            Comment::Semicolon => ";",
+            Comment::At => "@",
+            Comment::Slashes => "//",
+        }
+    }
+}
+
+/// How the assembly is laid out. The default leaves it as it is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    pub hex_case: HexCase,
+    pub indent: Indent,
+    /// The column the rest of a line starts at after a label, padded with
+    /// spaces, instead of the padding each table picks for itself.
+    pub label_column: Option<usize>,
+    /// What starts comments, instead of the assembler's usual character.
+    pub comment: Option<Comment>,
+}
+
+impl Style {
+    /// Reformat assembly, leaving comments starting with `comment` alone
+    /// apart from what starts them.
+    pub(crate) fn apply(&self, asm: &str, comment: char) -> String {
+        if *self == Style::default() {
+            return asm.to_owned();
+        }
+
+        let mut out = String::with_capacity(asm.len());
+        for line in asm.lines() {
+            let (code, comment) = match line.find(comment) {
+                Some(start) => line.split_at(start),
+                None => (line, ""),
+            };
+            out.push_str(&self.apply_line(code));
+            match self.comment {
+                // the comment characters are all ASCII
+                Some(marker) if !comment.is_empty() => {
+                    out.push_str(marker.marker());
+                    out.push_str(&comment[1..]);
+                }
+                _ => out.push_str(comment),
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+
+    fn apply_line(&self, code: &str) -> String {
+        let mut code = match self.hex_case {
+            HexCase::Upper => code.to_owned(),
+            HexCase::Lower => lower_hex(code),
+        };
+
+        if let Indent::Spaces(width) = self.indent {
+            let body = code.trim_start_matches('\t');
+            let depth = code.len() - body.len();
+            code = format!("{}{}", " ".repeat(depth * width), body.replace('\t', " "));
+        }
+
+        // only lines with a label and something after it are aligned
+        let label_column = match self.label_column {
+            Some(column) if !code.starts_with(char::is_whitespace) => column,
+            _ => return code,
+        };
+        match code.split_once(char::is_whitespace) {
+            Some((label, rest)) if !rest.trim().is_empty() => {
+                // padding inside the line was lining it up with the old columns
+                let mut previous = ' ';
+                let rest: String = rest
+                    .trim_start()
+                    .chars()
+                    .filter(|&c| {
+                        let keep = !(c == ' ' && previous == ' ');
+                        previous = c;
+                        keep
+                    })
+                    .collect();
+                match label.len() < label_column {
+                    true => format!("{:<width$}{}", label, rest, width = label_column),
+                    false => format!("{} {}", label, rest),
+                }
+            }
+            _ => code,
+        }
+    }
+}
+
+/// Lower the digits of every `0x` literal, leaving labels which happen to
+/// contain hex digits alone.
+fn lower_hex(code: &str) -> String {
+    let mut out = String::with_capacity(code.len());
+    let mut in_literal = false;
+    let mut previous = ' ';
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        let starts_literal = c == '0'
+            && chars.peek() == Some(&'x')
+            && !(previous.is_ascii_alphanumeric() || previous == '_');
+        if starts_literal {
+            in_literal = true;
+        } else if in_literal && !(c == 'x' && previous == '0') && !c.is_ascii_hexdigit() {
+            in_literal = false;
+        }
+
+        out.push(match in_literal {
+            true => c.to_ascii_lowercase(),
+            false => c,
+        });
+        previous = c;
+    }
+
+    out
+}
@@ -0,0 +1,191 @@
+//! Output shaped by a Handlebars template, for assemblers and formats with
+//! no backend of their own.
+
+use super::{OutputBackend, OutputFile, Packed};
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::encoding::{Endian, PixelFormat};
+use anyhow::Result;
+use handlebars::Handlebars;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// The bytes on each line of `hex_rows`.
+const BYTES_PER_ROW: usize = 8;
+
+/// Renders a template with the palette and packed assets, writing a file
+/// named after the template without its last extension, so `assets.inc.hbs`
+/// writes `assets.inc`.
+pub struct Template {
+    path: PathBuf,
+    name: String,
+    source: String,
+}
+
+#[derive(Serialize)]
+struct Context<'a> {
+    file_name: &'a str,
+    format: PixelFormat,
+    big_endian: bool,
+    bits_per_colour: Option<usize>,
+    palette_offset: Option<usize>,
+    pixels_per_byte: Option<usize>,
+    palette: Vec<Colour>,
+    assets: Vec<Asset<'a>>,
+    animations: Vec<Animation<'a>>,
+}
+
+#[derive(Serialize)]
+struct Colour {
+    index: usize,
+    r: u8,
+    g: u8,
+    b: u8,
+    a: u8,
+    hex: String,
+}
+
+#[derive(Serialize)]
+struct Asset<'a> {
+    index: usize,
+    name: &'a str,
+    label: &'a str,
+    width: Option<u32>,
+    height: Option<u32>,
+    size: usize,
+    stride: Option<usize>,
+    odd_field: Option<usize>,
+    mask_pairs: Option<usize>,
+    hash: Option<u32>,
+    priority: Option<i32>,
+    bytes: &'a [u8],
+    hex_rows: Vec<String>,
+    alpha_hex_rows: Option<Vec<String>>,
+    private: bool,
+}
+
+#[derive(Serialize)]
+struct Animation<'a> {
+    name: &'a str,
+    frames: &'a [String],
+    durations: Option<&'a [u32]>,
+}
+
+impl Template {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path).map_err(|e| {
+            Diagnostic::new(
+                FailureClass::Input,
+                format!("failed to read template: {}", e),
+            )
+            .with_file(path)
+            .with_suggestion("check that the path exists and is readable")
+        })?;
+
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| {
+                Diagnostic::new(
+                    FailureClass::Input,
+                    "couldn't parse a file name from the path",
+                )
+                .with_file(path)
+            })?
+            .to_owned();
+
+        Ok(Template {
+            path: path.to_owned(),
+            name,
+            source,
+        })
+    }
+}
+
+impl OutputBackend for Template {
+    fn name(&self) -> &str {
+        "template"
+    }
+
+    fn emit(&self, packed: &Packed) -> Result<Vec<OutputFile>> {
+        let context = Context {
+            file_name: packed.file_name(),
+            format: packed.format(),
+            big_endian: packed.endian() == Endian::Big,
+            bits_per_colour: packed.bits_per_colour(),
+            palette_offset: packed.palette_offset(),
+            pixels_per_byte: packed.pixels_per_byte(),
+            palette: packed
+                .palette()
+                .iter()
+                .enumerate()
+                .map(|(index, c)| Colour {
+                    index: packed.options.reserved_indices + index,
+                    r: c.r,
+                    g: c.g,
+                    b: c.b,
+                    a: c.a,
+                    hex: format!("#{:02X}{:02X}{:02X}{:02X}", c.r, c.g, c.b, c.a),
+                })
+                .collect(),
+            assets: packed
+                .assets()
+                .iter()
+                .enumerate()
+                .map(|(index, asset)| Asset {
+                    index,
+                    name: &asset.name,
+                    label: &asset.label,
+                    width: asset.width,
+                    height: asset.height,
+                    size: asset.data.len(),
+                    stride: packed.stride(asset),
+                    odd_field: asset.odd_field.map(|(odd, _)| odd),
+                    mask_pairs: packed.mask_pairs(asset),
+                    hash: packed.hash(asset),
+                    priority: asset.priority,
+                    bytes: &asset.data,
+                    hex_rows: hex_rows(&asset.data),
+                    alpha_hex_rows: asset.alpha.as_deref().map(hex_rows),
+                    private: asset.private,
+                })
+                .collect(),
+            animations: packed
+                .animations()
+                .iter()
+                .map(|animation| Animation {
+                    name: animation.name(),
+                    frames: animation.frames(),
+                    durations: animation.durations(),
+                })
+                .collect(),
+        };
+
+        // the output is code, not HTML, and a misspelt variable should fail
+        let mut handlebars = Handlebars::new();
+        handlebars.register_escape_fn(handlebars::no_escape);
+        handlebars.set_strict_mode(true);
+        let text = handlebars
+            .render_template(&self.source, &context)
+            .map_err(|e| {
+                Diagnostic::new(FailureClass::Usage, format!("the template failed: {}", e))
+                    .with_file(&self.path)
+                    .with_suggestion("check the variable names against the README")
+            })?;
+
+        Ok(vec![OutputFile {
+            name: self.name.clone(),
+            contents: text.into_bytes(),
+        }])
+    }
+}
+
+/// The bytes as rows of comma separated hex, ready to follow a directive.
+fn hex_rows(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(BYTES_PER_ROW)
+        .map(|row| {
+            let row: Vec<String> = row.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            row.join(", ")
+        })
+        .collect()
+}
@@ -0,0 +1,402 @@
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::image::{Image, Pixel};
+use crate::Label;
+use anyhow::Result;
+use log::{debug, trace};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt::Write as FmtWrite;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colours: Vec<Pixel>,
+    /// Colours which were merged into another entry, and the colour they
+    /// were merged into.
+    merged: HashMap<Pixel, Pixel>,
+    /// The index of every colour, including merged ones, built once so
+    /// packing doesn't search the palette for each pixel.
+    indices: HashMap<Pixel, u16>,
+}
+
+impl Palette {
+    pub(crate) fn new_from_images<'a, I>(images: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Image> + Clone,
+    {
+        // collect the unique pixels in the order they're first seen, so the
+        // same images always give the same palette
+        let mut seen: HashSet<Pixel> = HashSet::new();
+        let colours = images
+            .clone()
+            .into_iter()
+            .flat_map(|image| image.iter().copied())
+            .filter(|pixel| seen.insert(*pixel))
+            .collect();
+
+        let palette = Palette::from_parts(colours, HashMap::new());
+
+        if log::log_enabled!(log::Level::Debug) {
+            palette.log_usage(images);
+        }
+
+        palette
+    }
+
+    /// Merge colours whose channels all differ by no more than `tolerance`
+    /// into one entry, so noise from lossy tools doesn't grow the palette.
+    /// The most used colour of each group is the one kept.
+    pub(crate) fn merge<'a>(
+        self,
+        images: impl IntoIterator<Item = &'a Image>,
+        tolerance: u8,
+    ) -> Self {
+        if tolerance == 0 {
+            return self;
+        }
+
+        let mut usage: HashMap<Pixel, usize> = HashMap::new();
+        for pixel in images.into_iter().flat_map(Image::iter) {
+            *usage.entry(*pixel).or_default() += 1;
+        }
+
+        // most used first, then by value so ties always merge the same way
+        let mut colours = self.colours;
+        colours.sort_by_key(|colour| {
+            let used = usage.get(colour).copied().unwrap_or_default();
+            (
+                std::cmp::Reverse(used),
+                colour.r,
+                colour.g,
+                colour.b,
+                colour.a,
+            )
+        });
+
+        let close = |a: &Pixel, b: &Pixel| {
+            a.iter()
+                .zip(b.iter())
+                .all(|(a, b)| a.abs_diff(b) <= tolerance)
+        };
+
+        let mut kept_colours: Vec<Pixel> = Vec::new();
+        let mut merged = HashMap::new();
+        for colour in colours {
+            match kept_colours.iter().find(|kept| close(kept, &colour)) {
+                Some(&kept) => {
+                    debug!("merged {} into {}", hex(&colour), hex(&kept));
+                    merged.insert(colour, kept);
+                }
+                None => kept_colours.push(colour),
+            }
+        }
+
+        Palette::from_parts(kept_colours, merged)
+    }
+
+    /// Move colours to fixed indices, for hardware palettes where some entries
+    /// have a meaning of their own, filling the other slots with the rest of
+    /// the colours in order. Pinned colours needn't be used by any image.
+    pub(crate) fn pin(self, pins: &[(Pixel, usize)]) -> Self {
+        if pins.is_empty() {
+            return self;
+        }
+
+        let mut merged = self.merged;
+        let mut rest = self
+            .colours
+            .into_iter()
+            .filter(|colour| !pins.iter().any(|(pinned, _)| pinned == colour));
+
+        let slots = pins
+            .iter()
+            .map(|(_, index)| index + 1)
+            .max()
+            .unwrap_or_default();
+        let mut colours = Vec::with_capacity(slots);
+        let mut gaps = Vec::new();
+        for slot in 0..slots {
+            match pins.iter().find(|(_, index)| *index == slot) {
+                Some((pinned, _)) => {
+                    // a pinned colour always keeps its own entry
+                    merged.remove(pinned);
+                    colours.push(*pinned);
+                }
+                None => match rest.next() {
+                    Some(colour) => colours.push(colour),
+                    None => {
+                        // held by a pinned colour until the gaps are filled below
+                        gaps.push(slot);
+                        colours.push(pins[0].0);
+                    }
+                },
+            }
+        }
+        colours.extend(rest);
+
+        // gaps past the last colour are filled with colours no image uses
+        for slot in gaps {
+            colours[slot] = unused_colour(&colours, &merged);
+        }
+
+        Palette::from_parts(colours, merged)
+    }
+
+    fn from_parts(colours: Vec<Pixel>, merged: HashMap<Pixel, Pixel>) -> Self {
+        // direct colour palettes can be far bigger, but are never indexed
+        let mut indices = HashMap::with_capacity(colours.len() + merged.len());
+        for (index, colour) in colours.iter().enumerate() {
+            if let Ok(index) = u16::try_from(index) {
+                indices.entry(*colour).or_insert(index);
+            }
+        }
+        for (colour, kept) in merged.iter() {
+            if let Some(&index) = indices.get(kept) {
+                indices.entry(*colour).or_insert(index);
+            }
+        }
+
+        Palette {
+            colours,
+            merged,
+            indices,
+        }
+    }
+
+    /// Log how many colours each image brings and how many pixels map to each entry,
+    /// which is what you need to know when the palette is bigger than expected.
+    fn log_usage<'a>(&self, images: impl IntoIterator<Item = &'a Image>) {
+        let mut usage: HashMap<Pixel, usize> = HashMap::new();
+        for image in images {
+            let unique: HashSet<&Pixel> = image.iter().collect();
+            debug!("{} uses {} unique colours", image.name(), unique.len());
+            for pixel in image.iter() {
+                *usage.entry(*pixel).or_default() += 1;
+            }
+        }
+
+        for (index, colour) in self.colours.iter().enumerate() {
+            trace!(
+                "palette[{}] = #{:02X}{:02X}{:02X}{:02X} remaps {} pixels",
+                index,
+                colour.r,
+                colour.g,
+                colour.b,
+                colour.a,
+                usage.get(colour).copied().unwrap_or_default()
+            );
+        }
+    }
+
+    pub(crate) fn to_asm(&self, directive: &str) -> Result<String> {
+        // create a buffer to write into
+        let mut buf = String::new();
+
+        // first define a label for the start of the palette
+        let palette_label: Label = "Palette".into();
+        writeln!(buf, "{}", palette_label)?;
+
+        // now write out the colours
+        write_colours(&mut buf, directive, &self.colours)?;
+
+        Ok(buf)
+    }
+
+    /// Write `levels` copies of the palette running from darker to lighter,
+    /// so lighting can be done by switching palettes. The middle level of an
+    /// odd number is the palette unchanged.
+    pub(crate) fn shades_asm(&self, levels: u32, directive: &str) -> Result<String> {
+        let mut buf = String::new();
+        writeln!(buf, "SHADE_LEVELS\tEQU {}", levels)?;
+
+        for (level, colours) in self.shades(levels).iter().enumerate() {
+            writeln!(buf, "Palette_Shade{}", level)?;
+            write_colours(&mut buf, directive, colours)?;
+        }
+
+        Ok(buf)
+    }
+
+    /// The colours of each of the `levels` copies of the palette written by
+    /// [`Palette::shades_asm`].
+    pub(crate) fn shades(&self, levels: u32) -> Vec<Vec<Pixel>> {
+        let mut shades = Vec::with_capacity(levels as usize);
+        for level in 0..levels {
+            // mix towards black below the middle and towards white above it
+            let t = 2.0 * (level + 1) as f64 / (levels + 1) as f64;
+            let shade = |channel: u8| {
+                let channel = channel as f64;
+                let shaded = match t <= 1.0 {
+                    true => channel * t,
+                    false => channel + (255.0 - channel) * (t - 1.0),
+                };
+                shaded.round() as u8
+            };
+
+            let colours: Vec<Pixel> = self
+                .colours
+                .iter()
+                .map(|colour| {
+                    Pixel::new(shade(colour.r), shade(colour.g), shade(colour.b), colour.a)
+                })
+                .collect();
+            shades.push(colours);
+        }
+
+        shades
+    }
+
+    /// Write the palette in the index map format, so a later run can pin every
+    /// colour back where it was.
+    pub(crate) fn to_lock(&self) -> String {
+        let mut lock =
+            String::from("; palette lock written by image2arm, colours keep these indices\n");
+        for (index, colour) in self.colours.iter().enumerate() {
+            lock.push_str(&format!("{} -> {}\n", hex(colour), index));
+        }
+
+        lock
+    }
+
+    pub fn index(&self, colour: &Pixel) -> Option<usize> {
+        self.indices.get(colour).map(|&index| index as usize)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.colours.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.colours.is_empty()
+    }
+
+    /// The colours merged into another entry, along with the one they were
+    /// merged into.
+    pub(crate) fn merged(&self) -> impl Iterator<Item = (&Pixel, &Pixel)> {
+        self.merged.iter()
+    }
+
+    #[inline]
+    pub fn colours(&self) -> &[Pixel] {
+        &self.colours
+    }
+}
+
+/// A colour which isn't in the palette, used to fill gaps.
+fn unused_colour(colours: &[Pixel], merged: &HashMap<Pixel, Pixel>) -> Pixel {
+    (0..=u32::MAX)
+        .map(|value| {
+            let [r, g, b, a] = value.to_le_bytes();
+            Pixel::new(r, g, b, a)
+        })
+        .find(|colour| !colours.contains(colour) && !merged.contains_key(colour))
+        .unwrap_or_default()
+}
+
+/// Write each colour as a row of its channels, after the byte directive.
+fn write_colours(buf: &mut impl FmtWrite, directive: &str, colours: &[Pixel]) -> Result<()> {
+    for colour in colours.iter() {
+        writeln!(
+            buf,
+            "\t{} 0x{:02X}, 0x{:02X}, 0x{:02X}, 0x{:02X}",
+            directive, colour.r, colour.g, colour.b, colour.a
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write a colour as `#RRGGBBAA`.
+pub(crate) fn hex(colour: &Pixel) -> String {
+    format!(
+        "#{:02X}{:02X}{:02X}{:02X}",
+        colour.r, colour.g, colour.b, colour.a
+    )
+}
+
+/// Parse an index map, a line per colour like `#102030 -> 5` pinning that
+/// colour to palette index 5. Blank lines and lines starting `;` are skipped,
+/// and indices have to be below `indices`, the most the palette can hold.
+pub fn parse_index_map(text: &str, indices: usize) -> Result<Vec<(Pixel, usize)>, Diagnostic> {
+    let invalid = |reason: String| {
+        Diagnostic::new(
+            FailureClass::Usage,
+            format!("invalid index map: {}", reason),
+        )
+        .with_suggestion("write a line like `#102030 -> 5` per pinned colour")
+    };
+    let mut pins: Vec<(Pixel, usize)> = Vec::new();
+    for (number, line) in text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+    {
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let (colour, index) = line
+            .split_once("->")
+            .ok_or_else(|| invalid(format!("line {}: expected `#RRGGBB -> index`", number)))?;
+        let colour =
+            parse_colour(colour).map_err(|e| invalid(format!("line {}: {}", number, e)))?;
+        let index: usize = index.trim().parse().map_err(|e| {
+            invalid(format!(
+                "line {}: bad index '{}': {}",
+                number,
+                index.trim(),
+                e
+            ))
+        })?;
+        // a pin far past the end would fill every index before it
+        if index >= indices {
+            return Err(Diagnostic::new(
+                FailureClass::Palette,
+                format!(
+                    "invalid index map: line {}: index {} is past the {} indices a palette can have",
+                    number, index, indices
+                ),
+            )
+            .with_suggestion("palettes hold at most 65536 colours, including any reserved indices"));
+        }
+
+        if let Some((_, other)) = pins.iter().find(|(pinned, _)| *pinned == colour) {
+            return Err(invalid(format!(
+                "line {}: {} is already pinned to {}",
+                number,
+                hex(&colour),
+                other
+            )));
+        }
+        if let Some((other, _)) = pins.iter().find(|(_, pinned)| *pinned == index) {
+            return Err(invalid(format!(
+                "line {}: index {} is already taken by {}",
+                number,
+                index,
+                hex(other)
+            )));
+        }
+
+        pins.push((colour, index));
+    }
+
+    Ok(pins)
+}
+
+/// Parse a colour written as `#RRGGBB` or `#RRGGBBAA`, where a missing alpha
+/// means fully opaque and the `#` is optional.
+pub fn parse_colour(s: &str) -> Result<Pixel, String> {
+    let hex = s.trim().trim_start_matches('#');
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "expected a colour like #RRGGBB or #RRGGBBAA, found '{}'",
+            s
+        ));
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or_default();
+    let alpha = if hex.len() == 8 { channel(6) } else { 0xFF };
+
+    Ok(Pixel::new(channel(0), channel(2), channel(4), alpha))
+}
@@ -0,0 +1,124 @@
+//! Python bindings, for the many asset pipelines that are Python scripts.
+//!
+//! Build and install into the current virtualenv with `maturin develop`.
+
+use crate::{Options, Timings};
+use clap::ValueEnum;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+create_exception!(image2arm, ConversionError, PyException);
+
+/// Compile the images at the given paths into the text of an assembly file.
+#[pyfunction]
+#[pyo3(signature = (images, **options))]
+fn convert(images: Vec<PathBuf>, options: Option<&Bound<'_, PyDict>>) -> PyResult<String> {
+    let options = parse_options(options)?;
+    // the other outputs would be dropped on the floor
+    if options.emit != Options::default().emit {
+        return Err(PyTypeError::new_err(
+            "convert() only returns the assembly, use convert_files() with emit",
+        ));
+    }
+    crate::convert(&images, &options).map_err(conversion_error)
+}
+
+/// Compile the images at the given paths into every file `emit` names, as a
+/// dict of file names to their contents.
+#[pyfunction]
+#[pyo3(signature = (images, **options))]
+fn convert_files(
+    py: Python<'_>,
+    images: Vec<PathBuf>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<BTreeMap<String, Py<PyBytes>>> {
+    let options = parse_options(options)?;
+    let files = crate::convert_files(&images, &options).map_err(conversion_error)?;
+    Ok(files
+        .into_iter()
+        .map(|file| (file.name, PyBytes::new(py, &file.contents).unbind()))
+        .collect())
+}
+
+/// The colours of the palette `convert` writes for the images, as
+/// (r, g, b, a) tuples in index order after any reserved indices.
+#[pyfunction]
+#[pyo3(signature = (images, **options))]
+fn palette(
+    images: Vec<PathBuf>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Vec<(u8, u8, u8, u8)>> {
+    let options = parse_options(options)?;
+    let (images, animations) = crate::load_inputs(&images, &options).map_err(conversion_error)?;
+    let packed = crate::pack(images, animations, &options, &mut Timings::default())
+        .map_err(conversion_error)?;
+    Ok(packed
+        .palette()
+        .iter()
+        .map(|c| (c.r, c.g, c.b, c.a))
+        .collect())
+}
+
+fn conversion_error(e: anyhow::Error) -> PyErr {
+    ConversionError::new_err(format!("{:#}", e))
+}
+
+/// The options named by keyword arguments, spelt like the command line's
+/// flags with underscores. A typo shouldn't be silently ignored.
+fn parse_options(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Options> {
+    let mut options = Options::default();
+    let kwargs = match kwargs {
+        Some(kwargs) => kwargs,
+        None => return Ok(options),
+    };
+    for (key, value) in kwargs.iter() {
+        let key: String = key.extract()?;
+        match key.as_str() {
+            "format" => options.format = value_enum(&key, &value)?,
+            "endian" => options.endian = value_enum(&key, &value)?,
+            "emit" => options.emit = value.extract()?,
+            "merge_tolerance" => options.merge_tolerance = value.extract()?,
+            "index_map" => options.index_map = Some(value.extract()?),
+            "reserve_indices" => options.reserved_indices = value.extract()?,
+            _ => {
+                return Err(PyTypeError::new_err(format!(
+                    "got an unexpected keyword argument '{}'",
+                    key
+                )))
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+/// One of the values the command line accepts for the option, like "rgb565".
+fn value_enum<T: ValueEnum>(key: &str, value: &Bound<'_, PyAny>) -> PyResult<T> {
+    let name: String = value.extract()?;
+    T::from_str(&name, true).map_err(|_| {
+        let names: Vec<String> = T::value_variants()
+            .iter()
+            .filter_map(ValueEnum::to_possible_value)
+            .map(|value| value.get_name().to_owned())
+            .collect();
+        PyValueError::new_err(format!(
+            "{} can't be '{}', pick one of {}",
+            key,
+            name,
+            names.join(", ")
+        ))
+    })
+}
+
+#[pymodule]
+fn image2arm(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(convert, m)?)?;
+    m.add_function(wrap_pyfunction!(convert_files, m)?)?;
+    m.add_function(wrap_pyfunction!(palette, m)?)?;
+    m.add("ConversionError", m.py().get_type::<ConversionError>())?;
+    Ok(())
+}
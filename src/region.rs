@@ -0,0 +1,91 @@
+//! Cutting named rectangles out of an atlas, each of which becomes an asset,
+//! so sheets laid out by artists can be used without slicing them first.
+
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::image::Image;
+use std::str::FromStr;
+
+/// A named rectangle of an image, written `NAME=X,Y,WIDTH,HEIGHT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// Parse regions separated by semicolons, like `heart=0,0,8,8; coin=8,0,8,8`.
+    pub fn parse_list(s: &str) -> Result<Vec<Region>, String> {
+        let regions = s
+            .split(';')
+            .map(str::trim)
+            .filter(|region| !region.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Region>, _>>()?;
+        match regions.is_empty() {
+            true => Err(format!(
+                "expected NAME=X,Y,WIDTH,HEIGHT regions, found '{}'",
+                s
+            )),
+            false => Ok(regions),
+        }
+    }
+
+    /// Copy the region out of an image, which it has to lie inside.
+    pub(crate) fn cut(&self, image: &Image) -> Result<Image, Diagnostic> {
+        let inside = |start: u32, length: u32, size: u32| {
+            start.checked_add(length).is_some_and(|end| end <= size)
+        };
+        if !inside(self.x, self.width, image.width())
+            || !inside(self.y, self.height, image.height())
+        {
+            let reason = format!(
+                "the {}x{} region {} at {},{} doesn't fit in the {}x{} {}",
+                self.width,
+                self.height,
+                self.name,
+                self.x,
+                self.y,
+                image.width(),
+                image.height(),
+                image.name()
+            );
+            return Err(Diagnostic::new(FailureClass::Usage, reason)
+                .with_suggestion("check the region's position and size against the image"));
+        }
+
+        Ok(image.crop(self.name.clone(), self.x, self.y, self.width, self.height))
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("expected NAME=X,Y,WIDTH,HEIGHT, found '{}'", s);
+        let (name, rectangle) = s.split_once('=').ok_or_else(error)?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(error());
+        }
+        let numbers = rectangle
+            .split(',')
+            .map(|n| n.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid number in '{}': {}", s, e))?;
+
+        match numbers[..] {
+            [_, _, 0, _] | [_, _, _, 0] => Err(format!("the region {} is empty", name)),
+            [x, y, width, height] => Ok(Region {
+                name: name.to_owned(),
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(error()),
+        }
+    }
+}
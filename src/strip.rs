@@ -0,0 +1,30 @@
+//! Splitting images whose data won't fit in a loader's transfer buffer into
+//! strips of whole rows, each of which becomes an asset.
+
+use crate::Label;
+
+/// An image split into strips of the same number of rows from the top, but
+/// for the last, which has the rows left over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Strips {
+    /// The name the image had.
+    pub(crate) name: String,
+    /// The label the image would have had as one asset, which the strip
+    /// table and its constants are named after.
+    pub(crate) label: Label,
+    /// The rows in every strip but the last.
+    pub(crate) rows: u32,
+    pub(crate) count: usize,
+}
+
+impl Strips {
+    /// The name of the strip at `index` from the top of an image.
+    pub(crate) fn name(image: &str, index: usize) -> String {
+        format!("{}_Strip{}", image, index)
+    }
+
+    /// The names of the strips, from the top.
+    pub(crate) fn names(&self) -> impl Iterator<Item = String> + '_ {
+        (0..self.count).map(move |index| Strips::name(&self.name, index))
+    }
+}
@@ -0,0 +1,491 @@
+//! Tile mode, which turns a map made in Tiled and its tileset image into the
+//! graphics of the tiles it uses plus each layer as an array of tile indices.
+
+mod tmx;
+
+use crate::colour_space::ColourManagement;
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::encoding::Encoding;
+use crate::image::{Image, Pixel, Size};
+use anyhow::Result;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::path::{Path, PathBuf};
+
+/// The size of the tiles in a map which doesn't say.
+pub const DEFAULT_TILE: Size = Size::new(8, 8);
+
+/// The index stored for a cell without a tile.
+const EMPTY_CELL: u32 = 0xFFFF_FFFF;
+
+/// Tiled keeps the horizontal, vertical and diagonal flips in the top bits of a tile id.
+const FLIP_BITS: u32 = 0xE000_0000;
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TilemapOptions {
+    /// The map, either a Tiled `.tmx` file or one layer exported as CSV.
+    pub map: PathBuf,
+    /// The tileset image, by default the one named by the map's tileset.
+    pub tileset: Option<PathBuf>,
+    /// The size of each tile, by default the map's own tile size or 8x8.
+    pub tile: Option<Size>,
+    /// Group the map's tiles into metatiles of this many tiles across and down.
+    pub metatile: Option<Size>,
+    /// Store tiles which are flips of another tile as that tile with flip bits set.
+    pub flip_tiles: bool,
+}
+
+/// A map as written in the file, before its tiles are cut out of the tileset.
+struct MapSource {
+    /// The size of the tiles, when the file says.
+    tile: Option<Size>,
+    /// The id of the tileset's first tile, as 0 marks an empty cell.
+    first_gid: u32,
+    /// The tileset image, relative to the map.
+    tileset: Option<PathBuf>,
+    /// Layers whose cells are tile ids, not yet deduplicated.
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Layer {
+    name: String,
+    width: u32,
+    height: u32,
+    cells: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tilemap {
+    name: String,
+    tile: Size,
+    /// Every distinct tile the map uses, in the order they're first used.
+    tiles: Vec<Image>,
+    /// Whether cells use the flip bits.
+    flip_tiles: bool,
+    /// The size of the metatiles in tiles, and every distinct metatile as the
+    /// tile index of each of its cells, a row at a time.
+    metatiles: Option<(Size, Vec<Vec<u32>>)>,
+    /// Layers whose cells are indices into `metatiles` when there are
+    /// metatiles, otherwise into `tiles`.
+    layers: Vec<Layer>,
+    /// The image the tiles were cut from.
+    tileset: PathBuf,
+}
+
+impl Tilemap {
+    /// Read the map and cut the tiles it uses out of the tileset.
+    pub fn load(options: &TilemapOptions, colour_management: ColourManagement) -> Result<Self> {
+        let path = &options.map;
+        let source = std::fs::read_to_string(path).map_err(|e| {
+            Diagnostic::new(FailureClass::Input, format!("failed to open file: {}", e))
+                .with_file(path)
+                .with_suggestion("check that the path exists and is readable")
+        })?;
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("map")
+            .to_owned();
+
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        let map = match extension.as_deref() {
+            Some("tmx") => tmx::parse(&source),
+            _ => parse_csv(&name, &source),
+        }
+        .map_err(|d| d.with_file(path))?;
+
+        let tile = match (options.tile, map.tile) {
+            (Some(tile), Some(map_tile)) if tile != map_tile => {
+                return Err(Diagnostic::new(
+                    FailureClass::Usage,
+                    format!("the map uses {} tiles, not {}", map_tile, tile),
+                )
+                .with_file(path)
+                .with_suggestion("leave out --tiles to use the map's tile size")
+                .into())
+            }
+            (tile, map_tile) => tile.or(map_tile).unwrap_or(DEFAULT_TILE),
+        };
+
+        let tileset_path = match (&options.tileset, &map.tileset) {
+            (Some(tileset), _) => tileset.clone(),
+            (None, Some(image)) => path.parent().unwrap_or_else(|| Path::new("")).join(image),
+            (None, None) => {
+                return Err(Diagnostic::new(
+                    FailureClass::Usage,
+                    "the map doesn't say which image holds its tiles",
+                )
+                .with_file(path)
+                .with_suggestion("pass the tileset image with --tileset")
+                .into())
+            }
+        };
+        let tileset = colour_management
+            .apply(Image::load(&tileset_path)?)
+            .map_err(|d| d.with_file(&tileset_path))?;
+
+        let mut tilemap = Tilemap::from_tileset(name, &tileset, tile, map, options.flip_tiles)
+            .map_err(|d| d.with_file(path))?;
+        tilemap.tileset = tileset_path;
+        if let Some(metatile) = options.metatile {
+            tilemap.group(metatile).map_err(|d| d.with_file(path))?;
+        }
+        info!(
+            "loaded {} layers using {} distinct tiles from {}",
+            tilemap.layers.len(),
+            tilemap.tiles.len(),
+            path.display()
+        );
+
+        Ok(tilemap)
+    }
+
+    /// Cut the tiles the map uses out of the tileset, keeping one copy of any
+    /// tiles which are drawn the same. With `flip_tiles`, a tile which is a
+    /// flip of one already kept is stored as that tile with flip bits set.
+    fn from_tileset(
+        name: String,
+        tileset: &Image,
+        tile: Size,
+        map: MapSource,
+        flip_tiles: bool,
+    ) -> Result<Self, Diagnostic> {
+        if !tileset.width().is_multiple_of(tile.width)
+            || !tileset.height().is_multiple_of(tile.height)
+        {
+            return Err(Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "the {}x{} tileset isn't a whole number of {} tiles",
+                    tileset.width(),
+                    tileset.height(),
+                    tile
+                ),
+            )
+            .with_suggestion("check the tileset has no margin or spacing between tiles"));
+        }
+
+        let columns = tileset.width() / tile.width;
+        let count = columns * (tileset.height() / tile.height);
+
+        let mut tiles = Vec::new();
+        let mut distinct: HashMap<Vec<Pixel>, u32> = HashMap::new();
+        let mut indices: HashMap<u32, u32> = HashMap::new();
+        let mut skewed = false;
+        let mut layers = Vec::new();
+
+        for layer in map.layers {
+            let mut cells = Vec::with_capacity(layer.cells.len());
+            for &gid in layer.cells.iter() {
+                if gid == 0 {
+                    cells.push(EMPTY_CELL);
+                    continue;
+                }
+
+                let id = (gid & !FLIP_BITS)
+                    .checked_sub(map.first_gid)
+                    .filter(|id| *id < count)
+                    .ok_or_else(|| {
+                        Diagnostic::new(
+                            FailureClass::Decode,
+                            format!(
+                                "layer {} uses tile {} but the tileset only has {}",
+                                layer.name,
+                                gid & !FLIP_BITS,
+                                count
+                            ),
+                        )
+                        .with_suggestion("check the tileset matches the one the map was made with")
+                    })?;
+
+                // cells are cached by tile and how Tiled flipped it
+                let key = id | (gid & FLIP_BITS);
+                let cell = match indices.get(&key) {
+                    Some(cell) => *cell,
+                    None => {
+                        let x = (id % columns) * tile.width;
+                        let y = (id / columns) * tile.height;
+                        let mut image = tileset.crop(
+                            format!("{}[{}]", name, id),
+                            x,
+                            y,
+                            tile.width,
+                            tile.height,
+                        );
+
+                        // Tiled swaps the axes before flipping, which only keeps the size of square tiles
+                        if gid & FLIPPED_DIAGONALLY != 0 {
+                            match tile.width == tile.height {
+                                true => image = image.transpose(),
+                                false => skewed = true,
+                            }
+                        }
+                        let image = image.flip(
+                            gid & FLIPPED_HORIZONTALLY != 0,
+                            gid & FLIPPED_VERTICALLY != 0,
+                        );
+
+                        // drawing a stored tile flipped gives this one when flipping this one gives the stored tile
+                        let flips: &[(bool, bool)] = match flip_tiles {
+                            true => &[(false, false), (true, false), (false, true), (true, true)],
+                            false => &[(false, false)],
+                        };
+                        let existing = flips.iter().find_map(|&(horizontal, vertical)| {
+                            let pixels: Vec<Pixel> =
+                                image.flip(horizontal, vertical).iter().copied().collect();
+                            let flags = match (horizontal, vertical) {
+                                (true, true) => FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY,
+                                (true, false) => FLIPPED_HORIZONTALLY,
+                                (false, true) => FLIPPED_VERTICALLY,
+                                (false, false) => 0,
+                            };
+                            distinct.get(&pixels).map(|index| index | flags)
+                        });
+
+                        let cell = match existing {
+                            Some(cell) => cell,
+                            None => {
+                                distinct
+                                    .insert(image.iter().copied().collect(), tiles.len() as u32);
+                                tiles.push(image);
+                                tiles.len() as u32 - 1
+                            }
+                        };
+                        indices.insert(key, cell);
+                        cell
+                    }
+                };
+                cells.push(cell);
+            }
+
+            layers.push(Layer { cells, ..layer });
+        }
+
+        if skewed {
+            warn!(
+                "{} has diagonally flipped tiles, which can't be drawn as the tiles aren't square",
+                name
+            );
+        }
+
+        Ok(Tilemap {
+            name,
+            tile,
+            tiles,
+            flip_tiles,
+            metatiles: None,
+            layers,
+            tileset: PathBuf::new(),
+        })
+    }
+
+    /// Group each layer's cells into metatiles of `size` tiles, storing each
+    /// distinct metatile once and turning the layers into metatile indices.
+    fn group(&mut self, size: Size) -> Result<(), Diagnostic> {
+        let mut metatiles: Vec<Vec<u32>> = Vec::new();
+        let mut distinct: HashMap<Vec<u32>, u32> = HashMap::new();
+
+        for layer in self.layers.iter_mut() {
+            if !layer.width.is_multiple_of(size.width) || !layer.height.is_multiple_of(size.height)
+            {
+                return Err(Diagnostic::new(
+                    FailureClass::Usage,
+                    format!(
+                        "layer {} is {}x{} tiles, which isn't a whole number of {} metatiles",
+                        layer.name, layer.width, layer.height, size
+                    ),
+                )
+                .with_suggestion("resize the map or pick a metatile size which divides it"));
+            }
+
+            let (width, height) = (layer.width / size.width, layer.height / size.height);
+            let mut cells = Vec::with_capacity(width as usize * height as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    let metatile: Vec<u32> = (0..size.height)
+                        .flat_map(|row| {
+                            let start =
+                                ((y * size.height + row) * layer.width + x * size.width) as usize;
+                            layer.cells[start..start + size.width as usize]
+                                .iter()
+                                .copied()
+                        })
+                        .collect();
+                    let index = *distinct.entry(metatile.clone()).or_insert_with(|| {
+                        metatiles.push(metatile);
+                        metatiles.len() as u32 - 1
+                    });
+                    cells.push(index);
+                }
+            }
+
+            layer.width = width;
+            layer.height = height;
+            layer.cells = cells;
+        }
+
+        info!(
+            "grouped {} tiles into {} distinct {} metatiles",
+            self.tiles.len(),
+            metatiles.len(),
+            size
+        );
+        self.metatiles = Some((size, metatiles));
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn tiles(&self) -> &[Image] {
+        &self.tiles
+    }
+
+    /// The tileset image, whether it was passed in or named by the map.
+    #[inline]
+    pub fn tileset(&self) -> &Path {
+        &self.tileset
+    }
+
+    /// Write the tile constants and the tile graphics, one tile after another.
+    pub(crate) fn to_asm(&self, encoding: &Encoding) -> Result<String> {
+        let mut buf = String::new();
+        let tile_size = encoding.size(self.tile.width, self.tile.height);
+
+        writeln!(buf, "TILE_WIDTH\tEQU {}", self.tile.width)?;
+        writeln!(buf, "TILE_HEIGHT\tEQU {}", self.tile.height)?;
+        writeln!(buf, "TILE_SIZE\tEQU {}", tile_size)?;
+        writeln!(buf, "TILE_COUNT\tEQU {}", self.tiles.len())?;
+        if self.flip_tiles {
+            writeln!(buf, "TILE_FLIP_X\tEQU 0x{:08X}", FLIPPED_HORIZONTALLY)?;
+            writeln!(buf, "TILE_FLIP_Y\tEQU 0x{:08X}", FLIPPED_VERTICALLY)?;
+            writeln!(
+                buf,
+                "TILE_INDEX_MASK\tEQU 0x{:08X}",
+                !(FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY)
+            )?;
+        }
+        buf.push('\n');
+
+        writeln!(buf, "Tiles")?;
+        for (index, tile) in self.tiles.iter().enumerate() {
+            writeln!(buf, "; tile {}", index)?;
+            encoding.write_bytes(&mut buf, &encoding.encode(tile)?)?;
+        }
+
+        Ok(buf)
+    }
+
+    /// Write the metatiles, then each layer as a word per cell a row at a
+    /// time, all of which must be aligned. Cells without a tile are -1.
+    pub(crate) fn layers_asm(&self, encoding: &Encoding) -> Result<String> {
+        let mut buf = String::new();
+
+        if let Some((size, metatiles)) = &self.metatiles {
+            writeln!(buf, "METATILE_WIDTH\tEQU {}", size.width)?;
+            writeln!(buf, "METATILE_HEIGHT\tEQU {}", size.height)?;
+            writeln!(buf, "METATILE_COUNT\tEQU {}", metatiles.len())?;
+            writeln!(buf, "Metatiles")?;
+            for (index, metatile) in metatiles.iter().enumerate() {
+                let words = encoding.words(metatile);
+                writeln!(buf, "\t{}\t; metatile {}", words, index)?;
+            }
+            buf.push('\n');
+        }
+
+        for layer in self.layers.iter() {
+            let symbol = symbol(&layer.name);
+            writeln!(
+                buf,
+                "MAP_{}_WIDTH\tEQU {}",
+                symbol.to_uppercase(),
+                layer.width
+            )?;
+            writeln!(
+                buf,
+                "MAP_{}_HEIGHT\tEQU {}",
+                symbol.to_uppercase(),
+                layer.height
+            )?;
+            writeln!(buf, "Map_{}", symbol)?;
+            for row in layer.cells.chunks(layer.width.max(1) as usize) {
+                writeln!(buf, "\t{}", encoding.words(row))?;
+            }
+            buf.push('\n');
+        }
+
+        Ok(buf)
+    }
+}
+
+/// Turn a layer name into something which can be used in a label.
+fn symbol(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Read a layer exported with Tiled's CSV export, which holds a tile id per
+/// cell counting from 0, where -1 is an empty cell.
+fn parse_csv(name: &str, source: &str) -> Result<MapSource, Diagnostic> {
+    let mut rows = Vec::new();
+    for (line, text) in source.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let row = text
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(|field| match field.parse::<i64>() {
+                Ok(-1) => Ok(0),
+                Ok(id) if (0..i64::from(!FLIP_BITS)).contains(&id) => Ok(id as u32 + 1),
+                _ => Err(Diagnostic::new(
+                    FailureClass::Decode,
+                    format!(
+                        "invalid CSV map on line {}: '{}' isn't a tile id",
+                        line + 1,
+                        field
+                    ),
+                )
+                .with_suggestion("export the map with File > Export As > CSV")),
+            })
+            .collect::<Result<Vec<u32>, _>>()?;
+        rows.push(row);
+    }
+
+    let width = rows.first().map_or(0, Vec::len);
+    if width == 0 {
+        return Err(Diagnostic::new(
+            FailureClass::Decode,
+            "the CSV map is empty",
+        ));
+    }
+    if rows.iter().any(|row| row.len() != width) {
+        return Err(Diagnostic::new(
+            FailureClass::Decode,
+            "the CSV map's rows aren't all the same length",
+        )
+        .with_suggestion("export the map with File > Export As > CSV"));
+    }
+
+    Ok(MapSource {
+        tile: None,
+        first_gid: 1,
+        tileset: None,
+        layers: vec![Layer {
+            name: name.to_owned(),
+            width: width as u32,
+            height: rows.len() as u32,
+            cells: rows.concat(),
+        }],
+    })
+}
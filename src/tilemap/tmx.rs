@@ -0,0 +1,162 @@
+//! Tiled's own XML map format.
+
+use super::{Layer, MapSource};
+use crate::diagnostic::{Diagnostic, FailureClass};
+use crate::image::Size;
+use base64::Engine;
+use roxmltree::{Document, Node};
+use std::io::Read;
+use std::path::PathBuf;
+
+fn invalid(reason: impl Into<String>) -> Diagnostic {
+    Diagnostic::new(
+        FailureClass::Decode,
+        format!("invalid TMX map: {}", reason.into()),
+    )
+    .with_suggestion("check the map was saved by Tiled")
+}
+
+fn number(node: Node, name: &str) -> Result<u32, Diagnostic> {
+    node.attribute(name)
+        .ok_or_else(|| invalid(format!("<{}> has no {}", node.tag_name().name(), name)))?
+        .parse()
+        .map_err(|_| invalid(format!("<{}> has a bad {}", node.tag_name().name(), name)))
+}
+
+/// Read every tile layer of an orthogonal map with a single tileset.
+pub(super) fn parse(source: &str) -> Result<MapSource, Diagnostic> {
+    let doc = Document::parse(source).map_err(|e| invalid(e.to_string()))?;
+    let map = doc.root_element();
+    if !map.has_tag_name("map") {
+        return Err(invalid("the root element isn't <map>"));
+    }
+
+    if map.attribute("infinite") == Some("1") {
+        return Err(
+            Diagnostic::new(FailureClass::Usage, "infinite maps aren't supported")
+                .with_suggestion("untick Infinite in the map's properties"),
+        );
+    }
+    if let Some(orientation) = map.attribute("orientation").filter(|o| *o != "orthogonal") {
+        return Err(Diagnostic::new(
+            FailureClass::Usage,
+            format!("{} maps aren't supported, only orthogonal", orientation),
+        ));
+    }
+
+    let tile = Some(Size::new(
+        number(map, "tilewidth")?,
+        number(map, "tileheight")?,
+    ));
+
+    let tilesets: Vec<Node> = map
+        .children()
+        .filter(|node| node.has_tag_name("tileset"))
+        .collect();
+    let tileset = match tilesets.as_slice() {
+        [tileset] => *tileset,
+        _ => {
+            return Err(Diagnostic::new(
+                FailureClass::Usage,
+                format!(
+                    "the map uses {} tilesets but exactly one is supported",
+                    tilesets.len()
+                ),
+            )
+            .with_suggestion("merge the tiles into a single tileset image"))
+        }
+    };
+    let first_gid = number(tileset, "firstgid")?;
+    // external .tsx tilesets aren't read, so their image must be passed by hand
+    let image = tileset
+        .children()
+        .find(|node| node.has_tag_name("image"))
+        .and_then(|image| image.attribute("source"))
+        .map(PathBuf::from);
+
+    let layers = map
+        .descendants()
+        .filter(|node| node.has_tag_name("layer"))
+        .enumerate()
+        .map(|(index, layer)| parse_layer(index, layer))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(MapSource {
+        tile,
+        first_gid,
+        tileset: image,
+        layers,
+    })
+}
+
+fn parse_layer(index: usize, layer: Node) -> Result<Layer, Diagnostic> {
+    let name = layer
+        .attribute("name")
+        .map_or_else(|| format!("layer{}", index), str::to_owned);
+    let (width, height) = (number(layer, "width")?, number(layer, "height")?);
+
+    let data = layer
+        .children()
+        .find(|node| node.has_tag_name("data"))
+        .ok_or_else(|| invalid(format!("layer {} has no <data>", name)))?;
+    let text = data.text().unwrap_or_default().trim();
+
+    let cells = match (data.attribute("encoding"), data.attribute("compression")) {
+        (None, _) => data
+            .children()
+            .filter(|node| node.has_tag_name("tile"))
+            .map(|tile| match tile.attribute("gid") {
+                Some(_) => number(tile, "gid"),
+                None => Ok(0),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        (Some("csv"), _) => text
+            .split(',')
+            .map(|gid| gid.trim().parse())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid(format!("layer {} has a bad tile id", name)))?,
+        (Some("base64"), compression) => {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(text)
+                .map_err(|e| invalid(format!("layer {} isn't valid base64: {}", name, e)))?;
+            let mut raw = Vec::new();
+            let inflated = match compression {
+                None => {
+                    raw = bytes;
+                    Ok(0)
+                }
+                Some("zlib") => flate2::read::ZlibDecoder::new(&bytes[..]).read_to_end(&mut raw),
+                Some("gzip") => flate2::read::GzDecoder::new(&bytes[..]).read_to_end(&mut raw),
+                Some(other) => {
+                    return Err(Diagnostic::new(
+                        FailureClass::Usage,
+                        format!("{} compressed layers aren't supported", other),
+                    )
+                    .with_suggestion("set the map's tile layer format to CSV"))
+                }
+            };
+            inflated.map_err(|e| invalid(format!("layer {} won't decompress: {}", name, e)))?;
+            raw.chunks_exact(4)
+                .map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+                .collect()
+        }
+        (Some(other), _) => return Err(invalid(format!("unknown layer encoding {}", other))),
+    };
+
+    if cells.len() != width as usize * height as usize {
+        return Err(invalid(format!(
+            "layer {} has {} tiles but is {}x{}",
+            name,
+            cells.len(),
+            width,
+            height
+        )));
+    }
+
+    Ok(Layer {
+        name,
+        width,
+        height,
+        cells,
+    })
+}
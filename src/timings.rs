@@ -0,0 +1,100 @@
+//! How long each part of a conversion takes, for `--timings`.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The time spent in each phase of a conversion, and on each asset within
+/// the phases which work an asset at a time.
+///
+/// A disabled collector never reads the clock, which isn't available on
+/// wasm32.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    enabled: bool,
+    phases: Vec<(String, Duration)>,
+    /// The phase, the asset or input, and how long it took.
+    assets: Vec<(String, String, Duration)>,
+}
+
+impl Timings {
+    /// A collector which records everything it is given.
+    pub fn new() -> Self {
+        Timings {
+            enabled: true,
+            ..Timings::default()
+        }
+    }
+
+    /// The time now, or nothing if timings aren't being collected.
+    pub(crate) fn start(&self) -> Option<Instant> {
+        self.enabled.then(Instant::now)
+    }
+
+    /// Record a phase which started at `start`.
+    pub(crate) fn phase(&mut self, phase: &str, start: Option<Instant>) {
+        if let Some(start) = start.filter(|_| self.enabled) {
+            self.record(phase, start.elapsed());
+        }
+    }
+
+    /// Record the time spent on one asset during a phase.
+    pub(crate) fn asset(&mut self, phase: &str, asset: &str, start: Option<Instant>) {
+        self.asset_took(phase, asset, start.map(|start| start.elapsed()));
+    }
+
+    /// Record the time one asset took, timed somewhere else such as on
+    /// another thread.
+    pub(crate) fn asset_took(&mut self, phase: &str, asset: &str, took: Option<Duration>) {
+        if let Some(took) = took.filter(|_| self.enabled) {
+            self.assets.push((phase.to_owned(), asset.to_owned(), took));
+        }
+    }
+
+    /// Record a phase timed by the caller, such as writing the files.
+    pub fn record(&mut self, phase: &str, duration: Duration) {
+        if self.enabled {
+            self.phases.push((phase.to_owned(), duration));
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, duration)| *duration).sum()
+    }
+}
+
+/// The number of the slowest assets listed in the report.
+const SLOWEST: usize = 10;
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        let share = |duration: Duration| match total.is_zero() {
+            true => 0.0,
+            false => 100.0 * duration.as_secs_f64() / total.as_secs_f64(),
+        };
+
+        writeln!(f, "{:<12} {:>9} {:>7}", "phase", "time", "share")?;
+        for (phase, duration) in self.phases.iter() {
+            writeln!(
+                f,
+                "{:<12} {:>9.3?} {:>6.1}%",
+                phase,
+                duration,
+                share(*duration)
+            )?;
+        }
+        writeln!(f, "{:<12} {:>9.3?}", "total", total)?;
+
+        // the slowest assets are where a big conversion's time goes
+        let mut assets: Vec<&(String, String, Duration)> = self.assets.iter().collect();
+        assets.sort_by_key(|(_, _, duration)| std::cmp::Reverse(*duration));
+        if !assets.is_empty() {
+            writeln!(f, "\nslowest assets")?;
+        }
+        for (phase, asset, duration) in assets.into_iter().take(SLOWEST) {
+            writeln!(f, "{:<12} {:>9.3?} {}", phase, duration, asset)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! A JS friendly wrapper around the conversion, for running it in a browser
+//! where there is no filesystem and no Rust toolchain.
+//!
+//! Build with `wasm-pack build --target web -- --features wasm`.
+
+use crate::extract::{decode, Layout};
+use crate::output::Packed;
+use crate::{Armasm, Image, OutputBackend, Timings};
+use js_sys::Uint8Array;
+use wasm_bindgen::prelude::*;
+
+/// The result of converting a set of images in the browser.
+#[wasm_bindgen]
+pub struct Conversion {
+    asm: String,
+    preview_width: u32,
+    preview_height: u32,
+    preview: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Conversion {
+    /// The generated assembly file.
+    #[wasm_bindgen(getter)]
+    pub fn asm(&self) -> String {
+        self.asm.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = previewWidth)]
+    pub fn preview_width(&self) -> u32 {
+        self.preview_width
+    }
+
+    #[wasm_bindgen(getter, js_name = previewHeight)]
+    pub fn preview_height(&self) -> u32 {
+        self.preview_height
+    }
+
+    /// Every asset side by side as RGBA bytes, ready for `new ImageData(...)`.
+    #[wasm_bindgen(getter)]
+    pub fn preview(&self) -> Vec<u8> {
+        self.preview.clone()
+    }
+}
+
+/// Convert PNG files, given as their names and contents, into assembly along
+/// with a preview of the assets as the assembly holds them.
+#[wasm_bindgen]
+pub fn convert(names: Vec<String>, files: Vec<Uint8Array>) -> Result<Conversion, JsError> {
+    if names.len() != files.len() {
+        return Err(JsError::new("every file needs exactly one name"));
+    }
+
+    let images = names
+        .into_iter()
+        .zip(files)
+        .map(|(name, file)| {
+            let name = name
+                .rsplit_once('.')
+                .map_or(name.as_str(), |(stem, _)| stem);
+            Image::decode(name.to_owned(), file.to_vec().as_slice())
+                .map_err(|e| JsError::new(&format!("{}: {}", name, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let error = |e: anyhow::Error| JsError::new(&format!("{:#}", e));
+    let options = crate::Options::default();
+    let packed =
+        crate::pack(images, Vec::new(), &options, &mut Timings::default()).map_err(error)?;
+    let asm = Armasm.emit(&packed).map_err(error)?.remove(0).contents;
+    let asm = String::from_utf8(asm).map_err(|e| error(e.into()))?;
+    let (preview_width, preview_height, preview) = preview(&packed);
+
+    Ok(Conversion {
+        asm,
+        preview_width,
+        preview_height,
+        preview,
+    })
+}
+
+/// Lay the assets in the table out left to right, decoding each from the
+/// data written for it the way `extract` reads it back.
+fn preview(packed: &Packed) -> (u32, u32, Vec<u8>) {
+    let encoding = packed.encoding();
+    let assets: Vec<_> = packed
+        .table()
+        .filter_map(|(_, asset)| {
+            let layout = Layout {
+                palette: packed.palette(),
+                palette_offset: encoding.index_offset,
+                bits_per_colour: encoding.bits_per_colour,
+                pixels_per_byte: encoding.pixels_per_byte,
+                stride: packed.stride(asset),
+                endian: encoding.endian,
+                row_order: encoding.row_order,
+            };
+            let (width, height) = (asset.width? as usize, asset.height? as usize);
+            let pixels = decode(&asset.data, width, height, &layout).ok()?;
+            Some((width, height, pixels))
+        })
+        .collect();
+    let width: usize = assets.iter().map(|(width, _, _)| width).sum();
+    let height = assets
+        .iter()
+        .map(|(_, height, _)| *height)
+        .max()
+        .unwrap_or(0);
+
+    let mut rgba = vec![0; width * height * 4];
+    let mut left = 0;
+    for (asset_width, _, pixels) in assets.iter() {
+        for (i, colour) in pixels.iter().enumerate() {
+            let x = left + i % asset_width;
+            let y = i / asset_width;
+            let offset = (y * width + x) * 4;
+            rgba[offset..offset + 4].copy_from_slice(&[colour.r, colour.g, colour.b, colour.a]);
+        }
+        left += asset_width;
+    }
+
+    (width as u32, height as u32, rgba)
+}